@@ -0,0 +1,58 @@
+use crate::fixtures::{Fixtures, MockVersion};
+use actix_web::{web, HttpResponse, Responder};
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+/// Body of `POST /__admin/versions`
+#[derive(Deserialize)]
+pub struct NewVersion {
+    version: String,
+    #[serde(default)]
+    preview: bool,
+    /// Only this location, the default list otherwise
+    location: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LocationQuery {
+    location: Option<String>,
+}
+
+/// Add a version while the mock runs. Changes last until VERSIONS_FILE is reloaded.
+pub async fn add_version(fixtures: web::Data<ArcSwap<Fixtures>>, body: web::Json<NewVersion>) -> impl Responder {
+    let new = body.into_inner();
+    let mut updated = Fixtures::clone(&fixtures.load());
+
+    let versions = updated.versions_mut(new.location.as_deref());
+    match versions.iter_mut().find(|v| v.version == new.version) {
+        Some(existing) => existing.preview = new.preview,
+        None => versions.push(MockVersion { version: new.version.clone(), preview: new.preview }),
+    }
+    fixtures.store(updated.into());
+
+    info!("Admin: added {} ({})", new.version, new.location.as_deref().unwrap_or("default list"));
+    HttpResponse::Created().json(json!({ "version": new.version, "preview": new.preview, "location": new.location }))
+}
+
+/// Remove a version, from `?location=` only when given
+pub async fn remove_version(
+    fixtures: web::Data<ArcSwap<Fixtures>>,
+    path: web::Path<String>,
+    query: web::Query<LocationQuery>,
+) -> impl Responder {
+    let version = path.into_inner();
+    let mut updated = Fixtures::clone(&fixtures.load());
+
+    let versions = updated.versions_mut(query.location.as_deref());
+    let before = versions.len();
+    versions.retain(|v| v.version != version);
+    if versions.len() == before {
+        return HttpResponse::NotFound().json(json!({ "error": format!("Version {} is not served", version) }));
+    }
+    fixtures.store(updated.into());
+
+    info!("Admin: removed {} ({})", version, query.location.as_deref().unwrap_or("default list"));
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,185 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use arc_swap::ArcSwap;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+use crate::auth::AuthConfig;
+use crate::fixtures::{Fixtures, MockVersion};
+use crate::latency::{Latency, ENDPOINT_ARM};
+
+/// ARM error body: `{"error": {"code": ..., "message": ...}}`
+pub fn error_body(code: &str, message: &str) -> Value {
+    json!({ "error": { "code": code, "message": message } })
+}
+
+// Error body ARM answers for a location the provider isn't available in
+pub fn no_registered_provider(location: &str, locations: &[&str]) -> Value {
+    error_body(
+        "NoRegisteredProviderFound",
+        &format!(
+            "No registered resource provider found for location '{}' and API version '2019-08-01' for type 'locations/orchestrators'. The supported api-versions are '2017-09-30, 2019-04-01, 2019-06-01, 2019-08-01'. The supported locations are '{}'.",
+            location,
+            locations.join(", ")
+        ),
+    )
+}
+
+// "2025-10-01" or "2025-10-02-preview"
+fn valid_api_version(value: &str) -> bool {
+    let date = value.strip_suffix("-preview").unwrap_or(value);
+    let parts: Vec<&str> = date.split('-').collect();
+    parts.len() == 3
+        && [4, 2, 2].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The ARM error body for a missing or malformed `?api-version=`
+fn check_api_version(api_version: Option<&str>) -> Result<(), Value> {
+    match api_version {
+        None => Err(error_body(
+            "MissingApiVersionParameter",
+            "The api-version query parameter (?api-version=) is required for all requests.",
+        )),
+        Some(v) if !valid_api_version(v) => Err(error_body(
+            "InvalidApiVersionParameter",
+            &format!("The api-version '{}' is invalid. The supported versions are '2025-10-01'.", v),
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+// Numeric "1.28.10" parts, so 1.28.10 sorts after 1.28.9
+fn version_key(version: &str) -> Vec<u64> {
+    version.split('.').map(|p| p.parse().unwrap_or(0)).collect()
+}
+
+/// The `kubernetesVersions` body: one entry per minor version with its patches, each
+/// listing the later patches it can upgrade to.
+fn kubernetes_versions(versions: &[MockVersion]) -> Value {
+    let mut sorted: Vec<&MockVersion> = versions.iter().collect();
+    sorted.sort_by_key(|v| version_key(&v.version));
+
+    let mut minors: BTreeMap<Vec<u64>, Vec<&MockVersion>> = BTreeMap::new();
+    for v in &sorted {
+        minors.entry(version_key(&v.version).into_iter().take(2).collect()).or_default().push(v);
+    }
+
+    let values: Vec<Value> = minors
+        .into_iter()
+        .map(|(minor, patches)| {
+            let mut patch_versions = Map::new();
+            for patch in &patches {
+                let upgrades: Vec<&str> = sorted
+                    .iter()
+                    .filter(|v| version_key(&v.version) > version_key(&patch.version))
+                    .map(|v| v.version.as_str())
+                    .collect();
+                let mut detail = json!({ "upgrades": upgrades });
+                // ARM flags previews per minor; az reads the flag per patch too
+                if patch.preview {
+                    detail["isPreview"] = json!(true);
+                }
+                patch_versions.insert(patch.version.clone(), detail);
+            }
+
+            let mut item = json!({
+                "version": minor.iter().map(u64::to_string).collect::<Vec<_>>().join("."),
+                "capabilities": { "supportPlan": ["KubernetesOfficial"] },
+                "patchVersions": patch_versions,
+            });
+            if patches.iter().all(|p| p.preview) {
+                item["isPreview"] = json!(true);
+            }
+            item
+        })
+        .collect();
+
+    json!({ "values": values })
+}
+
+/// The Azure Resource Manager endpoint az queries, for `MOCK_MODE=arm`
+#[get("/subscriptions/{subscription}/providers/Microsoft.ContainerService/locations/{location}/kubernetesVersions")]
+pub async fn get_kubernetes_versions(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<HashMap<String, String>>,
+    fixtures: web::Data<ArcSwap<Fixtures>>,
+    auth: web::Data<AuthConfig>,
+    latency: web::Data<Latency>,
+) -> impl Responder {
+    let (subscription, location) = path.into_inner();
+    info!("ARM request for subscription {} location {}", subscription, location);
+    latency.delay(ENDPOINT_ARM).await;
+
+    if let Err(resp) = auth.check(&req) {
+        return resp;
+    }
+
+    if let Err(body) = check_api_version(query.get("api-version").map(String::as_str)) {
+        return HttpResponse::BadRequest().json(body);
+    }
+
+    // ARM lists previews too, filtering is up to the client
+    let fixtures = fixtures.load();
+    match fixtures.versions_for(&location) {
+        Some(versions) => HttpResponse::Ok().json(kubernetes_versions(versions)),
+        None => HttpResponse::BadRequest().json(no_registered_provider(&location, &fixtures.location_names())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str, preview: bool) -> MockVersion {
+        MockVersion { version: version.to_string(), preview }
+    }
+
+    #[test]
+    fn groups_patches_by_minor_in_numeric_order() {
+        let body = kubernetes_versions(&[version("1.28.10", false), version("1.27.7", false), version("1.28.9", false)]);
+
+        let values = body["values"].as_array().unwrap();
+        let minors: Vec<&str> = values.iter().map(|v| v["version"].as_str().unwrap()).collect();
+        assert_eq!(minors, ["1.27", "1.28"]);
+        assert_eq!(values[1]["patchVersions"].as_object().unwrap().len(), 2);
+        assert_eq!(values[1]["capabilities"], json!({ "supportPlan": ["KubernetesOfficial"] }));
+    }
+
+    #[test]
+    fn lists_later_patches_as_upgrades() {
+        let body = kubernetes_versions(&[version("1.28.10", false), version("1.27.7", false), version("1.28.9", false)]);
+
+        let values = body["values"].as_array().unwrap();
+        assert_eq!(values[0]["patchVersions"]["1.27.7"]["upgrades"], json!(["1.28.9", "1.28.10"]));
+        assert_eq!(values[1]["patchVersions"]["1.28.9"]["upgrades"], json!(["1.28.10"]));
+        assert_eq!(values[1]["patchVersions"]["1.28.10"]["upgrades"], json!([]));
+    }
+
+    #[test]
+    fn flags_previews_per_patch_and_all_preview_minors() {
+        let body = kubernetes_versions(&[version("1.29.0", false), version("1.29.1", true), version("1.30.0", true)]);
+
+        let values = body["values"].as_array().unwrap();
+        assert!(values[0].get("isPreview").is_none());
+        assert!(values[0]["patchVersions"]["1.29.0"].get("isPreview").is_none());
+        assert_eq!(values[0]["patchVersions"]["1.29.1"]["isPreview"], json!(true));
+        assert_eq!(values[1]["isPreview"], json!(true));
+    }
+
+    #[test]
+    fn requires_api_version() {
+        let body = check_api_version(None).unwrap_err();
+        assert_eq!(body["error"]["code"], "MissingApiVersionParameter");
+    }
+
+    #[test]
+    fn rejects_malformed_api_version() {
+        for bad in ["2025-10", "2025-1-01", "latest", "2025-10-01-beta"] {
+            let body = check_api_version(Some(bad)).unwrap_err();
+            assert_eq!(body["error"]["code"], "InvalidApiVersionParameter", "{}", bad);
+        }
+        assert!(check_api_version(Some("2025-10-01")).is_ok());
+        assert!(check_api_version(Some("2025-10-02-preview")).is_ok());
+    }
+}
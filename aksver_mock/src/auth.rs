@@ -0,0 +1,135 @@
+use actix_web::{HttpRequest, HttpResponse};
+use std::env;
+use tracing::info;
+
+use crate::arm::error_body;
+
+/// Which bearer tokens the mock rejects, and whether the Renovate endpoint wants one at all
+/// (the ARM endpoint always does).
+pub struct AuthConfig {
+    /// `REQUIRE_AUTH=true`
+    pub required: bool,
+    /// `EXPIRED_TOKENS`, comma separated, "expired" by default
+    expired: Vec<String>,
+    /// `INVALID_TOKENS`, comma separated, "invalid" by default
+    invalid: Vec<String>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let tokens = |name: &str, default: &str| -> Vec<String> {
+            env::var(name)
+                .unwrap_or_else(|_| default.to_string())
+                .split(',')
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect()
+        };
+        Self {
+            required: env::var("REQUIRE_AUTH").is_ok_and(|v| v == "true" || v == "1"),
+            expired: tokens("EXPIRED_TOKENS", "expired"),
+            invalid: tokens("INVALID_TOKENS", "invalid"),
+        }
+    }
+
+    /// The ARM 401 for a missing, expired or invalid token; any other token is accepted
+    pub fn check(&self, req: &HttpRequest) -> Result<(), HttpResponse> {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|t| !t.is_empty());
+
+        let (code, message, error) = match token {
+            None => (
+                "AuthenticationFailed",
+                "Authentication failed. The 'Authorization' header is missing.".to_string(),
+                "invalid_request",
+            ),
+            Some(t) if self.expired.iter().any(|e| e == t) => (
+                "ExpiredAuthenticationToken",
+                "The access token expiry UTC time '1/1/2024 12:00:00 AM' is earlier than current UTC time. The token has expired.".to_string(),
+                "invalid_token",
+            ),
+            Some(t) if self.invalid.iter().any(|i| i == t) => (
+                "InvalidAuthenticationToken",
+                "The access token is invalid.".to_string(),
+                "invalid_token",
+            ),
+            Some(_) => return Ok(()),
+        };
+
+        info!("Rejecting request: {}", code);
+        Err(HttpResponse::Unauthorized()
+            .insert_header((
+                "WWW-Authenticate",
+                format!(
+                    "Bearer authorization_uri=\"https://login.windows.net/\", error=\"{}\", error_description=\"{}\"",
+                    error, message
+                ),
+            ))
+            .json(error_body(code, &message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::StatusCode, test::TestRequest};
+    use serde_json::Value;
+
+    use super::*;
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            required: true,
+            expired: vec!["expired".to_string()],
+            invalid: vec!["invalid".to_string()],
+        }
+    }
+
+    /// The 401's ARM error code and the error of its WWW-Authenticate header
+    async fn rejection(authorization: Option<&str>) -> (String, String) {
+        let mut req = TestRequest::default();
+        if let Some(value) = authorization {
+            req = req.insert_header(("Authorization", value));
+        }
+        let resp = config().check(&req.to_http_request()).unwrap_err();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let challenge = resp.headers().get("WWW-Authenticate").unwrap().to_str().unwrap().to_string();
+        let body: Value = serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap();
+        (body["error"]["code"].as_str().unwrap().to_string(), challenge)
+    }
+
+    #[actix_web::test]
+    async fn rejects_missing_token() {
+        let (code, challenge) = rejection(None).await;
+        assert_eq!(code, "AuthenticationFailed");
+        assert!(challenge.contains("error=\"invalid_request\""));
+
+        let (code, _) = rejection(Some("Bearer ")).await;
+        assert_eq!(code, "AuthenticationFailed");
+    }
+
+    #[actix_web::test]
+    async fn rejects_expired_token() {
+        let (code, challenge) = rejection(Some("Bearer expired")).await;
+        assert_eq!(code, "ExpiredAuthenticationToken");
+        assert!(challenge.contains("error=\"invalid_token\""));
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_token() {
+        let (code, challenge) = rejection(Some("Bearer invalid")).await;
+        assert_eq!(code, "InvalidAuthenticationToken");
+        assert!(challenge.contains("error=\"invalid_token\""));
+    }
+
+    #[actix_web::test]
+    async fn accepts_any_other_token() {
+        let req = TestRequest::default().insert_header(("Authorization", "Bearer eyJ0eXAi")).to_http_request();
+        assert!(config().check(&req).is_ok());
+    }
+}
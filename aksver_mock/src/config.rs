@@ -0,0 +1,19 @@
+use clap::{ArgAction, Parser};
+
+#[derive(Parser, Clone)]
+#[clap(author, version, about, long_about = None)]
+pub struct Config {
+    #[arg(long, env = "HTTP_HOST", default_value = "0.0.0.0")]
+    pub host: String,
+
+    #[arg(long, env = "HTTP_PORT", default_value_t = 8080)]
+    pub port: u16,
+
+    // Serve HTTPS with a certificate generated at startup, for clients expecting a TLS upstream
+    #[arg(long, env = "TLS_ENABLED", default_value = "false", value_parser = clap::value_parser!(bool), action = ArgAction::Set)]
+    pub tls: bool,
+
+    // Names the self-signed certificate is valid for
+    #[arg(long, env = "TLS_HOSTNAMES", value_delimiter = ',', default_value = "localhost,aksver-mock")]
+    pub tls_hostnames: Vec<String>,
+}
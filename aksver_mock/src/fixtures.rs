@@ -0,0 +1,240 @@
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+// Served when no VERSIONS_FILE is mounted
+const BUILTIN_VERSIONS: &[&str] = &["1.26.6", "1.26.10", "1.27.3", "1.27.7", "1.28.3", "1.28.5"];
+
+/// One served version, `"1.29.0"` or `{ version: "1.30.0", preview: true }` in the file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "FixtureVersion")]
+pub struct MockVersion {
+    pub version: String,
+    pub preview: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureVersion {
+    Plain(String),
+    Detailed {
+        version: String,
+        #[serde(default)]
+        preview: bool,
+    },
+}
+
+impl From<FixtureVersion> for MockVersion {
+    fn from(v: FixtureVersion) -> Self {
+        match v {
+            FixtureVersion::Plain(version) => MockVersion { version, preview: false },
+            FixtureVersion::Detailed { version, preview } => MockVersion { version, preview },
+        }
+    }
+}
+
+/// The versions the mock serves, per location.
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    /// Served for locations without their own list
+    pub versions: Option<Vec<MockVersion>>,
+    /// Keyed by lowercase location name
+    pub locations: HashMap<String, Vec<MockVersion>>,
+}
+
+// Accepted file layouts: a bare list, or a document with a default "versions" list and/or
+// a "locations" map of location -> list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FixtureFile {
+    List(Vec<MockVersion>),
+    Document(FixtureDocument),
+}
+
+// Unknown keys are refused, a misspelled "version:" would otherwise serve nothing
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FixtureDocument {
+    versions: Option<Vec<MockVersion>>,
+    #[serde(default)]
+    locations: HashMap<String, Vec<MockVersion>>,
+}
+
+impl Fixtures {
+    pub fn builtin() -> Self {
+        Self {
+            versions: Some(
+                BUILTIN_VERSIONS
+                    .iter()
+                    .map(|v| MockVersion { version: v.to_string(), preview: false })
+                    .collect(),
+            ),
+            locations: HashMap::new(),
+        }
+    }
+
+    /// Versions of `location`, none for a location Azure wouldn't know
+    pub fn versions_for(&self, location: &str) -> Option<&[MockVersion]> {
+        self.locations
+            .get(&location.to_lowercase())
+            .or(self.versions.as_ref())
+            .map(Vec::as_slice)
+    }
+
+    /// Locations with their own list, sorted, for the error of unknown ones
+    pub fn location_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.locations.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// The list `location` (default list for none) can be edited in, a location without
+    /// its own list starting out with a copy of the default one
+    pub fn versions_mut(&mut self, location: Option<&str>) -> &mut Vec<MockVersion> {
+        match location {
+            Some(location) => {
+                let default = self.versions.clone().unwrap_or_default();
+                self.locations.entry(location.to_lowercase()).or_insert(default)
+            }
+            None => self.versions.get_or_insert_with(Vec::new),
+        }
+    }
+
+    pub fn version_count(&self) -> usize {
+        self.versions.iter().chain(self.locations.values()).map(Vec::len).sum()
+    }
+
+    /// Parse a JSON (`.json`) or YAML file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let json = path.extension().is_some_and(|e| e == "json");
+        Self::parse(&content, json).map_err(|e| format!("{} in {}", e, path.display()))
+    }
+
+    /// Parse fixtures in JSON or YAML. Fixtures serving no version at all are refused,
+    /// every request would fail with NoRegisteredProviderFound.
+    fn parse(content: &str, json: bool) -> Result<Self, String> {
+        let file: FixtureFile = if json {
+            serde_json::from_str(content).map_err(|e| format!("Invalid JSON ({})", e))?
+        } else {
+            serde_yaml::from_str(content).map_err(|e| format!("Invalid YAML ({})", e))?
+        };
+
+        let fixtures = match file {
+            FixtureFile::List(versions) => Self { versions: Some(versions), locations: HashMap::new() },
+            FixtureFile::Document(FixtureDocument { versions, locations }) => Self {
+                versions,
+                locations: locations.into_iter().map(|(l, v)| (l.to_lowercase(), v)).collect(),
+            },
+        };
+        if fixtures.versions.as_ref().is_none_or(Vec::is_empty) && fixtures.locations.is_empty() {
+            return Err("No versions and no locations".to_string());
+        }
+        Ok(fixtures)
+    }
+}
+
+/// Reload `path` into `store` whenever its modification time changes. A broken file keeps
+/// the previous fixtures, so a half-written update doesn't take the mock down.
+pub fn watch(path: PathBuf, store: Arc<ArcSwap<Fixtures>>, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut ticker = actix_web::rt::time::interval(interval);
+        loop {
+            ticker.tick().await;
+
+            let current = modified(&path);
+            if current == last_modified {
+                continue;
+            }
+            last_modified = current;
+
+            match Fixtures::load(&path) {
+                Ok(fixtures) => {
+                    info!("Reloaded {} ({} versions)", path.display(), fixtures.version_count());
+                    store.store(Arc::new(fixtures));
+                }
+                Err(e) => warn!("Keeping the previous fixtures: {}", e),
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(versions: &[MockVersion]) -> Vec<&str> {
+        versions.iter().map(|v| v.version.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_bare_list() {
+        let fixtures = Fixtures::parse(r#"["1.29.0", {"version": "1.30.0", "preview": true}]"#, true).unwrap();
+
+        let versions = fixtures.versions_for("westeurope").unwrap();
+        assert_eq!(names(versions), ["1.29.0", "1.30.0"]);
+        assert!(!versions[0].preview);
+        assert!(versions[1].preview);
+    }
+
+    #[test]
+    fn parses_document_with_locations() {
+        let yaml = "versions: [\"1.29.0\"]\nlocations:\n  WestEurope: [\"1.28.5\", \"1.29.2\"]\n";
+        let fixtures = Fixtures::parse(yaml, false).unwrap();
+
+        assert_eq!(names(fixtures.versions_for("westeurope").unwrap()), ["1.28.5", "1.29.2"]);
+        assert_eq!(names(fixtures.versions_for("eastus").unwrap()), ["1.29.0"]);
+        assert_eq!(fixtures.location_names(), ["westeurope"]);
+    }
+
+    #[test]
+    fn document_without_default_list_knows_only_its_locations() {
+        let fixtures = Fixtures::parse(r#"{"locations": {"eastus": ["1.29.0"]}}"#, true).unwrap();
+
+        assert!(fixtures.versions_for("eastus").is_some());
+        assert!(fixtures.versions_for("westeurope").is_none());
+    }
+
+    #[test]
+    fn editing_a_location_copies_the_default_list_first() {
+        let mut fixtures = Fixtures::parse(r#"["1.28.5", "1.29.0"]"#, true).unwrap();
+
+        fixtures.versions_mut(Some("WestEurope")).retain(|v| v.version != "1.28.5");
+        fixtures.versions_mut(Some("westeurope")).push(MockVersion { version: "1.30.0".to_string(), preview: true });
+
+        assert_eq!(names(fixtures.versions_for("westeurope").unwrap()), ["1.29.0", "1.30.0"]);
+        assert_eq!(names(fixtures.versions_for("eastus").unwrap()), ["1.28.5", "1.29.0"]);
+    }
+
+    #[test]
+    fn editing_the_default_list_leaves_locations_alone() {
+        let mut fixtures = Fixtures::parse(r#"{"locations": {"eastus": ["1.29.0"]}}"#, true).unwrap();
+
+        fixtures.versions_mut(None).push(MockVersion { version: "1.30.0".to_string(), preview: false });
+
+        assert_eq!(names(fixtures.versions_for("eastus").unwrap()), ["1.29.0"]);
+        assert_eq!(names(fixtures.versions_for("westeurope").unwrap()), ["1.30.0"]);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Fixtures::parse("version: [\"1.29.0\"]\n", false).is_err());
+        assert!(Fixtures::parse(r#"{"versions": ["1.29.0"], "location": {"eastus": ["1.29.0"]}}"#, true).is_err());
+    }
+
+    #[test]
+    fn rejects_fixtures_without_versions() {
+        assert!(Fixtures::parse("{}", true).is_err());
+        assert!(Fixtures::parse("versions: []\n", false).is_err());
+    }
+}
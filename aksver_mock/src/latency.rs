@@ -0,0 +1,83 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::info;
+
+/// Endpoints with their own delays, also the prefix of their variables
+pub const ENDPOINT_VERSIONS: &str = "VERSIONS";
+pub const ENDPOINT_ARM: &str = "ARM";
+
+/// Delays of one endpoint, in milliseconds.
+struct Profile {
+    base: u64,
+    /// Up to this much is added at random
+    jitter: u64,
+    /// Every nth request stalls (0 never), for STALL_MS instead of the usual delay
+    stall_every: u64,
+    stall: u64,
+    requests: AtomicU64,
+}
+
+impl Profile {
+    /// `<ENDPOINT>_LATENCY_MS`, falling back to `LATENCY_MS`, and so on for `JITTER_MS`,
+    /// `STALL_EVERY` and `STALL_MS`
+    fn from_env(endpoint: &str) -> Self {
+        let var = |name: &str, default: u64| {
+            env::var(format!("{}_{}", endpoint, name))
+                .or_else(|_| env::var(name))
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            base: var("LATENCY_MS", 0),
+            jitter: var("JITTER_MS", 0),
+            stall_every: var("STALL_EVERY", 0),
+            stall: var("STALL_MS", 5000),
+            requests: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Simulated network and ARM slowness. Jitter comes from a generator seeded with
+/// `LATENCY_SEED`, so a test run sees the same delays every time.
+pub struct Latency {
+    versions: Profile,
+    arm: Profile,
+    rng: Mutex<StdRng>,
+}
+
+impl Latency {
+    pub fn from_env() -> Self {
+        let seed = env::var("LATENCY_SEED").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+        Self {
+            versions: Profile::from_env(ENDPOINT_VERSIONS),
+            arm: Profile::from_env(ENDPOINT_ARM),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    /// Wait as long as the endpoint's next response should take
+    pub async fn delay(&self, endpoint: &str) {
+        let profile = if endpoint == ENDPOINT_ARM { &self.arm } else { &self.versions };
+        let n = profile.requests.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let millis = if profile.stall_every > 0 && n % profile.stall_every == 0 {
+            info!("Stalling {} request #{} for {}ms", endpoint, n, profile.stall);
+            profile.stall
+        } else {
+            let jitter = match profile.jitter {
+                0 => 0,
+                j => self.rng.lock().unwrap().random_range(0..=j),
+            };
+            profile.base + jitter
+        };
+
+        if millis > 0 {
+            actix_web::rt::time::sleep(Duration::from_millis(millis)).await;
+        }
+    }
+}
@@ -1,10 +1,39 @@
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{get, middleware::from_fn, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use arc_swap::ArcSwap;
+use clap::Parser;
+use service_kit::logging::{self, LogFormat};
+use service_kit::request_id;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-// 1. Define the Release struct (isStable removed)
+mod admin;
+mod arm;
+mod auth;
+mod config;
+mod fixtures;
+mod latency;
+mod recording;
+mod tls;
+
+use auth::AuthConfig;
+use config::Config;
+use fixtures::Fixtures;
+use latency::{Latency, ENDPOINT_VERSIONS};
+use recording::Recorder;
+use tracing::info;
+
+const DEFAULT_RELOAD_SECONDS: u64 = 2;
+
+// 1. Define the Release struct (isStable only while previews are shown)
 #[derive(Serialize)]
 struct Release {
     version: String,
+    #[serde(rename = "isStable", skip_serializing_if = "Option::is_none")]
+    is_stable: Option<bool>,
     #[serde(rename = "changelogUrl")]
     changelog_url: String,
     #[serde(rename = "sourceUrl")]
@@ -37,27 +66,45 @@ fn generate_changelog_url(version: &str) -> String {
     )
 }
 
+// `?preview=true|false`, SHOW_PREVIEW otherwise
+fn show_preview(query: &HashMap<String, String>) -> bool {
+    let value = query.get("preview").cloned().or_else(|| env::var("SHOW_PREVIEW").ok());
+    matches!(value.as_deref(), Some("true" | "1"))
+}
+
 #[get("/{location}")]
-async fn get_versions(path: web::Path<String>) -> impl Responder {
+async fn get_versions(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    fixtures: web::Data<ArcSwap<Fixtures>>,
+    auth: web::Data<AuthConfig>,
+    latency: web::Data<Latency>,
+) -> impl Responder {
     let location = path.into_inner();
-    println!("Request for location: {}", location);
-
-    // Mock logic: different lists for different locations?
-    // For now, returning the standard list
-    let raw_versions = vec![
-        "1.26.6", 
-        "1.26.10", 
-        "1.27.3", 
-        "1.27.7", 
-        "1.28.3", 
-        "1.28.5"
-    ];
-
-    let releases_vec: Vec<Release> = raw_versions
-        .into_iter()
+    let preview = show_preview(&query);
+    info!("Request for location: {} (preview: {})", location, preview);
+    latency.delay(ENDPOINT_VERSIONS).await;
+
+    if auth.required {
+        if let Err(resp) = auth.check(&req) {
+            return resp;
+        }
+    }
+
+    // Locations missing from the fixtures fail like they do on ARM
+    let fixtures = fixtures.load();
+    let Some(versions) = fixtures.versions_for(&location) else {
+        return HttpResponse::BadRequest().json(arm::no_registered_provider(&location, &fixtures.location_names()));
+    };
+
+    let releases_vec: Vec<Release> = versions
+        .iter()
+        .filter(|v| preview || !v.preview)
         .map(|v| Release {
-            version: v.to_string(),
-            changelog_url: generate_changelog_url(v),
+            version: v.version.clone(),
+            is_stable: preview.then_some(!v.preview),
+            changelog_url: generate_changelog_url(&v.version),
             source_url: "https://github.com/kubernetes/kubernetes".to_string(),
         })
         .collect();
@@ -73,13 +120,76 @@ async fn get_versions(path: web::Path<String>) -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Server starting at http://0.0.0.0:8080");
+    logging::init(LogFormat::Pretty);
+    let config = Config::parse();
+
+    // Fixtures: VERSIONS_FILE (JSON or YAML) when mounted, reloaded on change
+    let fixtures = match env::var("VERSIONS_FILE") {
+        Ok(file) => {
+            let path = PathBuf::from(file);
+            let loaded = Fixtures::load(&path).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            info!("Loaded {} versions from {}", loaded.version_count(), path.display());
+
+            let store = Arc::new(ArcSwap::from_pointee(loaded));
+            let interval = env::var("VERSIONS_RELOAD_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_RELOAD_SECONDS);
+            fixtures::watch(path, store.clone(), Duration::from_secs(interval.max(1)));
+            store
+        }
+        Err(_) => Arc::new(ArcSwap::from_pointee(Fixtures::builtin())),
+    };
+    let fixtures = web::Data::from(fixtures);
+
+    // renovate (default): the az service's own output; arm: the ARM API az itself calls
+    let arm_mode = env::var("MOCK_MODE").is_ok_and(|m| m == "arm");
 
-    HttpServer::new(|| {
-        App::new()
-            .service(get_versions)
+    info!(
+        "Server starting at {}://{}:{} ({} mode)",
+        if config.tls { "https" } else { "http" },
+        config.host,
+        config.port,
+        if arm_mode { "arm" } else { "renovate" }
+    );
+
+    let recorder = web::Data::new(Recorder::default());
+    let auth = web::Data::new(AuthConfig::from_env());
+    let latency = web::Data::new(Latency::from_env());
+
+    let server = HttpServer::new(move || {
+        // Test suites assert on what the client sent through /__requests
+        let app = App::new()
+            .app_data(fixtures.clone())
+            .app_data(recorder.clone())
+            .app_data(auth.clone())
+            .app_data(latency.clone())
+            .wrap(from_fn(recording::record))
+            .wrap(request_id::middleware())
+            // Before the renovate route, whose {location} would capture them
+            .configure(service_kit::configure)
+            .service(
+                web::resource("/__requests")
+                    .route(web::get().to(recording::list_requests))
+                    .route(web::delete().to(recording::clear_requests)),
+            )
+            // Tests change the served versions to exercise client caching
+            .route("/__admin/versions", web::post().to(admin::add_version))
+            .route("/__admin/versions/{version}", web::delete().to(admin::remove_version));
+        if arm_mode {
+            app.service(arm::get_kubernetes_versions)
+        } else {
+            app.service(get_versions)
+        }
     })
-    .bind(("0.0.0.0", 8080))?
-    .run()
-    .await
+    .disable_signals()
+    .shutdown_timeout(service_kit::DRAIN_TIMEOUT_SECONDS);
+
+    let addr = (config.host.as_str(), config.port);
+    let server = if config.tls {
+        server.bind_rustls_0_23(addr, tls::self_signed(config.tls_hostnames)?)?
+    } else {
+        server.bind(addr)?
+    };
+    service_kit::run(server.run()).await
 }
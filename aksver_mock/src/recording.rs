@@ -0,0 +1,76 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse, Responder};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Oldest requests are dropped beyond this, a long test run shouldn't grow the mock forever
+const MAX_RECORDED: usize = 1000;
+
+// Probes and scrapes would drown the client's requests
+const PROBE_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+/// A request as the mock received it.
+#[derive(Serialize, Clone)]
+pub struct RecordedRequest {
+    method: String,
+    path: String,
+    query: String,
+    headers: BTreeMap<String, String>,
+    /// Unix time in milliseconds
+    timestamp: u128,
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    requests: Mutex<VecDeque<RecordedRequest>>,
+}
+
+impl Recorder {
+    fn record(&self, req: &ServiceRequest) {
+        let request = RecordedRequest {
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            query: req.query_string().to_string(),
+            headers: req
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+                .collect(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0),
+        };
+
+        let mut requests = self.requests.lock().unwrap();
+        if requests.len() == MAX_RECORDED {
+            requests.pop_front();
+        }
+        requests.push_back(request);
+    }
+}
+
+/// Middleware recording every request but the inspection and probe ones
+pub async fn record(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if !req.path().starts_with("/__") && !PROBE_PATHS.contains(&req.path()) {
+        if let Some(recorder) = req.app_data::<web::Data<Recorder>>() {
+            recorder.record(&req);
+        }
+    }
+    next.call(req).await
+}
+
+/// The recorded requests, oldest first
+pub async fn list_requests(recorder: web::Data<Recorder>) -> impl Responder {
+    let requests: Vec<RecordedRequest> = recorder.requests.lock().unwrap().iter().cloned().collect();
+    HttpResponse::Ok().json(requests)
+}
+
+pub async fn clear_requests(recorder: web::Data<Recorder>) -> impl Responder {
+    recorder.requests.lock().unwrap().clear();
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,19 @@
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+
+/// A rustls config with a freshly generated self-signed certificate for `hostnames`.
+/// Clients have to skip verification or trust the certificate, it changes every start.
+pub fn self_signed(hostnames: Vec<String>) -> std::io::Result<ServerConfig> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+
+    let certified = rcgen::generate_simple_self_signed(hostnames)
+        .map_err(|e| invalid(format!("Failed to generate certificate: {}", e)))?;
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+
+    ServerConfig::builder_with_provider(rustls::crypto::ring::default_provider().into())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid(e.to_string()))?
+        .with_no_client_auth()
+        .with_single_cert(vec![certified.cert.der().clone()], key)
+        .map_err(|e| invalid(format!("Invalid certificate: {}", e)))
+}
@@ -1,7 +1,8 @@
-use actix_request_identifier::RequestIdentifier;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use anyhow::Result;
 use clap::Parser;
+use service_kit::logging::{self, LogFormat};
+use service_kit::request_id;
 use tracing::info;
 
 mod azure_client;
@@ -19,10 +20,7 @@ use state::AppState;
 #[actix_web::main]
 async fn main() -> Result<()> {
     // 1. Initialize Logging
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .json()
-        .init();
+    logging::init(LogFormat::Json);
 
     let config = Config::parse();
     info!(port = config.port, "Starting AKS service");
@@ -43,19 +41,22 @@ async fn main() -> Result<()> {
     worker::start(app_data.clone());
 
     // 4. Start HTTP Server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(app_data.clone())
-            .wrap(RequestIdentifier::with_uuid())
+            .wrap(request_id::middleware())
             .wrap(Logger::default())
             // Register specific paths FIRST to avoid wildcard capture.
-            // "status" matches the wildcard {location}, so it MUST be defined before aks_versions.
+            // "status" and the probes match the wildcard {location}, so they MUST be defined before aks_versions.
+            .configure(service_kit::configure)
             .service(status)
             .service(aks_versions)
     })
+    .disable_signals()
+    .shutdown_timeout(service_kit::DRAIN_TIMEOUT_SECONDS)
     .bind(("0.0.0.0", config.port))?
-    .run()
-    .await?;
+    .run();
+    service_kit::run(server).await?;
 
     Ok(())
 }
@@ -0,0 +1,82 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use moka::future::Cache;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::KodiError;
+
+const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 120;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Liveness probes and Prometheus scrapes must get through without a key
+const OPEN_PATHS: &[&str] = &["/health", "/healthz", "/readyz", "/metrics"];
+
+/// Who may call the API: the optional `X-Api-Key` and the requests per minute each client
+/// address gets.
+pub struct Access {
+    api_key: Option<String>,
+    limit: u32,
+    // Requests per client in the current window, the entry expiring ends the window
+    windows: Cache<IpAddr, Arc<AtomicU32>>,
+}
+
+impl Access {
+    /// Key from `API_KEY`, limit from `RATE_LIMIT_PER_MINUTE` (0 disables it)
+    pub fn from_env() -> Self {
+        let limit = layered_config::parse_or("RATE_LIMIT_PER_MINUTE", DEFAULT_RATE_LIMIT_PER_MINUTE);
+
+        Self {
+            api_key: layered_config::get("API_KEY"),
+            limit,
+            windows: Cache::builder().time_to_live(RATE_WINDOW).build(),
+        }
+    }
+
+    pub fn requires_key(&self) -> bool {
+        self.api_key.is_some()
+    }
+
+    async fn check(&self, req: &ServiceRequest) -> Result<(), KodiError> {
+        if OPEN_PATHS.contains(&req.path()) {
+            return Ok(());
+        }
+
+        if let Some(key) = &self.api_key {
+            let given = req.headers().get("X-Api-Key").and_then(|v| v.to_str().ok());
+            if given != Some(key.as_str()) {
+                return Err(KodiError::InvalidApiKey);
+            }
+        }
+
+        // Behind a proxy every client shares its address, the limit then applies to all of them
+        if self.limit == 0 {
+            return Ok(());
+        }
+        if let Some(addr) = req.peer_addr() {
+            let count = self
+                .windows
+                .get_with(addr.ip(), async { Arc::new(AtomicU32::new(0)) })
+                .await;
+            if count.fetch_add(1, Ordering::Relaxed) >= self.limit {
+                return Err(KodiError::RateLimited { retry_after: RATE_WINDOW.as_secs() });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Middleware rejecting requests without the API key or over the rate limit
+pub async fn guard(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if let Some(access) = req.app_data::<web::Data<Access>>() {
+        access.check(&req).await?;
+    }
+    next.call(req).await
+}
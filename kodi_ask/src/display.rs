@@ -0,0 +1,72 @@
+use std::sync::OnceLock;
+
+const DEFAULT_TEMPLATE: &str = "{index}. {title} ({year})";
+const DEFAULT_TEMPLATE_NO_YEAR: &str = "{index}. {title}";
+const DEFAULT_TEMPLATE_EPISODE: &str = "{index}. {show} {episode} {title}";
+
+/// How the menu lines are written and listed, from the environment:
+/// `DISPLAY_TEMPLATE` (items with a year), `DISPLAY_TEMPLATE_NO_YEAR` (artists and items
+/// Kodi knows no year of), `DISPLAY_TEMPLATE_EPISODE`, each with `{index}`, `{title}`,
+/// `{year}`, `{show}` and `{episode}` placeholders, and `DISPLAY_LOCALE` for the word
+/// before the last item of a spoken list.
+struct Display {
+    template: String,
+    template_no_year: String,
+    template_episode: String,
+    conjunction: &'static str,
+}
+
+fn display() -> &'static Display {
+    static DISPLAY: OnceLock<Display> = OnceLock::new();
+    DISPLAY.get_or_init(|| {
+        let template = |name: &str, default: &str| layered_config::get(name).unwrap_or(default.to_string());
+        Display {
+            template: template("DISPLAY_TEMPLATE", DEFAULT_TEMPLATE),
+            template_no_year: template("DISPLAY_TEMPLATE_NO_YEAR", DEFAULT_TEMPLATE_NO_YEAR),
+            template_episode: template("DISPLAY_TEMPLATE_EPISODE", DEFAULT_TEMPLATE_EPISODE),
+            conjunction: conjunction(&layered_config::get("DISPLAY_LOCALE").unwrap_or_default()),
+        }
+    })
+}
+
+/// "and" in the language of `locale` ("de", "pl_PL", "fr-CA"), English when unknown
+fn conjunction(locale: &str) -> &'static str {
+    let language = locale.split(['_', '-', '.']).next().unwrap_or("").to_lowercase();
+    match language.as_str() {
+        "de" => "und",
+        "pl" => "i",
+        "fr" => "et",
+        "es" => "y",
+        "it" => "e",
+        "nl" => "en",
+        "pt" => "e",
+        "sv" => "och",
+        "da" | "no" | "nb" => "og",
+        _ => "and",
+    }
+}
+
+/// Menu line of one item, `episode` being the "S01E02" of an episode
+pub fn option(index: usize, title: &str, year: Option<&str>, show: Option<&str>, episode: Option<&str>) -> String {
+    let display = display();
+    let template = match (year, show, episode) {
+        (_, Some(_), Some(_)) => &display.template_episode,
+        (Some(_), _, _) => &display.template,
+        (None, _, _) => &display.template_no_year,
+    };
+    template
+        .replace("{index}", &index.to_string())
+        .replace("{title}", title)
+        .replace("{year}", year.unwrap_or(""))
+        .replace("{show}", show.unwrap_or(""))
+        .replace("{episode}", episode.unwrap_or(""))
+}
+
+/// Menu lines as one spoken list: "1. Alien, 2. Aliens and 3. Alien 3"
+pub fn join(options: &[String]) -> String {
+    match options {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} {} {}", rest.join(", "), display().conjunction, last),
+    }
+}
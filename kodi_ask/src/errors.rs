@@ -0,0 +1,90 @@
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::json;
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Debug, Error, Clone)]
+pub enum KodiError {
+    // Connectivity errors (Kodi off, timeout, DNS)
+    #[error("Kodi {room} is unreachable: {message}")]
+    Unreachable { room: String, message: String },
+
+    #[error("Kodi {room} rejected the credentials, check KODI_USER/KODI_PASS")]
+    Unauthorized { room: String },
+
+    // Kodi answered with a JSON-RPC error
+    #[error("Kodi {room} returned an error for {method}: {message}")]
+    Rpc { room: String, method: String, message: String },
+
+    #[error("Failed to parse the response of Kodi {room}: {message}")]
+    Parse { room: String, message: String },
+
+    // 400/404 errors (user fault, do not retry)
+    #[error("Unknown room '{room}'")]
+    UnknownRoom { room: String, rooms: Vec<String> },
+
+    #[error("{0}")]
+    Validation(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("Missing or invalid X-Api-Key")]
+    InvalidApiKey,
+
+    #[error("Too many requests, retry in {retry_after}s")]
+    RateLimited { retry_after: u64 },
+}
+
+impl KodiError {
+    /// Machine readable `state` of the response body, also the error label of the metrics
+    pub fn state(&self) -> &'static str {
+        match self {
+            KodiError::Unreachable { .. } => "KODI_UNREACHABLE",
+            KodiError::Unauthorized { .. } => "KODI_UNAUTHORIZED",
+            KodiError::Rpc { .. } => "KODI_ERROR",
+            KodiError::Parse { .. } => "KODI_INVALID_RESPONSE",
+            KodiError::UnknownRoom { .. } => "UNKNOWN_ROOM",
+            KodiError::Validation(_) => "INVALID_REQUEST",
+            KodiError::NotFound(_) => "NOT_FOUND",
+            KodiError::InvalidApiKey => "UNAUTHORIZED",
+            KodiError::RateLimited { .. } => "RATE_LIMITED",
+        }
+    }
+}
+
+impl ResponseError for KodiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            KodiError::Unreachable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            KodiError::Unauthorized { .. } | KodiError::Rpc { .. } | KodiError::Parse { .. } => StatusCode::BAD_GATEWAY,
+            KodiError::UnknownRoom { .. } | KodiError::NotFound(_) => StatusCode::NOT_FOUND,
+            KodiError::Validation(_) => StatusCode::BAD_REQUEST,
+            KodiError::InvalidApiKey => StatusCode::UNAUTHORIZED,
+            KodiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        // Only Kodi failures need attention, bad requests would just spam the log
+        if !status.is_client_error() {
+            error!(error = %self, "Request failed");
+        }
+
+        let mut body = json!({
+            "state": self.state(),
+            "error": self.to_string(),
+        });
+        if let KodiError::UnknownRoom { rooms, .. } = self {
+            body["rooms"] = json!(rooms);
+        }
+
+        let mut response = HttpResponse::build(status);
+        if let KodiError::RateLimited { retry_after } = self {
+            response.insert_header(("Retry-After", retry_after.to_string()));
+        }
+        response.json(body)
+    }
+}
@@ -0,0 +1,117 @@
+use actix_web::web::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tracing::{info, warn};
+
+use crate::kodi::Kodi;
+
+const DEFAULT_WS_PORT: u16 = 9090;
+const RECONNECT_MIN: Duration = Duration::from_secs(5);
+const RECONNECT_MAX: Duration = Duration::from_secs(300);
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A notification of one Kodi instance, e.g. `Player.OnPlay`
+#[derive(Clone, Debug, Serialize)]
+pub struct KodiEvent {
+    pub room: String,
+    pub method: String,
+    pub data: Value,
+}
+
+/// Relays the notifications of every Kodi instance to any number of subscribers.
+#[derive(Clone)]
+pub struct Events {
+    sender: broadcast::Sender<KodiEvent>,
+}
+
+impl Events {
+    /// Listen to every instance on `KODI_WS_PORT` (Kodi's "Allow remote control from
+    /// applications on other systems" must be on), reconnecting when the connection drops
+    pub fn spawn(kodis: &[Kodi]) -> Self {
+        let port = layered_config::parse_or("KODI_WS_PORT", DEFAULT_WS_PORT);
+        let (sender, _) = broadcast::channel(64);
+
+        for kodi in kodis {
+            let name = kodi.name.clone();
+            let url = kodi.notifications_url(port);
+            let sender = sender.clone();
+            actix_web::rt::spawn(async move {
+                let mut backoff = RECONNECT_MIN;
+                loop {
+                    match listen(&name, &url, &sender).await {
+                        Ok(()) => {
+                            info!(room = %name, "Kodi notification connection closed");
+                            backoff = RECONNECT_MIN;
+                        }
+                        Err(e) => {
+                            warn!(room = %name, error = %e, retry_in = ?backoff, "Kodi notifications unavailable");
+                            backoff = (backoff * 2).min(RECONNECT_MAX);
+                        }
+                    }
+                    tokio::time::sleep(backoff).await;
+                }
+            });
+        }
+
+        Self { sender }
+    }
+
+    /// Server-Sent Events of the notifications of `room`, of every room without one
+    pub fn stream(&self, room: Option<String>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        let receiver = self.sender.subscribe();
+        futures_util::stream::unfold((receiver, room), |(mut receiver, room)| async move {
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = tokio::time::sleep(KEEP_ALIVE) => {
+                        return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), (receiver, room)));
+                    }
+                };
+                match event {
+                    Ok(event) if room.as_ref().is_none_or(|r| r.eq_ignore_ascii_case(&event.room)) => {
+                        let data = serde_json::to_string(&event).unwrap_or_default();
+                        let frame = format!("event: {}\ndata: {}\n\n", event.method, data);
+                        return Some((Ok(Bytes::from(frame)), (receiver, room)));
+                    }
+                    Ok(_) => continue,
+                    // A slow client missed some events, carry on with the next ones
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+/// Forward the notifications of one connection until it closes
+async fn listen(name: &str, url: &str, sender: &broadcast::Sender<KodiEvent>) -> Result<(), String> {
+    let (mut socket, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+    info!(room = %name, "Listening to Kodi notifications");
+
+    while let Some(message) = socket.next().await {
+        let text = match message.map_err(|e| e.to_string())? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let Ok(notification) = serde_json::from_str::<Value>(&text) else {
+            continue;
+        };
+        // Responses carry an id, notifications a method
+        let Some(method) = notification.get("method").and_then(|m| m.as_str()) else {
+            continue;
+        };
+        let data = notification.get("params").and_then(|p| p.get("data")).cloned().unwrap_or(Value::Null);
+        // No subscriber is not an error
+        let _ = sender.send(KodiEvent {
+            room: name.to_string(),
+            method: method.to_string(),
+            data,
+        });
+    }
+    Ok(())
+}
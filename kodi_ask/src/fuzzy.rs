@@ -0,0 +1,211 @@
+use strsim::{jaro_winkler, normalized_levenshtein};
+
+// Two words are taken as the same when they are at least this similar
+const TOKEN_MATCH: f64 = 0.85;
+
+/// How well `title` matches the (possibly misheard) `query`, from 0.0 to 1.0.
+///
+/// Blends whole-string similarity (Jaro-Winkler and Levenshtein) with how many query words
+/// closely match a word of the title, so "lord of the rins" still finds "The Lord of the Rings".
+/// A title containing the query as is always scores 1.0.
+pub fn score(query: &str, title: &str) -> f64 {
+    let query = normalize(query);
+    let title = normalize(title);
+    if query.is_empty() || title.contains(&query) {
+        return 1.0;
+    }
+
+    let whole = jaro_winkler(&query, &title).max(normalized_levenshtein(&query, &title));
+
+    let title_tokens: Vec<&str> = title.split_whitespace().collect();
+    let query_tokens: Vec<&str> = query.split_whitespace().collect();
+    let matched = query_tokens
+        .iter()
+        .filter(|q| title_tokens.iter().any(|t| jaro_winkler(q, t) >= TOKEN_MATCH))
+        .count();
+    let tokens = matched as f64 / query_tokens.len() as f64;
+
+    whole.max(tokens * 0.95)
+}
+
+/// Lowercase, with punctuation turned into spaces
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Sound-alike key of `text`, one simplified Metaphone code per word, so "shindlers list"
+/// and "Schindler's List" both become "XNTLRS LST".
+pub fn phonetic(text: &str) -> String {
+    normalize(&text.replace(['\'', '’'], ""))
+        .split_whitespace()
+        .map(metaphone)
+        .filter(|key| !key.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How well the phonetic keys match, a bit below the same spelling
+pub fn phonetic_score(query_key: &str, title_key: &str) -> f64 {
+    if query_key.is_empty() {
+        return 0.0;
+    }
+    score(query_key, title_key) * 0.9
+}
+
+fn metaphone(word: &str) -> String {
+    let w: Vec<char> = word.to_ascii_uppercase().chars().filter(|c| c.is_ascii_alphabetic()).collect();
+    let is_vowel = |c: Option<char>| matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'));
+    let is_front = |c: Option<char>| matches!(c, Some('E' | 'I' | 'Y'));
+
+    // Silent first letter: KNight, GNome, PNeumonia, WRite, PSalm
+    let mut i = match (w.first(), w.get(1)) {
+        (Some('K' | 'G' | 'P'), Some('N')) | (Some('W'), Some('R')) | (Some('P'), Some('S')) => 1,
+        _ => 0,
+    };
+    let start = i;
+    let mut key = String::new();
+
+    while i < w.len() {
+        let c = w[i];
+        let prev = if i > 0 { Some(w[i - 1]) } else { None };
+        let next = w.get(i + 1).copied();
+        let after = w.get(i + 2).copied();
+        let mut skip = 0;
+
+        if prev == Some(c) && c != 'C' {
+            i += 1;
+            continue;
+        }
+
+        let code = match c {
+            'A' | 'E' | 'I' | 'O' | 'U' if i == start => "A",
+            'A' | 'E' | 'I' | 'O' | 'U' => "",
+            'B' if prev == Some('M') && next.is_none() => "",
+            'C' if next == Some('H') => {
+                skip = 1;
+                "X"
+            }
+            'C' if is_front(next) => "S",
+            'C' => "K",
+            'D' if next == Some('G') && is_front(after) => {
+                skip = 1;
+                "J"
+            }
+            'D' => "T",
+            'G' if next == Some('H') && !is_vowel(after) => {
+                skip = 1;
+                ""
+            }
+            'G' if next == Some('N') && after.is_none() => "",
+            'G' if is_front(next) => "J",
+            'G' => "K",
+            'H' if is_vowel(next) && !matches!(prev, Some('C' | 'G' | 'P' | 'S' | 'T')) => "H",
+            'H' => "",
+            'K' if prev == Some('C') => "",
+            'P' if next == Some('H') => {
+                skip = 1;
+                "F"
+            }
+            'Q' => "K",
+            'S' if next == Some('H') => {
+                skip = 1;
+                "X"
+            }
+            // German "sch" as in Schindler, spoken like "sh"
+            'S' if next == Some('C') && after == Some('H') => {
+                skip = 2;
+                "X"
+            }
+            'T' if next == Some('H') => {
+                skip = 1;
+                "0"
+            }
+            'V' => "F",
+            'W' | 'Y' if !is_vowel(next) => "",
+            'W' => "W",
+            'Y' => "Y",
+            'X' if i == start => "S",
+            'X' => "KS",
+            'Z' | 'S' => "S",
+            'B' => "B",
+            'F' => "F",
+            'J' => "J",
+            'K' => "K",
+            'L' => "L",
+            'M' => "M",
+            'N' => "N",
+            'P' => "P",
+            'R' => "R",
+            'T' => "T",
+            _ => "",
+        };
+        key.push_str(code);
+        i += 1 + skip;
+    }
+
+    // Letters coding the same sound next to each other count once
+    let mut collapsed = String::new();
+    for c in key.chars() {
+        if !collapsed.ends_with(c) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misspelled_word_still_finds_title() {
+        let hit = score("lord of the rins", "The Lord of the Rings");
+        assert!(hit >= 0.75, "scored {hit}");
+        assert!(hit > score("lord of the rins", "Gone with the Wind"));
+    }
+
+    #[test]
+    fn title_containing_query_scores_full() {
+        assert_eq!(score("Alien", "Aliens: Special Edition"), 1.0);
+        assert_eq!(score("star wars", "Star Wars: A New Hope"), 1.0);
+    }
+
+    #[test]
+    fn unrelated_title_scores_low() {
+        assert!(score("casablanca", "The Matrix") < 0.75);
+    }
+
+    #[test]
+    fn misheard_title_has_same_phonetic_key() {
+        assert_eq!(phonetic("shindlers list"), "XNTLRS LST");
+        assert_eq!(phonetic("Schindler's List"), "XNTLRS LST");
+    }
+
+    #[test]
+    fn metaphone_drops_silent_letters() {
+        assert_eq!(metaphone("knight"), "NT");
+        assert_eq!(metaphone("wright"), "RT");
+        assert_eq!(metaphone("thumb"), "0M");
+    }
+
+    #[test]
+    fn metaphone_codes_consonant_sounds() {
+        assert_eq!(metaphone("phone"), "FN");
+        assert_eq!(metaphone("city"), "ST");
+        assert_eq!(metaphone("cat"), "KT");
+        assert_eq!(metaphone("bridge"), "BRJ");
+        assert_eq!(metaphone("xavier"), "SFR");
+    }
+
+    #[test]
+    fn phonetic_score_without_query_key_is_zero() {
+        assert_eq!(phonetic_score("", "XNTLRS LST"), 0.0);
+        assert_eq!(phonetic_score("XNTLRS LST", "XNTLRS LST"), 0.9);
+    }
+}
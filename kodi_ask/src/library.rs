@@ -0,0 +1,206 @@
+use moka::future::Cache;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+use crate::errors::KodiError;
+use crate::fuzzy;
+use crate::kodi::Kodi;
+use crate::metrics;
+
+const DEFAULT_CACHE_TTL_SECONDS: u64 = 300;
+// Library items kept across all cached listings, a full movie listing counting once per movie
+const DEFAULT_CACHE_MAX_ITEMS: u64 = 200_000;
+pub const MUSIC_PLAYLIST: u8 = 0;
+pub const VIDEO_PLAYLIST: u8 = 1;
+
+/// A searchable part of the Kodi library.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Movies,
+    TvShows,
+    Episodes,
+    Albums,
+    Artists,
+    Songs,
+}
+
+impl MediaKind {
+    /// Key of the list in the JSON-RPC result and in our responses
+    pub fn name(self) -> &'static str {
+        match self {
+            MediaKind::Movies => "movies",
+            MediaKind::TvShows => "tvshows",
+            MediaKind::Episodes => "episodes",
+            MediaKind::Albums => "albums",
+            MediaKind::Artists => "artists",
+            MediaKind::Songs => "songs",
+        }
+    }
+
+    /// Type tag of a single item, as in `?type=` and the combined search results
+    pub fn item_name(self) -> &'static str {
+        match self {
+            MediaKind::Movies => "movie",
+            MediaKind::TvShows => "tvshow",
+            MediaKind::Episodes => "episode",
+            MediaKind::Albums => "album",
+            MediaKind::Artists => "artist",
+            MediaKind::Songs => "song",
+        }
+    }
+
+    /// Field holding the item id, also the item key of `Player.Open`
+    pub fn id_key(self) -> &'static str {
+        match self {
+            MediaKind::Movies => "movieid",
+            MediaKind::TvShows => "tvshowid",
+            MediaKind::Episodes => "episodeid",
+            MediaKind::Albums => "albumid",
+            MediaKind::Artists => "artistid",
+            MediaKind::Songs => "songid",
+        }
+    }
+
+    /// Field holding the name searched for
+    pub fn label_key(self) -> &'static str {
+        match self {
+            MediaKind::Artists => "artist",
+            _ => "title",
+        }
+    }
+
+    /// Query parameters that narrow the listing, each a Kodi filter field
+    pub fn filter_fields(self) -> &'static [&'static str] {
+        match self {
+            MediaKind::Movies | MediaKind::TvShows => &["year", "genre", "actor"],
+            MediaKind::Episodes => &["year", "actor"],
+            MediaKind::Albums | MediaKind::Songs => &["year", "genre"],
+            MediaKind::Artists => &["genre"],
+        }
+    }
+
+    /// Kodi playlist the items are queued on: 0 is music, 1 video
+    pub fn playlist_id(self) -> u8 {
+        match self {
+            MediaKind::Movies | MediaKind::TvShows | MediaKind::Episodes => VIDEO_PLAYLIST,
+            _ => MUSIC_PLAYLIST,
+        }
+    }
+
+    fn method(self) -> &'static str {
+        match self {
+            MediaKind::Movies => "VideoLibrary.GetMovies",
+            MediaKind::TvShows => "VideoLibrary.GetTVShows",
+            MediaKind::Episodes => "VideoLibrary.GetEpisodes",
+            MediaKind::Albums => "AudioLibrary.GetAlbums",
+            MediaKind::Artists => "AudioLibrary.GetArtists",
+            MediaKind::Songs => "AudioLibrary.GetSongs",
+        }
+    }
+
+    pub fn properties(self) -> Value {
+        match self {
+            MediaKind::Movies => json!(["title", "originaltitle", "year", "playcount", "resume", "lastplayed"]),
+            MediaKind::TvShows => json!(["title", "originaltitle", "year"]),
+            MediaKind::Episodes => json!(["title", "showtitle", "season", "episode", "year", "playcount", "resume", "lastplayed"]),
+            MediaKind::Albums => json!(["title", "year", "artist"]),
+            MediaKind::Artists => json!([]),
+            MediaKind::Songs => json!(["title", "year", "artist", "album"]),
+        }
+    }
+}
+
+/// Items of one listing, with the phonetic key of each label when enabled
+pub struct Listing {
+    pub items: Vec<Value>,
+    pub phonetic: Option<Vec<String>>,
+}
+
+/// Library listings per Kodi instance, cached so a voice query doesn't pull the whole
+/// library every time.
+pub struct Library {
+    client: Client,
+    // Key: "<room>/<listing>"
+    cache: Cache<String, Arc<Listing>>,
+    phonetic: bool,
+}
+
+impl Library {
+    /// Entries live for `LIBRARY_CACHE_TTL_SECONDS`, `PHONETIC_MATCHING=true` indexes how
+    /// the labels sound when loading them. Every filter combination is a listing of its own,
+    /// so the cache holds at most `LIBRARY_CACHE_MAX_ITEMS` items in total.
+    pub fn from_env() -> Self {
+        let ttl = layered_config::parse_or("LIBRARY_CACHE_TTL_SECONDS", DEFAULT_CACHE_TTL_SECONDS);
+        let max_items = layered_config::parse_or("LIBRARY_CACHE_MAX_ITEMS", DEFAULT_CACHE_MAX_ITEMS);
+
+        Self {
+            client: Client::new(),
+            cache: Cache::builder()
+                .time_to_live(Duration::from_secs(ttl))
+                .max_capacity(max_items)
+                // Empty listings still take an entry
+                .weigher(|_, listing: &Arc<Listing>| u32::try_from(listing.items.len()).unwrap_or(u32::MAX).max(1))
+                // invalidate_entries_if() fails without it
+                .support_invalidation_closures()
+                .build(),
+            phonetic: layered_config::flag("PHONETIC_MATCHING"),
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Every item of `kind` in the instance's library, narrowed down by Kodi with `filter`
+    pub async fn items(&self, kodi: &Kodi, kind: MediaKind, filter: Option<&Value>) -> Result<Arc<Listing>, KodiError> {
+        let key = match filter {
+            Some(filter) => format!("{}/{}?{}", kodi.name, kind.name(), filter),
+            None => format!("{}/{}", kodi.name, kind.name()),
+        };
+        let loaded = AtomicBool::new(false);
+        let items = self
+            .cache
+            .try_get_with(key, async {
+                loaded.store(true, Ordering::Relaxed);
+                let mut params = json!({ "properties": kind.properties() });
+                if let Some(filter) = filter {
+                    params["filter"] = filter.clone();
+                }
+                let result = kodi.call(&self.client, kind.method(), params).await?;
+                let items = result
+                    .get(kind.name())
+                    .and_then(|m| m.as_array())
+                    .cloned()
+                    .unwrap_or_default();
+                let phonetic = self.phonetic.then(|| {
+                    items
+                        .iter()
+                        .map(|item| fuzzy::phonetic(item.get(kind.label_key()).and_then(|v| v.as_str()).unwrap_or("")))
+                        .collect()
+                });
+                info!(room = %kodi.name, kind = kind.name(), count = items.len(), "Loaded library into cache");
+                Ok(Arc::new(Listing { items, phonetic }))
+            })
+            .await
+            .map_err(|e: Arc<KodiError>| (*e).clone())?;
+
+        let result = if loaded.load(Ordering::Relaxed) { "miss" } else { "hit" };
+        metrics::LIBRARY_CACHE.with_label_values(&[result]).inc();
+        Ok(items)
+    }
+
+    /// Drop the cached listings of `room`, of every instance without
+    pub fn invalidate(&self, room: Option<&str>) {
+        match room {
+            Some(room) => {
+                let prefix = format!("{}/", room);
+                let _ = self.cache.invalidate_entries_if(move |key, _| key.starts_with(&prefix));
+            }
+            None => self.cache.invalidate_all(),
+        }
+    }
+}
@@ -1,125 +1,1396 @@
-use actix_web::{web, App, HttpServer, HttpResponse, Responder};
-use reqwest::Client;
+use actix_web::{middleware::{from_fn, Logger}, web, App, HttpRequest, HttpServer, HttpResponse, Responder};
+use futures_util::future::try_join_all;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::env;
 use std::collections::HashMap;
+use tracing::{debug, info, instrument, warn};
+use service_kit::logging::{self, LogFormat};
+use service_kit::request_id;
 
-const DEFAULT_KODI_URL: &str = "http://192.168.0.5/jsonrpc";
+mod access;
+mod display;
+mod errors;
+mod events;
+mod fuzzy;
+mod kodi;
+mod library;
+mod metrics;
+mod sessions;
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OutputMovie {
+use access::Access;
+use errors::KodiError;
+use events::Events;
+use kodi::{Instances, Kodi};
+use library::{Library, MediaKind, MUSIC_PLAYLIST, VIDEO_PLAYLIST};
+use sessions::{Selection, Sessions};
+
+const DEFAULT_MATCH_THRESHOLD: f64 = 0.75;
+// Menu lines read out in voice mode, the rest stay in display_options
+const VOICE_MAX_OPTIONS: usize = 5;
+const DEFAULT_LIST_LIMIT: usize = 10;
+
+#[derive(Serialize, Debug)]
+struct OutputItem {
     index: usize,
     title: String,
     year: String,
-    movieid: Option<i64>,
+    // Serialized under the kind's id key, e.g. "movieid"
+    #[serde(skip)]
+    id: Option<i64>,
+    score: f64,
+    // Watched status, only known for movies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    playcount: Option<u64>,
+    // Saved position in seconds, set when the movie was stopped partway through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lastplayed: Option<String>,
+    // Show and "S01E02" of an episode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    show: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    episode: Option<String>,
 }
 
-async fn fetch_movies_from_kodi(movie_name: &str) -> Vec<OutputMovie> {
-    let kodi_url = env::var("KODI_URL").unwrap_or_else(|_| DEFAULT_KODI_URL.to_string());
+impl OutputItem {
+    /// Item of `kind` as Kodi listed it, numbered later, with its original title rather than
+    /// the one in Kodi's language when `original` and Kodi knows it
+    fn from_kodi(kind: MediaKind, item: &Value, score: f64, original: bool) -> Self {
+        let title = match original_title(item).filter(|_| original) {
+            Some(title) => title,
+            None => item.get(kind.label_key()).and_then(|v| v.as_str()).unwrap_or(""),
+        };
+        let year = item
+            .get("year")
+            .map(|v| v.to_string())
+            .unwrap_or("Unknown".to_string());
 
-    let client = Client::new();
+        // Kodi sends an empty lastplayed and a zero position for items never played
+        let resume = item
+            .get("resume")
+            .and_then(|r| r.get("position"))
+            .and_then(|p| p.as_f64())
+            .filter(|p| *p > 0.0);
+        let lastplayed = item
+            .get("lastplayed")
+            .and_then(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+            .map(String::from);
+        let episode = match (item.get("season").and_then(|v| v.as_u64()), item.get("episode").and_then(|v| v.as_u64())) {
+            (Some(season), Some(episode)) => Some(format!("S{:02}E{:02}", season, episode)),
+            _ => None,
+        };
 
-    let payload = json!({
-        "jsonrpc": "2.0",
-        "method": "VideoLibrary.GetMovies",
-        "params": { "properties": ["title", "year"] },
-        "id": 1
-    });
+        OutputItem {
+            index: 0,
+            title: title.to_string(),
+            year,
+            id: item.get(kind.id_key()).and_then(|v| v.as_i64()),
+            score,
+            playcount: item.get("playcount").and_then(|v| v.as_u64()),
+            resume,
+            lastplayed,
+            show: item.get("showtitle").and_then(|v| v.as_str()).map(String::from),
+            episode,
+        }
+    }
+}
+
+/// Original title of a movie or TV show, when it differs from the title
+fn original_title(item: &Value) -> Option<&str> {
+    item.get("originaltitle")
+        .and_then(|v| v.as_str())
+        .filter(|t| !t.is_empty() && Some(*t) != item.get("title").and_then(|v| v.as_str()))
+}
 
-    let resp = match client.post(&kodi_url).json(&payload).send().await {
-        Ok(r) => r,
-        Err(e) => {
-            println!("[ERROR] Failed HTTP request to Kodi: {}", e);
-            return vec![];
+/// Whether `?titles=original` asked for original titles instead of Kodi's localized ones
+fn original_titles(query: &HashMap<String, String>) -> Result<bool, KodiError> {
+    match query.get("titles").map(String::as_str) {
+        None | Some("localized") => Ok(false),
+        Some("original") => Ok(true),
+        Some(other) => Err(KodiError::Validation(format!("Invalid titles '{}', expected original or localized", other))),
+    }
+}
+
+/// Minimum fuzzy score for an item to be listed, from `MATCH_THRESHOLD`
+fn match_threshold() -> f64 {
+    layered_config::parse_or("MATCH_THRESHOLD", DEFAULT_MATCH_THRESHOLD)
+}
+
+/// Kodi JSON-RPC filter from the `?year=`, `?genre=` and `?actor=` parameters the kind supports,
+/// and `?unwatched=true` for movies
+fn build_filter(kind: MediaKind, query: &HashMap<String, String>) -> Result<Option<Value>, KodiError> {
+    let mut rules = Vec::new();
+    for field in ["year", "genre", "actor"] {
+        let Some(value) = query.get(field).map(|v| v.trim()).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        if !kind.filter_fields().contains(&field) {
+            return Err(KodiError::Validation(format!(
+                "{} can't be filtered by {}, only by {}",
+                kind.name(),
+                field,
+                kind.filter_fields().join(", ")
+            )));
         }
-    };
+        if field == "year" && value.parse::<u16>().is_err() {
+            return Err(KodiError::Validation(format!("Invalid year '{}'", value)));
+        }
+        // Years must match exactly, names only need to contain the value
+        let operator = if field == "year" { "is" } else { "contains" };
+        rules.push(json!({ "field": field, "operator": operator, "value": value }));
+    }
 
-    let json_resp: Value = match resp.json().await {
-        Ok(v) => v,
-        Err(e) => {
-            println!("[ERROR] Failed to parse Kodi JSON response: {}", e);
-            return vec![];
+    if query.get("unwatched").is_some_and(|v| v == "true" || v == "1") {
+        if kind != MediaKind::Movies {
+            return Err(KodiError::Validation(format!("{} can't be filtered by unwatched", kind.name())));
         }
-    };
+        rules.push(json!({ "field": "playcount", "operator": "is", "value": "0" }));
+    }
 
-    let movies = json_resp
-        .get("result")
-        .and_then(|r| r.get("movies"))
-        .and_then(|m| m.as_array())
-        .cloned()
-        .unwrap_or_default();
+    Ok(match rules.len() {
+        0 => None,
+        1 => rules.pop(),
+        _ => Some(json!({ "and": rules })),
+    })
+}
+
+async fn fetch_items_from_kodi(
+    library: &Library,
+    kodi: &Kodi,
+    kind: MediaKind,
+    filter: Option<&Value>,
+    name: &str,
+    original: bool,
+) -> Result<Vec<OutputItem>, KodiError> {
+    let listing = library.items(kodi, kind, filter).await?;
 
+    let threshold = match_threshold();
     let mut results = Vec::new();
-    let mut index = 1usize;
+    // Catches what speech recognition misspelled beyond the edit distance
+    let name_key = listing.phonetic.as_ref().map(|_| fuzzy::phonetic(name));
 
-    for movie in movies {
-        let title = movie.get("title").and_then(|v| v.as_str()).unwrap_or("");
+    for (i, item) in listing.items.iter().enumerate() {
+        let title = item.get(kind.label_key()).and_then(|v| v.as_str()).unwrap_or("");
 
-        if title.to_lowercase().contains(&movie_name.to_lowercase()) {
-            let year = movie
-                .get("year")
-                .map(|v| v.to_string())
-                .unwrap_or("Unknown".to_string());
+        // Either title may be the one the user knows
+        let mut score = fuzzy::score(name, title);
+        if let Some(original_title) = original_title(item) {
+            score = score.max(fuzzy::score(name, original_title));
+        }
+        if let (Some(name_key), Some(keys)) = (&name_key, &listing.phonetic) {
+            score = score.max(fuzzy::phonetic_score(name_key, &keys[i]));
+        }
+        if score >= threshold {
+            results.push(OutputItem::from_kodi(kind, item, score, original));
+        }
+    }
 
-            let movieid = movie.get("movieid").and_then(|v| v.as_i64());
+    // Best matches first, numbered in that order for the voice menu
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    for (i, item) in results.iter_mut().enumerate() {
+        item.index = i + 1;
+    }
 
-            results.push(OutputMovie {
-                index,
-                title: title.to_string(),
-                year,
-                movieid,
-            });
+    Ok(results)
+}
 
-            index += 1;
-        }
+/// Whether `?mode=voice` asked for a `speech_text`
+fn voice_mode(query: &HashMap<String, String>) -> Result<bool, KodiError> {
+    match query.get("mode").map(String::as_str) {
+        None => Ok(false),
+        Some("voice") => Ok(true),
+        Some(other) => Err(KodiError::Validation(format!("Invalid mode '{}', expected voice", other))),
     }
+}
 
-    results
+/// Menu line of a match, e.g. "1. Alien (1979)"
+fn display_option(kind: MediaKind, item: &OutputItem) -> String {
+    let year = Some(item.year.as_str()).filter(|y| kind != MediaKind::Artists && *y != "Unknown");
+    display::option(item.index, &item.title, year, item.show.as_deref(), item.episode.as_deref())
 }
 
-async fn movies_endpoint(query: web::Query<HashMap<String, String>>) -> impl Responder {
-    let movie_name = query.get("name").cloned().unwrap_or_default();
+/// How `count` items of `kind` are read out
+fn spoken_kind(kind: MediaKind, count: usize) -> &'static str {
+    match (kind, count) {
+        (MediaKind::TvShows, 1) => "TV show",
+        (MediaKind::TvShows, _) => "TV shows",
+        (kind, 1) => kind.item_name(),
+        (kind, _) => kind.name(),
+    }
+}
 
-    println!("[REQUEST] /movies?name={}", movie_name);
+/// One sentence for a voice assistant to read out, e.g.
+/// "I found 2 movies: 1. Alien (1979), 2. Aliens (1986). Say a number."
+fn speech_text(kind_name: &str, name: &str, display_options: &[String]) -> String {
+    match display_options.len() {
+        0 if name.is_empty() => format!("I found no {}.", kind_name),
+        0 => format!("I found no {} matching {}.", kind_name, name),
+        n if n > VOICE_MAX_OPTIONS => format!(
+            "I found {} {}, the best are: {}. Say a number.",
+            n,
+            kind_name,
+            display::join(&display_options[..VOICE_MAX_OPTIONS])
+        ),
+        n => format!("I found {} {}: {}. Say a number.", n, kind_name, display::join(display_options)),
+    }
+}
+
+/// The instance picked by the `/{room}/...` path prefix or the `?room=` parameter
+fn select_kodi<'a>(
+    instances: &'a Instances,
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+) -> Result<&'a Kodi, KodiError> {
+    instances.get(requested_room(req, query))
+}
+
+/// Session of the dialogue, from the `X-Session-Id` header or the `?session=` parameter
+fn session_id(req: &HttpRequest, query: &HashMap<String, String>) -> Option<String> {
+    req.headers()
+        .get("X-Session-Id")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| query.get("session").map(String::as_str))
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Room named by the path prefix or the `?room=` parameter, if any
+fn requested_room<'a>(req: &'a HttpRequest, query: &'a HashMap<String, String>) -> Option<&'a str> {
+    req.match_info().get("room").or_else(|| query.get("room").map(String::as_str))
+}
 
-    let movies = fetch_movies_from_kodi(&movie_name).await;
+/// Search `kind` for `?name=`, answering with the matches, the id of each menu number
+/// and the menu lines to read out. Without a name every item passing the filters matches.
+/// `?mode=voice` adds the menu as one `speech_text` sentence, `?titles=original` lists the
+/// original titles of movies and TV shows instead of the localized ones.
+#[instrument(skip_all, fields(kind = kind.name(), room, request_id))]
+async fn search(
+    kind: MediaKind,
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let name = query.get("name").cloned().unwrap_or_default();
+    let filter = build_filter(kind, &query)?;
+    let voice = voice_mode(&query)?;
+
+    info!(name = %name, filter = ?filter, "Search");
+
+    let original = original_titles(&query)?;
+    let items = fetch_items_from_kodi(&library, kodi, kind, filter.as_ref(), &name, original).await?;
 
     // Build number_to_id map and display options
     let mut number_to_id = serde_json::Map::new();
     let mut display_options = Vec::new();
+    let mut output = Vec::new();
+    let mut selection = HashMap::new();
+
+    for item in &items {
+        let mut entry = json!(item);
+        entry[kind.id_key()] = json!(item.id);
+        output.push(entry);
+
+        if let Some(id) = item.id {
+            number_to_id.insert(item.index.to_string(), json!(id));
+            display_options.push(display_option(kind, item));
+            selection.insert(item.index, (kind, id));
+        }
+    }
+
+    if let Some(session) = session_id(&req, &query) {
+        sessions.remember(session, Selection { room: kodi.name.clone(), items: selection }).await;
+    }
+
+    let output_len = output.len();
+    let mut out = json!({
+        "state": "OK",
+        kind.name(): output,
+        "number_to_id": number_to_id,
+        "display_options": display_options
+    });
+    if voice {
+        let kind_name = spoken_kind(kind, display_options.len());
+        out["speech_text"] = json!(speech_text(kind_name, &name, &display_options));
+    }
+
+    // The full body is only worth logging while debugging the voice menus
+    info!(matches = output_len, "Search answered");
+    debug!(response = %out, "Search response");
+
+    Ok(HttpResponse::Ok().json(out))
+}
+
+async fn movies_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    search(MediaKind::Movies, instances, library, sessions, req, query).await
+}
+
+/// Plot, runtime, rating, genres, cast and artwork of a movie, to confirm it before playing
+#[instrument(skip_all, fields(room, request_id))]
+async fn movie_details_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let id: i64 = req
+        .match_info()
+        .get("movieid")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| KodiError::Validation("Invalid movie id".to_string()))?;
+
+    info!(movieid = id, "Movie details");
+
+    let params = json!({
+        "movieid": id,
+        "properties": ["title", "year", "plot", "runtime", "rating", "genre", "cast", "art", "playcount"],
+    });
+    let result = kodi.call(library.client(), "VideoLibrary.GetMovieDetails", params).await?;
+    let details = result.get("moviedetails").cloned().unwrap_or(Value::Null);
+
+    // Artwork comes as image:// paths, only usable through Kodi's /image/ handler
+    let art: serde_json::Map<String, Value> = details
+        .get("art")
+        .and_then(|a| a.as_object())
+        .map(|art| {
+            art.iter()
+                .filter_map(|(kind, path)| Some((kind.clone(), json!(kodi.image_url(path.as_str()?)))))
+                .collect()
+        })
+        .unwrap_or_default();
+    let cast: Vec<Value> = details
+        .get("cast")
+        .and_then(|c| c.as_array())
+        .map(|cast| {
+            cast.iter()
+                .map(|actor| {
+                    json!({
+                        "name": actor.get("name"),
+                        "role": actor.get("role"),
+                        "thumbnail": actor.get("thumbnail").and_then(|t| t.as_str()).map(|t| kodi.image_url(t)),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "state": "OK",
+        "movie": {
+            "movieid": id,
+            "title": details.get("title"),
+            "year": details.get("year"),
+            "plot": details.get("plot"),
+            // Seconds
+            "runtime": details.get("runtime"),
+            "rating": details.get("rating"),
+            "genres": details.get("genre"),
+            "playcount": details.get("playcount"),
+            "cast": cast,
+            "art": art,
+        },
+    })))
+}
+
+async fn tvshows_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    search(MediaKind::TvShows, instances, library, sessions, req, query).await
+}
+
+/// Search movies, TV shows and music at once for `?name=`, answering with one list of
+/// type-tagged matches numbered across all kinds, best first
+#[instrument(skip_all, fields(room, request_id))]
+async fn search_all_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let name = query.get("name").cloned().unwrap_or_default();
+    let voice = voice_mode(&query)?;
+    let original = original_titles(&query)?;
+
+    info!(name = %name, "Search all");
+
+    let kinds = [MediaKind::Movies, MediaKind::TvShows, MediaKind::Albums, MediaKind::Artists, MediaKind::Songs];
+    let per_kind = try_join_all(kinds.into_iter().map(|kind| {
+        let (library, name) = (&library, &name);
+        async move {
+            let items = fetch_items_from_kodi(library, kodi, kind, None, name, original).await?;
+            Ok::<_, KodiError>(items.into_iter().map(move |item| (kind, item)))
+        }
+    }))
+    .await?;
+
+    let mut matches: Vec<(MediaKind, OutputItem)> = per_kind.into_iter().flatten().collect();
+    matches.sort_by(|(_, a), (_, b)| b.score.total_cmp(&a.score));
+
+    let out = mixed_response(&sessions, kodi, &req, &query, matches, voice.then_some(("result", "results")), &name).await;
+
+    info!(matches = out["results"].as_array().map_or(0, |r| r.len()), "Search all answered");
+    debug!(response = %out, "Search all response");
+
+    Ok(HttpResponse::Ok().json(out))
+}
+
+/// Numbered response over items of several kinds, each tagged with its type. The session
+/// remembers the numbering, `voice` names the items for the `speech_text`.
+async fn mixed_response(
+    sessions: &Sessions,
+    kodi: &Kodi,
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+    mut matches: Vec<(MediaKind, OutputItem)>,
+    voice: Option<(&str, &str)>,
+    name: &str,
+) -> Value {
+    let mut number_to_id = serde_json::Map::new();
+    let mut display_options = Vec::new();
+    let mut output = Vec::new();
+    let mut selection = HashMap::new();
 
-    for movie in &movies {
-        if let Some(mid) = movie.movieid {
-            number_to_id.insert(movie.index.to_string(), json!(mid));
-            display_options.push(format!("{}. {} ({})", movie.index, movie.title, movie.year));
+    for (i, (kind, item)) in matches.iter_mut().enumerate() {
+        item.index = i + 1;
+
+        let mut entry = json!(item);
+        entry["type"] = json!(kind.item_name());
+        entry[kind.id_key()] = json!(item.id);
+        output.push(entry);
+
+        if let Some(id) = item.id {
+            number_to_id.insert(item.index.to_string(), json!({ "type": kind.item_name(), "id": id }));
+            display_options.push(format!("{}, {}", display_option(*kind, item), spoken_kind(*kind, 1)));
+            selection.insert(item.index, (*kind, id));
         }
     }
 
-    let out = json!({
+    if let Some(session) = session_id(req, query) {
+        sessions.remember(session, Selection { room: kodi.name.clone(), items: selection }).await;
+    }
+
+    let mut out = json!({
         "state": "OK",
-        "movies": movies,
+        "results": output,
         "number_to_id": number_to_id,
         "display_options": display_options
     });
+    if let Some((singular, plural)) = voice {
+        let kind_name = if display_options.len() == 1 { singular } else { plural };
+        out["speech_text"] = json!(speech_text(kind_name, name, &display_options));
+    }
+    out
+}
+
+/// `?limit=` of the listings, 10 by default
+fn limit(query: &HashMap<String, String>) -> Result<usize, KodiError> {
+    match query.get("limit") {
+        None => Ok(DEFAULT_LIST_LIMIT),
+        Some(v) => v
+            .parse()
+            .ok()
+            .filter(|l| *l > 0)
+            .ok_or_else(|| KodiError::Validation(format!("Invalid limit '{}'", v))),
+    }
+}
+
+/// Call a listing `method` for each kind concurrently and merge the items, newest `sort_key`
+/// first, up to `limit`
+async fn video_listing(
+    kodi: &Kodi,
+    library: &Library,
+    calls: [(MediaKind, &str, Value); 2],
+    sort_key: &str,
+    limit: usize,
+    original: bool,
+) -> Result<Vec<(MediaKind, OutputItem)>, KodiError> {
+    let per_kind = try_join_all(calls.into_iter().map(|(kind, method, params)| async move {
+        let params = listing_params(kind, params, sort_key, limit);
+        let result = kodi.call(library.client(), method, params).await?;
+        let items = result.get(kind.name()).and_then(|i| i.as_array()).cloned().unwrap_or_default();
+        Ok::<_, KodiError>(items.into_iter().map(move |item| (kind, item)))
+    }))
+    .await?;
+
+    // Kodi dates ("2024-05-01 20:15:00") sort as strings
+    let mut items: Vec<(MediaKind, Value)> = per_kind.into_iter().flatten().collect();
+    let date = |item: &Value| item.get(sort_key).and_then(|d| d.as_str()).map(String::from).unwrap_or_default();
+    items.sort_by_cached_key(|(_, item)| std::cmp::Reverse(date(item)));
+    items.truncate(limit);
+
+    Ok(items
+        .into_iter()
+        .map(|(kind, item)| {
+            let output = OutputItem::from_kodi(kind, &item, 1.0, original);
+            (kind, output)
+        })
+        .collect())
+}
+
+/// `params` of a listing call with the kind's properties plus `sort_key`, capped at `limit`.
+/// Kodi rejects duplicate properties, so `sort_key` is only added when missing.
+fn listing_params(kind: MediaKind, mut params: Value, sort_key: &str, limit: usize) -> Value {
+    let mut properties = kind.properties();
+    if let Some(properties) = properties.as_array_mut() {
+        if !properties.iter().any(|p| p == sort_key) {
+            properties.push(json!(sort_key));
+        }
+    }
+    params["properties"] = properties;
+    params["limits"] = json!({ "end": limit });
+    params
+}
+
+/// Movies and episodes added last, newest first, for "what's new"
+#[instrument(skip_all, fields(room, request_id))]
+async fn recent_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let voice = voice_mode(&query)?;
+    let limit = limit(&query)?;
+
+    let calls = [
+        (MediaKind::Movies, "VideoLibrary.GetRecentlyAddedMovies", json!({})),
+        (MediaKind::Episodes, "VideoLibrary.GetRecentlyAddedEpisodes", json!({})),
+    ];
+    let items = video_listing(kodi, &library, calls, "dateadded", limit, original_titles(&query)?).await?;
+    let out = mixed_response(&sessions, kodi, &req, &query, items, voice.then_some(("new video", "new videos")), "").await;
+
+    info!(items = out["results"].as_array().map_or(0, |r| r.len()), "Recent");
+    debug!(response = %out, "Recent response");
+
+    Ok(HttpResponse::Ok().json(out))
+}
+
+/// Movies and episodes stopped partway through, last watched first, for "continue watching"
+#[instrument(skip_all, fields(room, request_id))]
+async fn inprogress_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let voice = voice_mode(&query)?;
+    let limit = limit(&query)?;
+
+    // Sorted by Kodi, or the limit would cut the in-progress items in database order
+    let in_progress = json!({
+        "filter": { "field": "inprogress", "operator": "true", "value": "" },
+        "sort": { "method": "lastplayed", "order": "descending" },
+    });
+    let calls = [
+        (MediaKind::Movies, "VideoLibrary.GetMovies", in_progress.clone()),
+        (MediaKind::Episodes, "VideoLibrary.GetEpisodes", in_progress),
+    ];
+    let items = video_listing(kodi, &library, calls, "lastplayed", limit, original_titles(&query)?).await?;
+    let out = mixed_response(&sessions, kodi, &req, &query, items, voice.then_some(("unfinished video", "unfinished videos")), "").await;
+
+    info!(items = out["results"].as_array().map_or(0, |r| r.len()), "In progress");
+    debug!(response = %out, "In progress response");
+
+    Ok(HttpResponse::Ok().json(out))
+}
+
+async fn albums_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    search(MediaKind::Albums, instances, library, sessions, req, query).await
+}
+
+async fn artists_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    search(MediaKind::Artists, instances, library, sessions, req, query).await
+}
+
+async fn songs_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> impl Responder {
+    search(MediaKind::Songs, instances, library, sessions, req, query).await
+}
+
+/// Kind of the item named by `?type=movie|episode|song|album|artist`
+fn item_kind(query: &HashMap<String, String>) -> Result<MediaKind, KodiError> {
+    match query.get("type").map(String::as_str) {
+        Some("movie") => Ok(MediaKind::Movies),
+        Some("episode") => Ok(MediaKind::Episodes),
+        Some("song") => Ok(MediaKind::Songs),
+        Some("album") => Ok(MediaKind::Albums),
+        Some("artist") => Ok(MediaKind::Artists),
+        other => Err(KodiError::Validation(format!(
+            "Invalid type '{}', expected movie, episode, song, album or artist",
+            other.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Kodi playlist named by `?playlist=music|video`
+fn playlist_id(query: &HashMap<String, String>) -> Result<u8, KodiError> {
+    match query.get("playlist").map(String::as_str) {
+        Some("music") => Ok(MUSIC_PLAYLIST),
+        Some("video") => Ok(VIDEO_PLAYLIST),
+        other => Err(KodiError::Validation(format!(
+            "Invalid playlist '{}', expected music or video",
+            other.unwrap_or_default()
+        ))),
+    }
+}
+
+/// Start playing `?type=movie|episode|song|album|artist` `?id=` on the room's Kodi
+#[instrument(skip_all, fields(room, request_id))]
+async fn play_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let kind = item_kind(&query)?;
+    let id: i64 = query
+        .get("id")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| KodiError::Validation("Missing or invalid id".to_string()))?;
+
+    info!(item = kind.id_key(), id, "Play");
+
+    let params = json!({ "item": { kind.id_key(): id } });
+    kodi.call(library.client(), "Player.Open", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+#[derive(Deserialize)]
+struct SelectRequest {
+    number: usize,
+}
+
+/// Play the item `{"number": 2}` of the session's last search, on the room it searched
+#[instrument(skip_all, fields(room, request_id))]
+async fn select_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    sessions: web::Data<Sessions>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    body: web::Json<SelectRequest>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let session = session_id(&req, &query)
+        .ok_or_else(|| KodiError::Validation("Missing X-Session-Id header or ?session=".to_string()))?;
+    let selection = sessions
+        .get(&session)
+        .await
+        .ok_or_else(|| KodiError::NotFound(format!("No recent search in session '{}'", session)))?;
+    let (kind, id) = *selection
+        .items
+        .get(&body.number)
+        .ok_or_else(|| KodiError::NotFound(format!("The last search has no number {}", body.number)))?;
+    if kind == MediaKind::TvShows {
+        return Err(KodiError::Validation("TV shows can't be played directly, pick an episode".to_string()));
+    }
+    let kodi = instances.get(Some(selection.room.as_str()))?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+
+    info!(number = body.number, item = kind.id_key(), id, "Select");
+
+    let params = json!({ "item": { kind.id_key(): id } });
+    kodi.call(library.client(), "Player.Open", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK", "type": kind.item_name(), "id": id })))
+}
+
+/// Play the movie from the position it was stopped at
+#[instrument(skip_all, fields(room, request_id))]
+async fn resume_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let id: i64 = req
+        .match_info()
+        .get("movieid")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| KodiError::Validation("Invalid movie id".to_string()))?;
+
+    info!(movieid = id, "Resume");
+
+    let params = json!({ "item": { "movieid": id }, "options": { "resume": true } });
+    kodi.call(library.client(), "Player.Open", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+/// Append `?type=` items `?id=1,2,3` to the end of their playlist, in that order
+#[instrument(skip_all, fields(room, request_id))]
+async fn queue_add_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let kind = item_kind(&query)?;
+    let ids = query
+        .get("id")
+        .map(|v| v.split(',').map(|id| id.trim().parse::<i64>()).collect::<Result<Vec<_>, _>>())
+        .and_then(Result::ok)
+        .filter(|ids| !ids.is_empty())
+        .ok_or_else(|| KodiError::Validation("Missing or invalid id".to_string()))?;
+
+    info!(item = kind.id_key(), ids = ?ids, "Queue");
+
+    let items: Vec<Value> = ids.iter().map(|id| json!({ kind.id_key(): id })).collect();
+    let params = json!({ "playlistid": kind.playlist_id(), "item": items });
+    kodi.call(library.client(), "Playlist.Add", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK", "queued": ids.len() })))
+}
+
+/// The items queued on `?playlist=music|video`, in play order
+#[instrument(skip_all, fields(room, request_id))]
+async fn queue_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let playlist = playlist_id(&query)?;
+
+    let params = json!({ "playlistid": playlist, "properties": ["title", "artist", "year"] });
+    let result = kodi.call(library.client(), "Playlist.GetItems", params).await?;
+    let items: Vec<Value> = result
+        .get("items")
+        .and_then(|i| i.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .enumerate()
+                .map(|(position, item)| {
+                    json!({
+                        "position": position + 1,
+                        "type": item.get("type"),
+                        "id": item.get("id"),
+                        "title": item.get("title").or_else(|| item.get("label")),
+                        "artist": item.get("artist"),
+                        "year": item.get("year"),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    info!(playlist, items = items.len(), "Queue listed");
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK", "items": items })))
+}
+
+/// Remove every item queued on `?playlist=music|video`
+#[instrument(skip_all, fields(room, request_id))]
+async fn queue_clear_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let playlist = playlist_id(&query)?;
+
+    info!(playlist, "Clearing queue");
+
+    kodi.call(library.client(), "Playlist.Clear", json!({ "playlistid": playlist })).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+/// Poster, fanart or thumbnail `?path=image://...` of the library, fetched through Kodi's
+/// WebServer so clients don't need access to it
+#[instrument(skip_all, fields(room, request_id))]
+async fn art_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    // Files.PrepareDownload serves any file Kodi can read, keep it to artwork
+    let path = query
+        .get("path")
+        .filter(|p| p.starts_with("image://"))
+        .ok_or_else(|| KodiError::Validation("Missing or invalid path, expected image://...".to_string()))?;
+
+    let result = kodi.call(library.client(), "Files.PrepareDownload", json!({ "path": path })).await?;
+    let download = result
+        .get("details")
+        .and_then(|d| d.get("path"))
+        .and_then(|p| p.as_str())
+        .ok_or_else(|| KodiError::Parse { room: kodi.name.clone(), message: "PrepareDownload returned no path".to_string() })?;
+
+    debug!(path = %path, download = %download, "Art");
+
+    let resp = kodi.download(library.client(), download).await?;
+    let content_type = resp
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(("Cache-Control", "max-age=86400"))
+        .streaming(resp.bytes_stream()))
+}
+
+/// Start `VideoLibrary.Scan`, of `?directory=` only when given. Kodi scans in the background,
+/// `GET /library/status` tells when it is done.
+#[instrument(skip_all, fields(room, request_id))]
+async fn library_scan_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    library_job(&instances, &library, &req, &query, "VideoLibrary.Scan", json!({})).await
+}
+
+/// Start `VideoLibrary.Clean`, removing the items whose files are gone, of `?directory=` only
+/// when given
+#[instrument(skip_all, fields(room, request_id))]
+async fn library_clean_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    // Kodi shows a progress dialog on the TV by default
+    library_job(&instances, &library, &req, &query, "VideoLibrary.Clean", json!({ "showdialogs": false })).await
+}
+
+async fn library_job(
+    instances: &Instances,
+    library: &Library,
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+    method: &str,
+    mut params: Value,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(req);
+    let kodi = select_kodi(instances, req, query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    if let Some(directory) = query.get("directory").filter(|d| !d.is_empty()) {
+        params["directory"] = json!(directory);
+    }
+
+    info!(method, params = %params, "Library job");
+
+    kodi.call(library.client(), method, params).await?;
+    Ok(HttpResponse::Accepted().json(json!({ "state": "STARTED" })))
+}
+
+/// Whether Kodi is still scanning or cleaning its libraries
+#[instrument(skip_all, fields(room, request_id))]
+async fn library_status_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+
+    let params = json!({ "booleans": ["Library.IsScanningVideo", "Library.IsScanningMusic"] });
+    let result = kodi.call(library.client(), "XBMC.GetInfoBooleans", params).await?;
+    let flag = |name: &str| result.get(name).and_then(|v| v.as_bool()).unwrap_or(false);
+    let (video, music) = (flag("Library.IsScanningVideo"), flag("Library.IsScanningMusic"));
+
+    Ok(HttpResponse::Ok().json(json!({
+        "state": if video || music { "RUNNING" } else { "IDLE" },
+        "video": video,
+        "music": music,
+    })))
+}
+
+/// Id of the active player, of the one of `kind` ("video", "audio") when given
+async fn active_player(kodi: &Kodi, library: &Library, kind: Option<&str>) -> Result<i64, KodiError> {
+    let players = kodi.call(library.client(), "Player.GetActivePlayers", json!({})).await?;
+    players
+        .as_array()
+        .and_then(|players| {
+            players
+                .iter()
+                .find(|p| kind.is_none() || p.get("type").and_then(|t| t.as_str()) == kind)
+        })
+        .and_then(|p| p.get("playerid"))
+        .and_then(|id| id.as_i64())
+        .ok_or_else(|| KodiError::NotFound(format!("Nothing is playing on Kodi {}", kodi.name)))
+}
+
+/// Toggle pause of whatever is playing
+#[instrument(skip_all, fields(room, request_id))]
+async fn player_playpause_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let player = active_player(kodi, &library, None).await?;
+
+    info!("Play/pause");
+
+    let result = kodi.call(library.client(), "Player.PlayPause", json!({ "playerid": player })).await?;
+    let paused = result.get("speed").and_then(|s| s.as_i64()) == Some(0);
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK", "paused": paused })))
+}
+
+#[instrument(skip_all, fields(room, request_id))]
+async fn player_stop_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let player = active_player(kodi, &library, None).await?;
+
+    info!("Stop");
+
+    kodi.call(library.client(), "Player.Stop", json!({ "playerid": player })).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+/// Jump `?by=` seconds (negative goes back), to `?to=` seconds from the start or to
+/// `?percent=` of the item
+#[instrument(skip_all, fields(room, request_id))]
+async fn player_seek_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let invalid = |param: &str, value: &str| KodiError::Validation(format!("Invalid {} '{}'", param, value));
+    let value = match (query.get("by"), query.get("to"), query.get("percent")) {
+        (Some(by), None, None) => json!({ "seconds": by.parse::<i64>().map_err(|_| invalid("by", by))? }),
+        (None, Some(to), None) => {
+            let to: u64 = to.parse().map_err(|_| invalid("to", to))?;
+            json!({ "time": { "hours": to / 3600, "minutes": to / 60 % 60, "seconds": to % 60, "milliseconds": 0 } })
+        }
+        (None, None, Some(percent)) => {
+            let percent = percent.parse::<f64>().ok().filter(|p| (0.0..=100.0).contains(p)).ok_or_else(|| invalid("percent", percent))?;
+            json!({ "percentage": percent })
+        }
+        _ => return Err(KodiError::Validation("Expected exactly one of by, to or percent".to_string())),
+    };
+    let player = active_player(kodi, &library, None).await?;
+
+    info!(value = %value, "Seek");
+
+    let result = kodi.call(library.client(), "Player.Seek", json!({ "playerid": player, "value": value })).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "state": "OK",
+        "time": result.get("time"),
+        "totaltime": result.get("totaltime"),
+    })))
+}
+
+/// Set the volume: `?level=0-100`, `up` or `down`, and `?mute=true|false`
+#[instrument(skip_all, fields(room, request_id))]
+async fn player_volume_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let level = match query.get("level").map(String::as_str) {
+        None => None,
+        Some("up") => Some(json!("increment")),
+        Some("down") => Some(json!("decrement")),
+        Some(level) => Some(
+            level
+                .parse::<u8>()
+                .ok()
+                .filter(|l| *l <= 100)
+                .map(|l| json!(l))
+                .ok_or_else(|| KodiError::Validation(format!("Invalid level '{}', expected 0-100, up or down", level)))?,
+        ),
+    };
+    let mute = match query.get("mute").map(String::as_str) {
+        None => None,
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        Some(other) => return Err(KodiError::Validation(format!("Invalid mute '{}', expected true or false", other))),
+    };
+    if level.is_none() && mute.is_none() {
+        return Err(KodiError::Validation("Expected level or mute".to_string()));
+    }
+
+    info!(level = ?level, mute = ?mute, "Volume");
+
+    let mut out = json!({ "state": "OK" });
+    if let Some(level) = level {
+        out["volume"] = kodi.call(library.client(), "Application.SetVolume", json!({ "volume": level })).await?;
+    }
+    if let Some(mute) = mute {
+        out["muted"] = kodi.call(library.client(), "Application.SetMute", json!({ "mute": mute })).await?;
+    }
+    Ok(HttpResponse::Ok().json(out))
+}
+
+/// `next`, `previous`, the extra keywords or a stream index, as `Player.SetAudioStream`
+/// and `Player.SetSubtitle` take them
+fn stream_param(value: Option<&String>, keywords: &[&str]) -> Result<Value, KodiError> {
+    match value.map(String::as_str) {
+        Some(v) if keywords.contains(&v) => Ok(json!(v)),
+        Some(v) => v
+            .parse::<u32>()
+            .map(|index| json!(index))
+            .map_err(|_| KodiError::Validation(format!("Invalid stream '{}', expected {} or an index", v, keywords.join(", ")))),
+        None => Err(KodiError::Validation("Missing stream".to_string())),
+    }
+}
+
+/// Audio and subtitle streams of the playing video, with the selected ones
+#[instrument(skip_all, fields(room, request_id))]
+async fn streams_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let player = active_player(kodi, &library, Some("video")).await?;
+
+    let params = json!({
+        "playerid": player,
+        "properties": ["audiostreams", "currentaudiostream", "subtitles", "currentsubtitle", "subtitleenabled"],
+    });
+    let props = kodi.call(library.client(), "Player.GetProperties", params).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "state": "OK",
+        "audio": props.get("audiostreams"),
+        "current_audio": props.get("currentaudiostream"),
+        "subtitles": props.get("subtitles"),
+        "current_subtitle": props.get("currentsubtitle"),
+        "subtitles_enabled": props.get("subtitleenabled"),
+    })))
+}
+
+/// Switch the audio track: `?stream=next|previous|<index>`
+#[instrument(skip_all, fields(room, request_id))]
+async fn audio_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let stream = stream_param(query.get("stream"), &["next", "previous"])?;
+    let player = active_player(kodi, &library, Some("video")).await?;
+
+    info!(stream = %stream, "Audio stream");
+
+    let params = json!({ "playerid": player, "stream": stream });
+    kodi.call(library.client(), "Player.SetAudioStream", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+/// Turn subtitles on or off or switch them: `?stream=on|off|next|previous|<index>`
+#[instrument(skip_all, fields(room, request_id))]
+async fn subtitles_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let kodi = select_kodi(&instances, &req, &query)?;
+    tracing::Span::current().record("room", kodi.name.as_str());
+    let subtitle = stream_param(query.get("stream"), &["on", "off", "next", "previous"])?;
+    let player = active_player(kodi, &library, Some("video")).await?;
+
+    info!(subtitle = %subtitle, "Subtitles");
+
+    let mut params = json!({ "playerid": player, "subtitle": subtitle });
+    // Picking a track should also show it
+    if subtitle != "off" && subtitle != "on" {
+        params["enable"] = json!(true);
+    }
+    kodi.call(library.client(), "Player.SetSubtitle", params).await?;
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+/// Stream the Kodi notifications (`Player.OnPlay`, `Player.OnStop`, ...) of the room, of
+/// every room without one, as Server-Sent Events
+#[instrument(skip_all, fields(request_id))]
+async fn events_endpoint(
+    instances: web::Data<Instances>,
+    events: web::Data<Events>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let room = match requested_room(&req, &query) {
+        Some(_) => Some(select_kodi(&instances, &req, &query)?.name.clone()),
+        None => None,
+    };
 
-    // Log response
-    println!("[RESPONSE] {}", serde_json::to_string_pretty(&out).unwrap());
+    info!(room = room.as_deref().unwrap_or("all"), "Streaming events");
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events.stream(room)))
+}
+
+/// Forget the cached library of the room, of every room without one
+#[instrument(skip_all, fields(request_id))]
+async fn refresh_endpoint(
+    instances: web::Data<Instances>,
+    library: web::Data<Library>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, KodiError> {
+    request_id::record(&req);
+    let room = match requested_room(&req, &query) {
+        Some(_) => Some(select_kodi(&instances, &req, &query)?.name.as_str()),
+        None => None,
+    };
 
+    info!(room = room.unwrap_or("all"), "Refreshing library cache");
+    library.invalidate(room);
+
+    Ok(HttpResponse::Ok().json(json!({ "state": "OK" })))
+}
+
+async fn health_endpoint(instances: web::Data<Instances>) -> impl Responder {
+    let health: serde_json::Map<String, Value> = instances
+        .all()
+        .iter()
+        .map(|k| (k.name.clone(), json!(k.health())))
+        .collect();
+    let healthy = instances.all().iter().all(|k| k.health().healthy);
+
+    let out = json!({ "state": if healthy { "OK" } else { "DEGRADED" }, "instances": health });
     HttpResponse::Ok().json(out)
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let port = env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+/// Library routes, each also under a `/{room}` prefix
+fn routes(cfg: &mut web::ServiceConfig) {
+    for prefix in ["", "/{room}"] {
+        cfg.route(&format!("{}/search", prefix), web::get().to(search_all_endpoint))
+            .route(&format!("{}/recent", prefix), web::get().to(recent_endpoint))
+            .route(&format!("{}/inprogress", prefix), web::get().to(inprogress_endpoint))
+            .route(&format!("{}/movies", prefix), web::get().to(movies_endpoint))
+            .route(&format!("{}/movies/{{movieid}}", prefix), web::get().to(movie_details_endpoint))
+            .route(&format!("{}/tvshows", prefix), web::get().to(tvshows_endpoint))
+            .route(&format!("{}/albums", prefix), web::get().to(albums_endpoint))
+            .route(&format!("{}/artists", prefix), web::get().to(artists_endpoint))
+            .route(&format!("{}/songs", prefix), web::get().to(songs_endpoint))
+            .route(&format!("{}/play", prefix), web::post().to(play_endpoint))
+            .route(&format!("{}/resume/{{movieid}}", prefix), web::post().to(resume_endpoint))
+            .route(&format!("{}/events", prefix), web::get().to(events_endpoint))
+            .route(&format!("{}/art", prefix), web::get().to(art_endpoint))
+            .route(&format!("{}/library/scan", prefix), web::post().to(library_scan_endpoint))
+            .route(&format!("{}/library/clean", prefix), web::post().to(library_clean_endpoint))
+            .route(&format!("{}/library/status", prefix), web::get().to(library_status_endpoint))
+            .route(&format!("{}/player/playpause", prefix), web::post().to(player_playpause_endpoint))
+            .route(&format!("{}/player/stop", prefix), web::post().to(player_stop_endpoint))
+            .route(&format!("{}/player/seek", prefix), web::post().to(player_seek_endpoint))
+            .route(&format!("{}/player/volume", prefix), web::post().to(player_volume_endpoint))
+            .route(&format!("{}/streams", prefix), web::get().to(streams_endpoint))
+            .route(&format!("{}/streams/audio", prefix), web::post().to(audio_endpoint))
+            .route(&format!("{}/streams/subtitles", prefix), web::post().to(subtitles_endpoint))
+            .service(
+                web::resource(format!("{}/queue", prefix))
+                    .route(web::get().to(queue_endpoint))
+                    .route(web::post().to(queue_add_endpoint))
+                    .route(web::delete().to(queue_clear_endpoint)),
+            );
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    // CONFIG_FILE and the *_FILE secrets, before the runtime's threads read the environment
+    layered_config::load(&["KODI_PASS", "API_KEY"]).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    actix_web::rt::System::new().block_on(run())
+}
+
+async fn run() -> std::io::Result<()> {
+    // RUST_LOG=debug also logs the full search responses
+    logging::init(LogFormat::Pretty);
+
+    let port = layered_config::get("PORT").unwrap_or_else(|| "8080".to_string());
+    let instances = Instances::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
 
-    println!("Kodi server running on http://0.0.0.0:{port}");
+    let rooms: Vec<&str> = instances.all().iter().map(|k| k.name.as_str()).collect();
+    info!(port = %port, rooms = ?rooms, "Kodi server running");
 
-    HttpServer::new(|| {
+    let instances = web::Data::new(instances);
+    let library = web::Data::new(Library::from_env());
+    let sessions = web::Data::new(Sessions::from_env());
+    let access = web::Data::new(Access::from_env());
+    if !access.requires_key() {
+        warn!("API_KEY is not set, every client on the network can search and play");
+    }
+    let events = web::Data::new(Events::spawn(instances.all()));
+    let server = HttpServer::new(move || {
         App::new()
-            .route("/movies", web::get().to(movies_endpoint))
+            .app_data(instances.clone())
+            .app_data(library.clone())
+            .app_data(sessions.clone())
+            .app_data(access.clone())
+            .wrap(from_fn(access::guard))
+            .wrap(from_fn(metrics::track))
+            .app_data(events.clone())
+            .wrap(request_id::middleware())
+            .wrap(Logger::default())
+            .route("/health", web::get().to(health_endpoint))
+            .configure(service_kit::configure)
+            .route("/refresh", web::post().to(refresh_endpoint))
+            .route("/select", web::post().to(select_endpoint))
+            .route("/{room}/refresh", web::post().to(refresh_endpoint))
+            .configure(routes)
     })
+    .disable_signals()
+    .shutdown_timeout(service_kit::DRAIN_TIMEOUT_SECONDS)
     .bind(format!("0.0.0.0:{port}"))?
-    .run()
-    .await
+    .run();
+    service_kit::run(server).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn filter_is_none_without_parameters() {
+        assert_eq!(build_filter(MediaKind::Movies, &query(&[])).unwrap(), None);
+        assert_eq!(build_filter(MediaKind::Movies, &query(&[("genre", "  ")])).unwrap(), None);
+    }
+
+    #[test]
+    fn single_filter_is_a_bare_rule() {
+        let filter = build_filter(MediaKind::Movies, &query(&[("year", "1979")])).unwrap();
+        assert_eq!(filter, Some(json!({ "field": "year", "operator": "is", "value": "1979" })));
+    }
+
+    #[test]
+    fn several_filters_are_combined() {
+        let filter = build_filter(MediaKind::Movies, &query(&[("genre", "Horror"), ("actor", "Weaver"), ("unwatched", "true")])).unwrap();
+        assert_eq!(filter, Some(json!({ "and": [
+            { "field": "genre", "operator": "contains", "value": "Horror" },
+            { "field": "actor", "operator": "contains", "value": "Weaver" },
+            { "field": "playcount", "operator": "is", "value": "0" },
+        ] })));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for (kind, pairs) in [
+            (MediaKind::Movies, [("year", "nineteen")]),
+            (MediaKind::Episodes, [("genre", "Drama")]),
+            (MediaKind::Artists, [("year", "1999")]),
+            (MediaKind::Songs, [("unwatched", "1")]),
+        ] {
+            let err = build_filter(kind, &query(&pairs)).unwrap_err();
+            assert!(matches!(err, KodiError::Validation(_)), "{:?}", pairs);
+        }
+    }
+
+    fn count(properties: &Value, name: &str) -> usize {
+        properties.as_array().unwrap().iter().filter(|p| *p == name).count()
+    }
+
+    #[test]
+    fn listing_params_add_sort_key_once() {
+        let params = listing_params(MediaKind::Movies, json!({}), "lastplayed", 5);
+        assert_eq!(count(&params["properties"], "lastplayed"), 1);
+        assert_eq!(params["limits"], json!({ "end": 5 }));
+
+        let params = listing_params(MediaKind::Episodes, json!({}), "dateadded", 5);
+        assert_eq!(count(&params["properties"], "dateadded"), 1);
+    }
+
+    #[test]
+    fn listing_params_keep_the_requested_sort() {
+        let sort = json!({ "method": "lastplayed", "order": "descending" });
+        let params = listing_params(MediaKind::Episodes, json!({ "sort": sort.clone() }), "lastplayed", 10);
+        assert_eq!(params["sort"], sort);
+        assert_eq!(count(&params["properties"], "lastplayed"), 1);
+    }
 }
@@ -0,0 +1,36 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::Error;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+
+lazy_static::lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "kodi_ask_http_requests_total", "HTTP requests by route and status", &["endpoint", "status"]
+    ).unwrap();
+    pub static ref KODI_RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "kodi_ask_kodi_rpc_duration_seconds", "Latency of Kodi JSON-RPC calls", &["room", "method"]
+    ).unwrap();
+    pub static ref KODI_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "kodi_ask_kodi_errors_total", "Failed Kodi JSON-RPC calls by error", &["room", "error"]
+    ).unwrap();
+    pub static ref LIBRARY_CACHE: IntCounterVec = register_int_counter_vec!(
+        "kodi_ask_library_cache_requests_total", "Library listings served from the cache (hit) or loaded from Kodi (miss)", &["result"]
+    ).unwrap();
+}
+
+/// Middleware counting the requests by route pattern, so ids in paths don't explode the labels
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let endpoint = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let result = next.call(req).await;
+
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    HTTP_REQUESTS.with_label_values(&[endpoint.as_str(), status.as_str()]).inc();
+    result
+}
@@ -0,0 +1,44 @@
+use moka::future::Cache;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::library::MediaKind;
+
+const DEFAULT_SESSION_TTL_SECONDS: u64 = 600;
+
+/// The numbered matches of a session's last search, so the dialogue can go on with
+/// "number two" instead of an id.
+#[derive(Debug)]
+pub struct Selection {
+    /// Room the search ran against, the one the item gets played on
+    pub room: String,
+    pub items: HashMap<usize, (MediaKind, i64)>,
+}
+
+pub struct Sessions {
+    // Key: session id from the X-Session-Id header or ?session=
+    cache: Cache<String, Arc<Selection>>,
+}
+
+impl Sessions {
+    /// Selections are forgotten `SESSION_TTL_SECONDS` after the search
+    pub fn from_env() -> Self {
+        let ttl = layered_config::parse_or("SESSION_TTL_SECONDS", DEFAULT_SESSION_TTL_SECONDS);
+
+        Self {
+            cache: Cache::builder()
+                .time_to_live(Duration::from_secs(ttl))
+                .max_capacity(10_000)
+                .build(),
+        }
+    }
+
+    pub async fn remember(&self, session: String, selection: Selection) {
+        self.cache.insert(session, Arc::new(selection)).await;
+    }
+
+    pub async fn get(&self, session: &str) -> Option<Arc<Selection>> {
+        self.cache.get(session).await
+    }
+}
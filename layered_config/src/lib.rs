@@ -0,0 +1,211 @@
+//! Settings from several layers, highest first:
+//!
+//! 1. CLI flags, for binaries parsing them with clap `env = ...` arguments
+//! 2. environment variables
+//! 3. `NAME_FILE` environment variables pointing at a file holding the value, for the
+//!    secrets the binary names (other variables may legitimately end in `_FILE`)
+//! 4. the `KEY=VALUE` file named by `CONFIG_FILE`, where secrets may use `NAME_FILE` too
+//!
+//! [`load`] folds layers 3 and 4 into the environment, so clap and the getters below see
+//! one merged view. Call it first thing in `main`, before any thread reads the environment.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+const CONFIG_FILE_VAR: &str = "CONFIG_FILE";
+const FILE_SUFFIX: &str = "_FILE";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {path}: {source}")]
+    Read { path: String, source: std::io::Error },
+
+    #[error("Invalid line {line} in {path}, expected KEY=VALUE")]
+    Syntax { path: String, line: usize },
+
+    #[error("{0} is not set")]
+    Missing(String),
+
+    #[error("Invalid {name} '{value}'")]
+    Invalid { name: String, value: String },
+}
+
+/// Merge the `CONFIG_FILE` (when set) and the `*_FILE` variants of `secrets` into the
+/// environment
+pub fn load(secrets: &[&str]) -> Result<(), ConfigError> {
+    let file = match env::var(CONFIG_FILE_VAR) {
+        Ok(path) if !path.is_empty() => parse_file(Path::new(&path))?,
+        _ => BTreeMap::new(),
+    };
+    apply(env::vars().collect(), file, secrets)
+}
+
+/// [`load`] falling back to a file of the binary's choosing, skipped when missing (e.g. `.env`)
+pub fn load_or(default_file: impl AsRef<Path>, secrets: &[&str]) -> Result<(), ConfigError> {
+    let path = env::var(CONFIG_FILE_VAR)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .map(Into::into)
+        .unwrap_or_else(|| default_file.as_ref().to_path_buf());
+    let file = if path.exists() { parse_file(&path)? } else { BTreeMap::new() };
+    apply(env::vars().collect(), file, secrets)
+}
+
+fn apply(
+    environment: BTreeMap<String, String>,
+    file: BTreeMap<String, String>,
+    secrets: &[&str],
+) -> Result<(), ConfigError> {
+    for (name, value) in resolve(&environment, &file, secrets)? {
+        if !environment.contains_key(&name) {
+            env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// Every setting of the layers with its winning value
+fn resolve(
+    environment: &BTreeMap<String, String>,
+    file: &BTreeMap<String, String>,
+    secrets: &[&str],
+) -> Result<BTreeMap<String, String>, ConfigError> {
+    let names: BTreeSet<&str> = environment.keys().chain(file.keys()).map(String::as_str).chain(secrets.iter().copied()).collect();
+
+    let mut resolved = BTreeMap::new();
+    for name in names {
+        let secret = secrets.contains(&name).then(|| format!("{}{}", name, FILE_SUFFIX));
+        let secret = secret.as_ref();
+        let value = match (
+            environment.get(name),
+            secret.and_then(|s| environment.get(s)),
+            file.get(name),
+            secret.and_then(|s| file.get(s)),
+        ) {
+            (Some(value), ..) => value.clone(),
+            (None, Some(path), ..) => read_secret(path)?,
+            (None, None, Some(value), _) => value.clone(),
+            (None, None, None, Some(path)) => read_secret(path)?,
+            (None, None, None, None) => continue,
+        };
+        resolved.insert(name.to_string(), value);
+    }
+    Ok(resolved)
+}
+
+// Mounted secrets usually end in a newline that isn't part of the value
+fn read_secret(path: &str) -> Result<String, ConfigError> {
+    fs::read_to_string(path)
+        .map(|s| s.trim_end_matches(['\r', '\n']).to_string())
+        .map_err(|source| ConfigError::Read { path: path.to_string(), source })
+}
+
+/// `KEY=VALUE` lines, `#` comments, optional `export ` and quotes, like a `.env` file
+fn parse_file(path: &Path) -> Result<BTreeMap<String, String>, ConfigError> {
+    let content = fs::read_to_string(path)
+        .map_err(|source| ConfigError::Read { path: path.display().to_string(), source })?;
+    parse(&content).map_err(|line| ConfigError::Syntax { path: path.display().to_string(), line })
+}
+
+fn parse(content: &str) -> Result<BTreeMap<String, String>, usize> {
+    let mut values = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line.split_once('=').ok_or(i + 1)?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(i + 1);
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|q| value.strip_prefix(*q).and_then(|v| v.strip_suffix(*q)))
+            .unwrap_or(value);
+        values.insert(key.to_string(), value.to_string());
+    }
+    Ok(values)
+}
+
+/// The value of `name`, None when unset or empty
+pub fn get(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+pub fn required(name: &str) -> Result<String, ConfigError> {
+    get(name).ok_or_else(|| ConfigError::Missing(name.to_string()))
+}
+
+/// `name` parsed, an error when set to something unparsable
+pub fn parse_var<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    get(name)
+        .map(|value| value.parse().map_err(|_| ConfigError::Invalid { name: name.to_string(), value }))
+        .transpose()
+}
+
+/// `name` parsed, `default` when unset or unparsable
+pub fn parse_or<T: FromStr>(name: &str, default: T) -> T {
+    get(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// `true` or `1`
+pub fn flag(name: &str) -> bool {
+    matches!(get(name).as_deref(), Some("true" | "1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn parses_env_style_files() {
+        let parsed = parse("# comment\nA=1\nexport B = \"two words\"\n\nC='x=y'\n").unwrap();
+        assert_eq!(parsed, map(&[("A", "1"), ("B", "two words"), ("C", "x=y")]));
+        assert_eq!(parse("A=1\nnot a pair\n"), Err(2));
+    }
+
+    #[test]
+    fn layers_resolve_by_precedence() {
+        let dir = env::temp_dir().join(format!("layered_config_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("secret");
+        fs::write(&secret, "from-secret\n").unwrap();
+        let secret = secret.to_str().unwrap();
+
+        let environment = map(&[
+            ("ENV_WINS", "env"),
+            ("ENV_WINS_FILE", secret),
+            ("SECRET_WINS_FILE", secret),
+            ("VERSIONS_FILE", "/not/a/secret"),
+        ]);
+        let file = map(&[("ENV_WINS", "file"), ("SECRET_WINS", "file"), ("FILE_ONLY", "file"), ("FILE_SECRET_FILE", secret)]);
+        let secrets = ["ENV_WINS", "SECRET_WINS", "FILE_SECRET"];
+        let resolved = resolve(&environment, &file, &secrets).unwrap();
+
+        assert_eq!(resolved["ENV_WINS"], "env");
+        assert_eq!(resolved["SECRET_WINS"], "from-secret");
+        assert_eq!(resolved["FILE_ONLY"], "file");
+        assert_eq!(resolved["FILE_SECRET"], "from-secret");
+        // Not a declared secret, left as it is
+        assert_eq!(resolved["VERSIONS_FILE"], "/not/a/secret");
+        assert!(!resolved.contains_key("VERSIONS"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_secret_file_is_an_error() {
+        let environment = map(&[("TOKEN_FILE", "/nonexistent/token")]);
+        assert!(matches!(resolve(&environment, &BTreeMap::new(), &["TOKEN"]), Err(ConfigError::Read { .. })));
+    }
+}
@@ -0,0 +1,138 @@
+use dashmap::DashMap;
+use prometheus::{register_counter_vec, register_int_gauge_vec, CounterVec, IntGaugeVec};
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+lazy_static::lazy_static! {
+    static ref NOT_READY_TOO_LONG: IntGaugeVec = register_int_gauge_vec!(
+        "multus_node_not_ready_too_long", "1 while the node has been not ready for longer than ALERT_AFTER_SECONDS",
+        &["cluster", "node", "taint_key"]
+    ).unwrap();
+    static ref ALERTS_SENT: CounterVec = register_counter_vec!(
+        "multus_alerts_sent_total", "Webhook alerts posted", &["cluster", "status"]
+    ).unwrap();
+}
+
+struct Outage {
+    since: Instant,
+    firing: bool,
+}
+
+/// Raises an alert, as a metric and optionally a webhook POST, for nodes that stay
+/// not ready for too long, and resolves it once they recover.
+pub struct Alerter {
+    cluster: String,
+    after: Duration,
+    webhook: Option<String>,
+    http: reqwest::Client,
+    // Map: (NodeName, TaintKey) -> ongoing outage
+    outages: DashMap<(String, String), Outage>,
+}
+
+impl Alerter {
+    pub fn new(cluster: String, after: Duration, webhook: Option<String>) -> Self {
+        Self {
+            cluster,
+            after,
+            webhook,
+            http: reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap_or_default(),
+            outages: DashMap::new(),
+        }
+    }
+
+    /// Record the node's readiness for one rule, returns the time left before it alerts
+    pub fn observe(&self, node_name: &str, taint_key: &str, ready: bool) -> Option<Duration> {
+        let key = (node_name.to_string(), taint_key.to_string());
+
+        if ready {
+            if let Some((_, outage)) = self.outages.remove(&key) {
+                if outage.firing {
+                    self.set_gauge(&key, 0);
+                    self.send("resolved", &key, outage.since.elapsed());
+                }
+            }
+            return None;
+        }
+
+        let mut outage = self.outages.entry(key.clone()).or_insert_with(|| Outage { since: Instant::now(), firing: false });
+        let elapsed = outage.since.elapsed();
+        if outage.firing {
+            return None;
+        }
+        if elapsed < self.after {
+            return Some(self.after - elapsed);
+        }
+
+        outage.firing = true;
+        drop(outage);
+        tracing::warn!("🚨 Node {} not ready for {} since {:?}", node_name, taint_key, elapsed);
+        self.set_gauge(&key, 1);
+        self.send("firing", &key, elapsed);
+        None
+    }
+
+    /// Drop a node going away: its outages end without a resolved alert and its series
+    /// of `taint_keys` and of the ongoing outages are removed
+    pub fn forget(&self, node_name: &str, taint_keys: &HashSet<String>) {
+        let mut keys = taint_keys.clone();
+        self.outages.retain(|(node, key), _| {
+            if node != node_name {
+                return true;
+            }
+            keys.insert(key.clone());
+            false
+        });
+        for key in &keys {
+            // Not there when the node never alerted for this key
+            let _ = NOT_READY_TOO_LONG.remove_label_values(&[self.cluster.as_str(), node_name, key.as_str()]);
+        }
+    }
+
+    fn set_gauge(&self, (node, taint_key): &(String, String), value: i64) {
+        NOT_READY_TOO_LONG.with_label_values(&[self.cluster.as_str(), node.as_str(), taint_key.as_str()]).set(value);
+    }
+
+    /// POST in the background so a slow receiver doesn't hold up reconciles
+    fn send(&self, status: &str, (node, taint_key): &(String, String), not_ready_for: Duration) {
+        let Some(url) = self.webhook.clone() else {
+            return;
+        };
+        ALERTS_SENT.with_label_values(&[self.cluster.as_str(), status]).inc();
+
+        let payload = json!({
+            "status": status,
+            "cluster": self.cluster,
+            "node": node,
+            "taintKey": taint_key,
+            "notReadySeconds": not_ready_for.as_secs(),
+        });
+        let request = self.http.post(url).json(&payload);
+
+        tokio::spawn(async move {
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Failed to post alert: {}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgetting_a_node_removes_its_alert_series() {
+        let alerter = Alerter::new("test".to_string(), Duration::ZERO, None);
+        alerter.observe("n-gone", "CriticalAddonsOnly", false);
+        assert_eq!(NOT_READY_TOO_LONG.with_label_values(&["test", "n-gone", "CriticalAddonsOnly"]).get(), 1);
+
+        alerter.forget("n-gone", &HashSet::new());
+
+        assert!(NOT_READY_TOO_LONG.remove_label_values(&["test", "n-gone", "CriticalAddonsOnly"]).is_err());
+        assert!(alerter.outages.is_empty());
+    }
+}
@@ -0,0 +1,317 @@
+use std::{collections::HashSet, env, net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::state::{CrashLoopLimit, PodChecks, PodSource};
+
+const DEFAULT_SELECTOR: &str = "app.kubernetes.io/name=multus";
+const DEFAULT_TAINT_KEY: &str = "CriticalAddonsOnly";
+const DEFAULT_TAINT_EFFECT: &str = "NoSchedule";
+const DEFAULT_RESYNC_SECONDS: u64 = 300;
+const DEFAULT_ERROR_REQUEUE_SECONDS: u64 = 5;
+const DEFAULT_CONCURRENCY: u16 = 10;
+const DEFAULT_ERROR_BACKOFF_MAX_SECONDS: u64 = 300;
+const DEFAULT_HTTP_ADDR: &str = "0.0.0.0:8080";
+const DEFAULT_ALERT_AFTER_SECONDS: u64 = 600;
+const DEFAULT_LEASE_NAME: &str = "multus-controller-leader";
+const DEFAULT_LEASE_TTL_SECONDS: u64 = 15;
+const DEFAULT_LEASE_RENEW_SECONDS: u64 = 5;
+const DEFAULT_CRASHLOOP_RESTARTS: usize = 3;
+const DEFAULT_CRASHLOOP_WINDOW_SECONDS: u64 = 600;
+
+/// How node readiness is published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Keep a NoSchedule taint on nodes that are not ready
+    Taint,
+    /// Set the ready label to true/false, for scheduling via nodeAffinity
+    Label,
+    Both,
+    /// Cordon nodes that are not ready, uncordoning only nodes cordoned by the controller
+    Cordon,
+}
+
+impl Mode {
+    pub fn taints(self) -> bool {
+        matches!(self, Mode::Taint | Mode::Both)
+    }
+
+    pub fn labels(self) -> bool {
+        matches!(self, Mode::Label | Mode::Both)
+    }
+
+    pub fn cordons(self) -> bool {
+        self == Mode::Cordon
+    }
+}
+
+/// A CNI daemon that must be ready on a node before its taint is lifted.
+#[derive(Clone, Debug)]
+pub struct ReadinessRule {
+    /// The daemon's pods
+    pub source: PodSource,
+    /// Taint kept on nodes without a ready pod from `source`
+    pub taint_key: String,
+    /// NoSchedule, PreferNoSchedule or NoExecute
+    pub effect: String,
+}
+
+/// A cluster to manage and how to reach it.
+#[derive(Clone, Debug)]
+pub enum ClusterSource {
+    /// The in-cluster service account, or the current kubeconfig context
+    Default,
+    /// A context of the default kubeconfig
+    Context(String),
+    /// A mounted kubeconfig file, with its current context
+    Kubeconfig(PathBuf),
+}
+
+impl ClusterSource {
+    /// Value of the `cluster` metric label
+    pub fn name(&self) -> String {
+        match self {
+            ClusterSource::Default => "default".to_string(),
+            ClusterSource::Context(context) => context.clone(),
+            ClusterSource::Kubeconfig(path) => path.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        }
+    }
+}
+
+/// Certificate and key files for serving HTTPS
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+pub struct Config {
+    /// Every cluster gets its own controller and leader election
+    pub clusters: Vec<ClusterSource>,
+    pub namespace: String,
+    pub hostname: String,
+    /// Address of the metrics and health server
+    pub http_addr: SocketAddr,
+    /// Serve metrics and health over TLS
+    pub tls: Option<TlsFiles>,
+    pub lease_name: String,
+    /// How long the lease stays valid without renewal
+    pub lease_ttl: Duration,
+    /// How often the lease is acquired or renewed, must stay below `lease_ttl`
+    pub lease_renew: Duration,
+    pub rules: Vec<ReadinessRule>,
+    pub mode: Mode,
+    /// NetworkAttachmentDefinitions ("namespace/name") that must exist before nodes are marked ready
+    pub required_nads: Vec<String>,
+    /// Nodes not ready for longer than this raise an alert
+    pub alert_after: Duration,
+    /// Receives a JSON POST when an alert fires or resolves
+    pub alert_webhook: Option<String>,
+    /// Also publish readiness as the `MultusReady` node condition
+    pub node_condition: bool,
+    /// Allow NoExecute taints, which evict the pods not tolerating them from gated nodes.
+    /// Without it TAINT_EFFECT=NoExecute is refused and policies fall back to NoSchedule.
+    pub allow_no_execute: bool,
+    /// Serve the pod mutating webhook on `/mutate` (needs TLS)
+    pub admission_webhook: bool,
+    /// Also load rules from CniReadinessPolicy objects (the CRD must be installed)
+    pub watch_policies: bool,
+    /// On shutdown the leader removes the managed taints from every node, e.g. when uninstalling
+    pub cleanup_on_shutdown: bool,
+    /// Evaluate every node but only log and count the changes instead of patching
+    pub dry_run: bool,
+    /// When Ready CNI pods count: not while restarting often (CRASHLOOP_RESTARTS, 0 disables)
+    /// nor before running for MIN_POD_AGE_SECONDS
+    pub pod_checks: PodChecks,
+    /// How long a node must stay not-ready before it is tainted (environment rules)
+    pub not_ready_grace: Duration,
+    /// How long a node must stay ready before its taint is removed (environment rules)
+    pub ready_grace: Duration,
+    /// Every node is reconciled again after this long, even without events
+    pub resync_interval: Duration,
+    /// Delay before retrying a node whose reconcile failed, doubled on each further failure
+    pub error_requeue: Duration,
+    /// Upper bound of the per-node error backoff
+    pub error_backoff_max: Duration,
+    /// Nodes reconciled in parallel per cluster
+    pub concurrency: u16,
+    /// Average API requests per second issued by reconciles per cluster, 0 for no limit
+    pub api_qps: f64,
+    /// Requests allowed at once above `api_qps`
+    pub api_burst: u32,
+}
+
+impl Config {
+    /// Read the configuration from the environment.
+    ///
+    /// `CNI_RULES` lists `selector:taint-key` pairs separated by `;`, e.g.
+    /// `app.kubernetes.io/name=multus:CriticalAddonsOnly;app=whereabouts:example.com/whereabouts`.
+    /// A selector of the form `daemonset/<namespace>/<name>` counts the pods of that DaemonSet.
+    /// Without it a single rule is built from `MULTUS_DAEMONSET` (or `MULTUS_LABEL_SELECTOR`) and `TAINT_KEY`.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let effect = parse_effect(&env::var("TAINT_EFFECT").unwrap_or_else(|_| DEFAULT_TAINT_EFFECT.to_string()))?;
+        let allow_no_execute = env::var("ALLOW_NO_EXECUTE").map(|v| v == "true").unwrap_or(false);
+        if effect == "NoExecute" && !allow_no_execute {
+            return Err(anyhow::anyhow!("TAINT_EFFECT=NoExecute evicts pods from gated nodes, set ALLOW_NO_EXECUTE=true to confirm"));
+        }
+
+        let namespaces = list_var("MULTUS_NAMESPACES");
+        let rules = match env::var("CNI_RULES") {
+            Ok(spec) if !spec.trim().is_empty() => parse_rules(&spec, &effect, &namespaces)?,
+            _ => vec![ReadinessRule {
+                source: match env::var("MULTUS_DAEMONSET") {
+                    Ok(ds) if !ds.trim().is_empty() => PodSource::parse(&format!("daemonset/{}", ds.trim()))?,
+                    _ => PodSource::selector(&env::var("MULTUS_LABEL_SELECTOR").unwrap_or_else(|_| DEFAULT_SELECTOR.to_string()))
+                        .in_namespaces(&namespaces),
+                },
+                taint_key: env::var("TAINT_KEY").unwrap_or_else(|_| DEFAULT_TAINT_KEY.to_string()),
+                effect,
+            }],
+        };
+
+        let mut clusters: Vec<ClusterSource> = list_var("KUBE_CONTEXTS").into_iter()
+            .map(ClusterSource::Context)
+            .chain(list_var("KUBECONFIGS").into_iter().map(|p| ClusterSource::Kubeconfig(PathBuf::from(p))))
+            .collect();
+        if clusters.is_empty() {
+            clusters.push(ClusterSource::Default);
+        }
+        // The names label metrics, so they must tell clusters apart
+        let mut names = HashSet::new();
+        if let Some(dup) = clusters.iter().map(ClusterSource::name).find(|n| !names.insert(n.clone())) {
+            return Err(anyhow::anyhow!("Cluster name '{}' is used twice in KUBE_CONTEXTS/KUBECONFIGS", dup));
+        }
+
+        let lease_ttl = parse_seconds("LEASE_TTL_SECONDS", DEFAULT_LEASE_TTL_SECONDS)?;
+        let lease_renew = parse_seconds("LEASE_RENEW_SECONDS", DEFAULT_LEASE_RENEW_SECONDS)?;
+        if lease_renew.is_zero() || lease_renew >= lease_ttl {
+            return Err(anyhow::anyhow!("LEASE_RENEW_SECONDS must be positive and below LEASE_TTL_SECONDS"));
+        }
+
+        let http_addr = env::var("HTTP_ADDR").unwrap_or_else(|_| DEFAULT_HTTP_ADDR.to_string());
+        let tls = match (env::var("TLS_CERT_FILE"), env::var("TLS_KEY_FILE")) {
+            (Ok(cert), Ok(key)) => Some(TlsFiles { cert: cert.into(), key: key.into() }),
+            (Err(_), Err(_)) => None,
+            _ => return Err(anyhow::anyhow!("TLS_CERT_FILE and TLS_KEY_FILE must be set together")),
+        };
+        let admission_webhook = env::var("ADMISSION_WEBHOOK").map(|v| v == "true").unwrap_or(false);
+        check_admission_webhook(admission_webhook, tls.as_ref())?;
+
+        let crash_loop_restarts = parse_number("CRASHLOOP_RESTARTS", DEFAULT_CRASHLOOP_RESTARTS)?;
+        let crash_loop = (crash_loop_restarts > 0).then_some(CrashLoopLimit {
+            restarts: crash_loop_restarts,
+            window: parse_seconds("CRASHLOOP_WINDOW_SECONDS", DEFAULT_CRASHLOOP_WINDOW_SECONDS)?,
+        });
+
+        Ok(Self {
+            clusters,
+            http_addr: http_addr.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid HTTP_ADDR '{}', expected ip:port", http_addr))?,
+            tls,
+            namespace: env::var("NAMESPACE").unwrap_or_else(|_| "networking".to_string()),
+            hostname: env::var("HOSTNAME").unwrap_or_else(|_| "unknown-host".to_string()),
+            lease_name: env::var("LEASE_NAME").unwrap_or_else(|_| DEFAULT_LEASE_NAME.to_string()),
+            lease_ttl,
+            lease_renew,
+            rules,
+            required_nads: list_var("REQUIRED_NADS"),
+            alert_after: parse_seconds("ALERT_AFTER_SECONDS", DEFAULT_ALERT_AFTER_SECONDS)?,
+            alert_webhook: env::var("ALERT_WEBHOOK_URL").ok().filter(|u| !u.trim().is_empty()),
+            pod_checks: PodChecks {
+                crash_loop,
+                min_age: parse_seconds("MIN_POD_AGE_SECONDS", 0)?,
+            },
+            not_ready_grace: parse_seconds("NOT_READY_GRACE_SECONDS", 0)?,
+            ready_grace: parse_seconds("READY_GRACE_SECONDS", 0)?,
+            resync_interval: parse_seconds("RESYNC_INTERVAL_SECONDS", DEFAULT_RESYNC_SECONDS)?,
+            error_requeue: parse_seconds("ERROR_REQUEUE_SECONDS", DEFAULT_ERROR_REQUEUE_SECONDS)?,
+            error_backoff_max: parse_seconds("ERROR_BACKOFF_MAX_SECONDS", DEFAULT_ERROR_BACKOFF_MAX_SECONDS)?,
+            concurrency: parse_number("CONTROLLER_CONCURRENCY", DEFAULT_CONCURRENCY)?,
+            api_qps: parse_number("API_QPS", 0.0)?,
+            api_burst: parse_number("API_BURST", 10)?,
+            cleanup_on_shutdown: env::var("CLEANUP_ON_SHUTDOWN").map(|v| v == "true").unwrap_or(false),
+            dry_run: env::var("DRY_RUN").map(|v| v == "true").unwrap_or(false),
+            allow_no_execute,
+            admission_webhook,
+            node_condition: env::var("NODE_CONDITION").map(|v| v == "true").unwrap_or(false),
+            watch_policies: env::var("WATCH_POLICIES").map(|v| v == "true").unwrap_or(false),
+            mode: parse_mode(&env::var("MODE").unwrap_or_else(|_| "taint".to_string()))?,
+        })
+    }
+}
+
+/// The API server only calls admission webhooks over HTTPS
+fn check_admission_webhook(enabled: bool, tls: Option<&TlsFiles>) -> anyhow::Result<()> {
+    if enabled && tls.is_none() {
+        return Err(anyhow::anyhow!("ADMISSION_WEBHOOK=true needs TLS_CERT_FILE and TLS_KEY_FILE, the API server only calls webhooks over HTTPS"));
+    }
+    Ok(())
+}
+
+/// Comma separated list, empty when unset
+fn list_var(var: &str) -> Vec<String> {
+    env::var(var)
+        .map(|v| v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn parse_seconds(var: &str, default: u64) -> anyhow::Result<Duration> {
+    parse_number(var, default).map(Duration::from_secs)
+}
+
+fn parse_number<T: std::str::FromStr>(var: &str, default: T) -> anyhow::Result<T> {
+    match env::var(var) {
+        Ok(v) if !v.trim().is_empty() => v.trim().parse()
+            .map_err(|_| anyhow::anyhow!("Invalid {} '{}', expected a number", var, v)),
+        _ => Ok(default),
+    }
+}
+
+fn parse_mode(mode: &str) -> anyhow::Result<Mode> {
+    match mode.to_ascii_lowercase().as_str() {
+        "taint" => Ok(Mode::Taint),
+        "label" => Ok(Mode::Label),
+        "both" => Ok(Mode::Both),
+        "cordon" => Ok(Mode::Cordon),
+        other => Err(anyhow::anyhow!("Invalid MODE '{}', expected taint, label, both or cordon", other)),
+    }
+}
+
+fn parse_effect(effect: &str) -> anyhow::Result<String> {
+    match effect {
+        "NoSchedule" | "PreferNoSchedule" | "NoExecute" => Ok(effect.to_string()),
+        other => Err(anyhow::anyhow!("Invalid TAINT_EFFECT '{}', expected NoSchedule, PreferNoSchedule or NoExecute", other)),
+    }
+}
+
+fn parse_rules(spec: &str, effect: &str, namespaces: &[String]) -> anyhow::Result<Vec<ReadinessRule>> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|rule| {
+            // Taint keys never contain ':', label selectors don't either
+            let (selector, taint_key) = rule
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow::anyhow!("Invalid CNI_RULES entry '{}', expected selector:taint-key", rule))?;
+            Ok(ReadinessRule {
+                source: PodSource::parse(selector.trim())?.in_namespaces(namespaces),
+                taint_key: taint_key.trim().to_string(),
+                effect: effect.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admission_webhook_requires_tls() {
+        let tls = TlsFiles { cert: "tls.crt".into(), key: "tls.key".into() };
+
+        assert!(check_admission_webhook(true, None).is_err());
+        assert!(check_admission_webhook(true, Some(&tls)).is_ok());
+        assert!(check_admission_webhook(false, None).is_ok());
+    }
+}
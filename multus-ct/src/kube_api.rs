@@ -0,0 +1,145 @@
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::Node;
+use kube::api::{Api, Patch, PatchParams};
+use serde_json::Value;
+
+const FIELD_MANAGER: &str = "multus-ct";
+
+/// The Node operations the reconciler issues, so it can run against a fake in tests.
+#[async_trait]
+pub trait NodeApi: Send + Sync {
+    async fn get(&self, name: &str) -> Result<Node, kube::Error>;
+
+    /// Forced Server-Side Apply under our field manager, taking over fields owned by others.
+    /// A resourceVersion in the patch still makes it fail with a conflict when stale.
+    async fn apply(&self, name: &str, patch: Value) -> Result<Node, kube::Error>;
+
+    /// JSON merge patch of the node
+    async fn merge(&self, name: &str, patch: Value) -> Result<Node, kube::Error>;
+
+    /// Strategic merge patch of the node status
+    async fn patch_status(&self, name: &str, patch: Value) -> Result<Node, kube::Error>;
+}
+
+#[async_trait]
+impl NodeApi for Api<Node> {
+    async fn get(&self, name: &str) -> Result<Node, kube::Error> {
+        Api::get(self, name).await
+    }
+
+    async fn apply(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+        self.patch(name, &PatchParams::apply(FIELD_MANAGER).force(), &Patch::Apply(patch)).await
+    }
+
+    async fn merge(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+        self.patch(name, &PatchParams::default(), &Patch::Merge(patch)).await
+    }
+
+    async fn patch_status(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+        Api::patch_status(self, name, &PatchParams::default(), &Patch::Strategic(patch)).await
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::{
+        collections::HashMap,
+        sync::{atomic::{AtomicU32, Ordering}, Mutex},
+    };
+
+    use super::*;
+
+    /// In-memory nodes. Every kind of patch is applied as a JSON merge patch, which is
+    /// close enough for the fields the controller writes, and bumps the resourceVersion.
+    /// An apply carrying a different resourceVersion is rejected with a 409.
+    #[derive(Default)]
+    pub struct FakeNodeApi {
+        nodes: Mutex<HashMap<String, Node>>,
+        // Upcoming applies rejected with a 409
+        conflicts: AtomicU32,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl FakeNodeApi {
+        pub fn insert(&self, node: Node) {
+            let name = node.metadata.name.clone().expect("node without a name");
+            self.nodes.lock().unwrap().insert(name, node);
+        }
+
+        pub fn node(&self, name: &str) -> Node {
+            self.nodes.lock().unwrap()[name].clone()
+        }
+
+        /// Reject the next `n` applies, like a write landing between our read and the apply
+        pub fn conflict_next(&self, n: u32) {
+            self.conflicts.store(n, Ordering::SeqCst);
+        }
+
+        /// Requests received so far, e.g. `get n1` or `apply n1`
+        pub fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn patch(&self, name: &str, patch: &Value) -> Result<Node, kube::Error> {
+            let mut nodes = self.nodes.lock().unwrap();
+            let node = nodes.get_mut(name).ok_or_else(|| error(404, "NotFound", name))?;
+
+            let version: u64 = node.metadata.resource_version.as_deref()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            let mut doc = serde_json::to_value(&*node).unwrap();
+            json_patch::merge(&mut doc, patch);
+            *node = serde_json::from_value(doc).unwrap();
+            node.metadata.resource_version = Some((version + 1).to_string());
+            Ok(node.clone())
+        }
+    }
+
+    fn error(code: u16, reason: &str, name: &str) -> kube::Error {
+        kube::Error::Api(kube::error::ErrorResponse {
+            status: "Failure".to_string(),
+            message: format!("node {}: {}", name, reason),
+            reason: reason.to_string(),
+            code,
+        })
+    }
+
+    #[async_trait]
+    impl NodeApi for FakeNodeApi {
+        async fn get(&self, name: &str) -> Result<Node, kube::Error> {
+            self.record(format!("get {}", name));
+            self.nodes.lock().unwrap().get(name).cloned().ok_or_else(|| error(404, "NotFound", name))
+        }
+
+        async fn apply(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+            self.record(format!("apply {}", name));
+            if self.conflicts.load(Ordering::SeqCst) > 0 {
+                self.conflicts.fetch_sub(1, Ordering::SeqCst);
+                return Err(error(409, "Conflict", name));
+            }
+            let stale = match patch["metadata"]["resourceVersion"].as_str() {
+                Some(version) => self.nodes.lock().unwrap().get(name)
+                    .is_some_and(|n| n.metadata.resource_version.as_deref() != Some(version)),
+                None => false,
+            };
+            if stale {
+                return Err(error(409, "Conflict", name));
+            }
+            self.patch(name, &patch)
+        }
+
+        async fn merge(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+            self.record(format!("merge {}", name));
+            self.patch(name, &patch)
+        }
+
+        async fn patch_status(&self, name: &str, patch: Value) -> Result<Node, kube::Error> {
+            self.record(format!("status {}", name));
+            self.patch(name, &patch)
+        }
+    }
+}
@@ -1,60 +1,420 @@
 use futures::StreamExt;
-use k8s_openapi::api::core::v1::{Node, Pod};
+use k8s_openapi::{
+    api::core::v1::{Node, NodeCondition, Pod, Taint},
+    apimachinery::pkg::apis::meta::v1::Time,
+    chrono::Utc,
+};
 use kube::{
-    api::{Api, Patch, PatchParams},
+    api::{Api, ListParams},
     runtime::{
         controller::{Action, Controller},
-        reflector,
-        reflector::ObjectRef,
+        reflector::{ObjectRef, Store},
         watcher,
     },
-    Client, ResourceExt,
+    core::admission::AdmissionReview,
+    Client, CustomResourceExt, ResourceExt,
 };
 use kube_leader_election::{LeaseLock, LeaseLockParams};
-use prometheus::{register_counter, register_histogram, Counter, Histogram, Encoder, TextEncoder};
+use dashmap::DashMap;
+use prometheus::{
+    register_counter_vec, register_histogram_vec, register_int_gauge_vec,
+    CounterVec, HistogramVec, IntGaugeVec, Encoder, TextEncoder,
+};
 use serde_json::json;
 use std::{
-    env,
+    collections::{BTreeSet, HashSet},
+    net::SocketAddr,
     sync::{atomic::{AtomicBool, Ordering}, Arc},
     time::Duration,
 };
-use warp::Filter; // Required for .boxed()
+use anyhow::Context as _;
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use warp::{http::StatusCode, Filter}; // Filter is required for .boxed()
 
+mod alert;
+mod config;
+mod kube_api;
+mod limiter;
+mod nad;
+mod policy;
 mod state;
-use state::NodeIndex;
+mod webhook;
+use alert::Alerter;
+use config::{ClusterSource, Config, Mode, TlsFiles};
+use policy::{CniReadinessPolicy, GateRegistry};
+use kube_api::NodeApi;
+use limiter::RateLimiter;
+use nad::NadTracker;
+use state::Debouncer;
 
 // --- 1. CONSTANTS & METRICS ---
-const TAINT_KEY: &str = "CriticalAddonsOnly";
-const LEASE_NAME: &str = "multus-controller-leader";
+const READY_LABEL: &str = "multus.network.k8s.io/ready";
+// Marks nodes cordoned by the controller, so nodes cordoned by an admin stay cordoned
+const CORDON_ANNOTATION: &str = "multus.network.k8s.io/cordoned";
+// Comma separated taint keys the controller added, so taints of rules that no longer apply
+// to the node can be found again, even after a restart with a different configuration
+const MANAGED_TAINTS_ANNOTATION: &str = "multus.network.k8s.io/managed-taints";
+const CONDITION_TYPE: &str = "MultusReady";
+// Set to "true" on a node to leave it alone, e.g. while debugging it
+const SKIP_ANNOTATION: &str = "multus.network.k8s.io/skip-readiness-taint";
+
+// Each shutdown stage gets this long, staying well inside the pod's termination grace period
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
 lazy_static::lazy_static! {
-    static ref RECONCILE_DURATION: Histogram = register_histogram!(
-        "multus_reconcile_duration_seconds", "Duration of node reconciliation"
+    static ref RECONCILE_DURATION: HistogramVec = register_histogram_vec!(
+        "multus_reconcile_duration_seconds", "Duration of node reconciliation", &["cluster"]
+    ).unwrap();
+    static ref TAINT_OPERATIONS: CounterVec = register_counter_vec!(
+        "multus_taint_operations_total", "Total number of taint additions/removals", &["cluster"]
+    ).unwrap();
+    static ref LABEL_OPERATIONS: CounterVec = register_counter_vec!(
+        "multus_label_operations_total", "Total number of ready label updates", &["cluster"]
+    ).unwrap();
+    static ref CONDITION_UPDATES: CounterVec = register_counter_vec!(
+        "multus_condition_updates_total", "Total number of MultusReady condition updates", &["cluster"]
+    ).unwrap();
+    static ref CORDON_OPERATIONS: CounterVec = register_counter_vec!(
+        "multus_cordon_operations_total", "Total number of cordons/uncordons", &["cluster"]
+    ).unwrap();
+    static ref DRY_RUN_CHANGES: CounterVec = register_counter_vec!(
+        "multus_dry_run_changes_total", "Changes skipped because DRY_RUN is enabled", &["cluster", "action"]
+    ).unwrap();
+    static ref NODE_READY: IntGaugeVec = register_int_gauge_vec!(
+        "multus_node_ready", "1 when the node has a ready pod for the rule, 0 while it is gated", &["cluster", "node", "taint_key"]
     ).unwrap();
-    static ref TAINT_OPERATIONS: Counter = register_counter!(
-        "multus_taint_operations_total", "Total number of taint additions/removals"
+    static ref TAINT_CONFLICTS: CounterVec = register_counter_vec!(
+        "multus_taint_patch_conflicts_total", "Taint patches rejected with a conflict and retried", &["cluster"]
+    ).unwrap();
+    static ref TAINT_RETRIES_EXHAUSTED: CounterVec = register_counter_vec!(
+        "multus_taint_patch_retries_exhausted_total", "Taint updates abandoned after every retry conflicted", &["cluster"]
+    ).unwrap();
+    static ref TAINT_APPLY_ATTEMPTS: HistogramVec = register_histogram_vec!(
+        "multus_taint_apply_attempts", "Apply attempts a taint update needed, above 1 when other controllers interfere",
+        &["cluster"], vec![1.0, 2.0, 3.0, 4.0, 5.0]
+    ).unwrap();
+    static ref NODES_TAINTED: IntGaugeVec = register_int_gauge_vec!(
+        "multus_nodes_tainted", "Nodes currently carrying the taint", &["cluster", "taint_key"]
+    ).unwrap();
+    static ref RECONCILE_ERRORS: CounterVec = register_counter_vec!(
+        "multus_reconcile_errors_total", "Node reconciliations that failed", &["cluster"]
+    ).unwrap();
+    static ref IS_LEADER: IntGaugeVec = register_int_gauge_vec!(
+        "multus_leader", "1 while this replica holds the leader lease", &["cluster"]
     ).unwrap();
 }
 
 // --- 2. MAIN APPLICATION ---
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().init();
+    init_logging()?;
+
+    // `multus-ct crd | kubectl apply -f -` installs the CniReadinessPolicy CRD
+    if std::env::args().nth(1).as_deref() == Some("crd") {
+        println!("{}", serde_json::to_string_pretty(&CniReadinessPolicy::crd())?);
+        return Ok(());
+    }
 
     // A. Configuration
-    let namespace = env::var("NAMESPACE").unwrap_or_else(|_| "networking".to_string());
-    let selector = env::var("MULTUS_LABEL_SELECTOR").unwrap_or_else(|_| "app.kubernetes.io/name=multus".to_string());
-    let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "unknown-host".to_string());
-    let client = Client::try_default().await?;
-
-    // B. Metrics & Health Server
-    // -----------------------------------------------------------------------
-    // FIX: logic for Warp + Tokio
-    // 1. Define routes.
-    // 2. call .boxed() at the end. This is critical for tokio::spawn.
-    // -----------------------------------------------------------------------
-    let health_route = warp::path("health").map(|| "ok".to_string());
-    
+    let config = Config::from_env()?;
+    // A bad certificate or a taken port stops the process, rather than running without probes
+    let listener = bind_server(config.http_addr, config.tls.as_ref()).await?;
+
+    // Controllers stop first so no patch is cut off, leases are released after them
+    let (shutdown, shutting_down) = watch::channel(false);
+    let (stop, stopped) = watch::channel(false);
+    let mut clusters = Vec::new();
+    let mut controllers = Vec::new();
+    let mut leases = Vec::new();
+    for source in &config.clusters {
+        let name = source.name();
+        let client = connect(source).await?;
+
+        // B. Cache Setup: one pod index per readiness rule, from the environment and from policies
+        let gates = GateRegistry::new(Api::all(client.clone()), config.pod_checks, config.allow_no_execute);
+        gates.set_static(&config.rules, config.not_ready_grace, config.ready_grace);
+        if config.watch_policies {
+            policy::spawn_policy_watcher(client.clone(), gates.clone());
+        }
+        let nads = NadTracker::spawn(client.clone(), config.required_nads.clone(), gates.clone());
+
+        // C. Leader Election, one lease per cluster
+        let (is_leader, leadership, lease) = start_leader_election(client.clone(), &name, &config, stopped.clone());
+        leases.push(lease);
+
+        let cluster = Cluster { name, client, is_leader, leadership, gates, nads };
+        controllers.push(run_controller(cluster.clone(), &config, shutting_down.clone()));
+        clusters.push(cluster);
+    }
+
+    // D. Metrics & Health Server
+    let server = spawn_server(clusters.clone(), listener, config.admission_webhook, stopped.clone());
+
+    tracing::info!("🚀 Controller started. Watching Nodes & Pods for {} rule(s) in {} cluster(s)...",
+        config.rules.len(), config.clusters.len());
+
+    // E. Main Controller Loops
+    let controllers = futures::future::join_all(controllers);
+    tokio::pin!(controllers);
+    tokio::select! {
+        _ = &mut controllers => {},
+        _ = shutdown::signal() => {
+            tracing::info!("🛑 Shutdown signal received, finishing in-flight reconciles...");
+            let _ = shutdown.send(true);
+            shutdown::drain("Reconciles", controllers, DRAIN_TIMEOUT).await;
+        }
+    }
+
+    // Then the pod watches, and the leases: stepping down lets another replica take over
+    // now instead of after the lease TTL. The server goes last, answering probes until then.
+    for cluster in &clusters {
+        cluster.gates.stop();
+    }
+    tracing::info!("🛑 Releasing leases...");
+    let _ = stop.send(true);
+    shutdown::drain("Lease release", futures::future::join_all(leases), DRAIN_TIMEOUT).await;
+    let _ = server.await;
+
+    Ok(())
+}
+
+/// Connections and shared state of one managed cluster
+#[derive(Clone)]
+struct Cluster {
+    name: String,
+    client: Client,
+    is_leader: Arc<AtomicBool>,
+    // Follows is_leader, for waiting on changes
+    leadership: watch::Receiver<bool>,
+    gates: GateRegistry,
+    nads: NadTracker,
+}
+
+async fn connect(source: &ClusterSource) -> anyhow::Result<Client> {
+    let options = kube::config::KubeConfigOptions::default();
+    let kube_config = match source {
+        ClusterSource::Default => return Ok(Client::try_default().await?),
+        ClusterSource::Context(context) => kube::Config::from_kubeconfig(&kube::config::KubeConfigOptions {
+            context: Some(context.clone()),
+            ..options
+        }).await?,
+        ClusterSource::Kubeconfig(path) => {
+            let kubeconfig = kube::config::Kubeconfig::read_from(path)?;
+            kube::Config::from_custom_kubeconfig(kubeconfig, &options).await?
+        }
+    };
+    Ok(Client::try_from(kube_config)?)
+}
+
+/// Run the node controller of one cluster while this replica leads it, until `shutting_down`
+/// flips. Losing the lease pauses it: the running reconciles finish and the watches stop, so a
+/// follower holds no queue. Regaining it starts over from fresh watches, reconciling every node.
+async fn run_controller(
+    cluster: Cluster,
+    config: &Config,
+    mut shutting_down: watch::Receiver<bool>,
+) {
+    let client = cluster.client;
+    let mut leadership = cluster.leadership;
+    // Kept across leadership terms, so ongoing outages aren't raised twice
+    let alerter = Arc::new(Alerter::new(cluster.name.clone(), config.alert_after, config.alert_webhook.clone()));
+
+    loop {
+        tokio::select! {
+            leading = async { leadership.wait_for(|leader| *leader).await.is_ok() } => {
+                if !leading {
+                    return;
+                }
+            },
+            _ = shutting_down.wait_for(|stop| *stop) => return,
+        }
+        tracing::info!("▶️ Starting the controller of {}", cluster.name);
+
+        let pods_api = Api::<Pod>::all(client.clone());
+        let nodes_api = Api::<Node>::all(client.clone());
+        let mut controller = Controller::new(nodes_api, watcher::Config::default())
+            .with_config(kube::runtime::controller::Config::default().concurrency(config.concurrency));
+
+        for rule in &config.rules {
+            for (namespace, watch_config) in rule.source.watches() {
+                let source = rule.source.clone();
+                let rule_pods = match namespace {
+                    Some(ns) => Api::<Pod>::namespaced(client.clone(), ns),
+                    None => pods_api.clone(),
+                };
+                controller = controller.watches(
+                    rule_pods,
+                    watch_config,
+                    move |pod| {
+                        if !source.owns(&pod) {
+                            return None;
+                        }
+                        pod.spec.as_ref()
+                            .and_then(|s| s.node_name.clone())
+                            .map(|name| ObjectRef::<Node>::new(name.as_str()))
+                    },
+                );
+            }
+        }
+
+        let store = controller.store();
+        let ctx = Arc::new(Context {
+            alerter: alerter.clone(),
+            node_cache: store.clone(),
+            cluster: cluster.name.clone(),
+            nodes: Arc::new(Api::<Node>::all(client.clone())),
+            pods: Api::all(client.clone()),
+            is_leader: cluster.is_leader.clone(),
+            gates: cluster.gates.clone(),
+            nads: cluster.nads.clone(),
+            mode: config.mode,
+            dry_run: config.dry_run,
+            node_condition: config.node_condition,
+            resync_interval: config.resync_interval,
+            error_requeue: config.error_requeue,
+            error_backoff_max: config.error_backoff_max,
+            failures: DashMap::new(),
+            tainted: DashMap::new(),
+            limiter: (config.api_qps > 0.0).then(|| RateLimiter::new(config.api_qps, config.api_burst)),
+            debouncer: Debouncer::new(),
+        });
+
+        // Requests made while following are covered by the initial reconcile of every node
+        let triggers = cluster.gates.triggers();
+        triggers.clear();
+        let mut lost = leadership.clone();
+        let mut stop = shutting_down.clone();
+        controller
+            // Policy changes re-evaluate every node, readiness flips of an index only their node
+            .reconcile_all_on(triggers.all_requests())
+            .reconcile_on(triggers.node_requests())
+            .graceful_shutdown_on(async move {
+                tokio::select! {
+                    _ = lost.wait_for(|leader| !*leader) => {},
+                    _ = stop.wait_for(|stop| *stop) => {},
+                }
+            })
+            .run(reconcile, error_policy, ctx.clone())
+            .for_each(|_| async {})
+            .await;
+
+        if *shutting_down.borrow_and_update() {
+            if config.cleanup_on_shutdown && ctx.is_leader.load(Ordering::Relaxed) {
+                remove_managed_taints(&ctx, store.state()).await;
+            }
+            return;
+        }
+        tracing::info!("⏸️ Paused the controller of {}, no longer leading", cluster.name);
+    }
+}
+
+/// Strip the taints this controller manages from every node, for CLEANUP_ON_SHUTDOWN
+async fn remove_managed_taints(ctx: &Context, nodes: Vec<Arc<Node>>) {
+    tracing::info!("🧹 Removing managed taints from {} node(s) in {}", nodes.len(), ctx.cluster);
+    for node in nodes {
+        let node_name = node.name_any();
+        let current_taints = node.spec.as_ref().and_then(|s| s.taints.as_ref());
+        let changes: Vec<(Taint, bool)> = managed_taints(&node)
+            .into_iter()
+            .filter(|key| current_taints.is_some_and(|t| t.iter().any(|t| &t.key == key)))
+            .map(|key| (Taint { key, ..Default::default() }, false))
+            .collect();
+
+        if ctx.dry_run {
+            for (taint, _) in &changes {
+                report_dry_run(ctx, &node_name, "untaint", &taint.key);
+            }
+        } else if !changes.is_empty() {
+            if let Err(e) = ensure_taints(ctx, &node_name, &changes).await {
+                tracing::warn!("Could not remove the taints of node {}: {}", node_name, e);
+            }
+        }
+    }
+}
+
+/// Serve `/livez`, `/readyz`, `/leader`, `/debug/state` and `/metrics` on `addr`, over HTTPS with `tls`,
+/// and with `webhook` the pod mutating webhook on `/mutate`. The task ends once `stopped` flips
+/// and open requests are answered.
+fn spawn_server(
+    clusters: Vec<Cluster>,
+    server: ServerListener,
+    webhook: bool,
+    mut stopped: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    let clusters = Arc::new(clusters);
+    let leader_clusters = clusters.clone();
+    let debug_clusters = clusters.clone();
+    let webhook_clusters = clusters.clone();
+
+    // Answering at all proves the runtime is not wedged
+    let livez_route = warp::path("livez").map(|| "ok".to_string());
+
+    // Ready once every pod index is synced and every API server answers
+    let readyz_route = warp::path("readyz").and_then(move || {
+        let clusters = clusters.clone();
+        async move {
+            for cluster in clusters.iter() {
+                if !cluster.gates.synced() {
+                    let reply = format!("{}: pod cache not synced", cluster.name);
+                    return Ok::<_, warp::Rejection>(warp::reply::with_status(reply, StatusCode::SERVICE_UNAVAILABLE));
+                }
+                let error = match tokio::time::timeout(Duration::from_secs(5), cluster.client.apiserver_version()).await {
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(e)) => format!("API server unreachable: {}", e),
+                    Err(_) => "API server timed out".to_string(),
+                };
+                let reply = format!("{}: {}", cluster.name, error);
+                return Ok(warp::reply::with_status(reply, StatusCode::SERVICE_UNAVAILABLE));
+            }
+            Ok(warp::reply::with_status("ok".to_string(), StatusCode::OK))
+        }
+    });
+
+    // Lease status per cluster
+    let leader_route = warp::path("leader").map(move || {
+        let leaders: serde_json::Map<String, serde_json::Value> = leader_clusters.iter()
+            .map(|c| (c.name.clone(), json!(c.is_leader.load(Ordering::Relaxed))))
+            .collect();
+        warp::reply::json(&json!({ "leader": leaders }))
+    });
+
+    // Gates and their pod indexes, for diagnosing reconcile stalls
+    let debug_route = warp::path!("debug" / "state").map(move || {
+        let state: serde_json::Map<String, serde_json::Value> = debug_clusters.iter()
+            .map(|c| {
+                let gates: Vec<_> = c.gates.active().iter().map(|g| g.debug_state()).collect();
+                let cluster = json!({
+                    "leader": c.is_leader.load(Ordering::Relaxed),
+                    "gates": gates,
+                    "retiredTaints": c.gates.retired_keys(),
+                    "missingNads": c.nads.missing(),
+                });
+                (c.name.clone(), cluster)
+            })
+            .collect();
+        warp::reply::json(&state)
+    });
+
+    // Tolerations for the taints of every cluster's gates, extra ones are harmless
+    let mutate_route = warp::path("mutate")
+        .and(warp::post())
+        .and(warp::any().and_then(move || async move {
+            if webhook { Ok(()) } else { Err(warp::reject::not_found()) }
+        }))
+        .untuple_one()
+        .and(warp::body::json())
+        .map(move |review: AdmissionReview<Pod>| {
+            let gates: Vec<_> = webhook_clusters.iter().flat_map(|c| c.gates.active()).collect();
+            warp::reply::json(&webhook::mutate(review, &gates))
+        });
+
     let metrics_route = warp::path("metrics").map(|| {
         let encoder = TextEncoder::new();
         let families = prometheus::gather();
@@ -64,169 +424,449 @@ async fn main() -> anyhow::Result<()> {
     });
 
     // .boxed() erases the complex types and standardizes lifetimes
-    let routes = health_route.or(metrics_route).boxed();
+    let routes = livez_route.or(readyz_route).or(leader_route).or(debug_route).or(mutate_route).or(metrics_route).boxed();
 
+    let stopped = async move {
+        let _ = stopped.wait_for(|stop| *stop).await;
+    };
     tokio::spawn(async move {
-        warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
-    });
+        match server.tls {
+            Some(acceptor) => serve_tls(warp::service(routes), server.listener, acceptor, stopped).await,
+            None => warp::serve(routes).incoming(server.listener).graceful(stopped).run().await,
+        }
+    })
+}
 
-    // C. Leader Election
-    let is_leader = start_leader_election(client.clone(), &namespace, &hostname);
+/// The bound socket of the metrics and health server, with its TLS acceptor for HTTPS
+struct ServerListener {
+    listener: tokio::net::TcpListener,
+    tls: Option<TlsAcceptor>,
+}
 
-    // D. Cache Setup
-    let pods_api = Api::<Pod>::all(client.clone());
-    let pod_config = watcher::Config::default().labels(&selector);
-    
-    let (_pod_store, pod_writer) = reflector::store();
-    let pod_watcher = watcher(pods_api.clone(), pod_config.clone());
-    let pod_reflector = reflector::reflector(pod_writer, pod_watcher);
-    
-    let node_index = NodeIndex::new();
-    let node_index_clone = node_index.clone();
+async fn bind_server(addr: SocketAddr, tls: Option<&TlsFiles>) -> anyhow::Result<ServerListener> {
+    let tls = tls.map(|files| tls_config(files).map(|config| TlsAcceptor::from(Arc::new(config)))).transpose()?;
+    let listener = tokio::net::TcpListener::bind(addr).await
+        .with_context(|| format!("Failed to bind the HTTP server to {}", addr))?;
+    Ok(ServerListener { listener, tls })
+}
 
-    tokio::spawn(async move {
-        // We use `applied_objects()` which is a stream of `Result<Event<Pod>, Error>`.
-        // Wait, `applied_objects()` returns Objects, not Events.
-        // `reflector` implements `Stream` yielding `Result<Event<K>, ...>`.
-        // So just iterate `pod_reflector`.
-        use futures::StreamExt;
-        
-        pod_reflector.for_each(|res| {
-            let idx = node_index_clone.clone();
-            async move {
-                match res {
-                    Ok(event) => idx.update(&event),
-                    Err(e) => tracing::warn!("Pod watcher error: {}", e),
+/// Serve `service` over HTTPS until `stopped` resolves, then let open connections finish.
+/// warp 0.4 has no TLS of its own.
+async fn serve_tls<S>(
+    service: S,
+    listener: tokio::net::TcpListener,
+    acceptor: TlsAcceptor,
+    stopped: impl std::future::Future<Output = ()>,
+)
+where
+    S: tower_service::Service<
+            hyper::Request<hyper::body::Incoming>,
+            Response = warp::reply::Response,
+            Error = std::convert::Infallible,
+        > + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(stopped);
+
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept connection: {}", e);
+                    continue;
                 }
-            }
-        }).await;
-    });
+            },
+            _ = &mut stopped => break,
+        };
 
-    tracing::info!("🚀 Controller started. Watching Nodes & Pods...");
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(service.clone());
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => return tracing::debug!("TLS handshake failed: {}", e),
+            };
+            let connection = hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = watcher.watch(connection).await {
+                tracing::debug!("HTTPS connection ended with an error: {}", e);
+            }
+        });
+    }
 
-    // E. Main Controller Loop
-    let nodes_api = Api::<Node>::all(client.clone());
-    let ctx = Arc::new(Context {
-        client: client.clone(),
-        is_leader,
-        node_index,
-    });
+    graceful.shutdown().await;
+}
 
-    Controller::new(nodes_api, watcher::Config::default())
-        .with_config(kube::runtime::controller::Config::default().concurrency(10))
-        .watches(
-            pods_api,
-            pod_config, 
-            |pod| {
-                pod.spec.as_ref()
-                    .and_then(|s| s.node_name.clone())
-                    .map(|name| ObjectRef::<Node>::new(name.as_str()))
-            },
-        )
-        .run(reconcile, error_policy, ctx)
-        .for_each(|_| async {})
-        .await;
+/// Certificate chain and key from PEM files, read once at startup
+fn tls_config(files: &TlsFiles) -> anyhow::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", files.cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(&files.key)
+        .with_context(|| format!("Failed to read private key from {}", files.key.display()))?;
 
-    Ok(())
+    Ok(ServerConfig::builder_with_provider(Arc::new(tokio_rustls::rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?)
 }
 
 // --- 3. RECONCILIATION LOGIC ---
 struct Context {
-    client: Client,
+    cluster: String,
+    nodes: Arc<dyn NodeApi>,
+    // The controller's view of the nodes, read instead of GETting a node before patching it
+    node_cache: Store<Node>,
+    // Only listed to report the evictions of NoExecute taints
+    pods: Api<Pod>,
     is_leader: Arc<AtomicBool>,
-    node_index: NodeIndex,
+    gates: GateRegistry,
+    nads: NadTracker,
+    mode: Mode,
+    dry_run: bool,
+    node_condition: bool,
+    resync_interval: Duration,
+    error_requeue: Duration,
+    error_backoff_max: Duration,
+    // Consecutive reconcile failures per node, for the error backoff
+    failures: DashMap<String, u32>,
+    // Map: Taint key -> Nodes carrying it, for the tainted nodes gauge
+    tainted: DashMap<String, HashSet<String>>,
+    limiter: Option<RateLimiter>,
+    debouncer: Debouncer,
+    alerter: Arc<Alerter>,
+}
+
+impl Context {
+    /// Wait for the API rate limiter, if any, before a request
+    async fn throttle(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+    }
+
+    /// Record whether the node carries the taint now
+    fn set_tainted(&self, node_name: &str, key: &str, tainted: bool) {
+        let mut nodes = self.tainted.entry(key.to_string()).or_default();
+        let changed = if tainted { nodes.insert(node_name.to_string()) } else { nodes.remove(node_name) };
+        if changed {
+            NODES_TAINTED.with_label_values(&[self.cluster.as_str(), key]).set(nodes.len() as i64);
+        }
+    }
+
+    /// Drop a node going away from the tainted nodes gauge and its per-node series
+    fn forget_node(&self, node_name: &str) {
+        let mut keys: HashSet<String> = self.tainted.iter().map(|e| e.key().clone()).collect();
+        for key in &keys {
+            self.set_tainted(node_name, key, false);
+        }
+        keys.extend(self.gates.active().iter().map(|g| g.taint.key.clone()));
+        for key in &keys {
+            // Not there when the node was never evaluated for this key
+            let _ = NODE_READY.remove_label_values(&[self.cluster.as_str(), node_name, key.as_str()]);
+        }
+        self.alerter.forget(node_name, &keys);
+    }
 }
 
 async fn reconcile(node: Arc<Node>, ctx: Arc<Context>) -> Result<Action, kube::Error> {
+    // The controller is about to pause, the next leader reconciles the node
     if !ctx.is_leader.load(Ordering::Relaxed) {
         return Ok(Action::await_change());
     }
 
-    let _timer = RECONCILE_DURATION.start_timer();
+    let _timer = RECONCILE_DURATION.with_label_values(&[ctx.cluster.as_str()]).start_timer();
     let node_name = node.name_any();
-    let client = &ctx.client;
 
-    // Fast O(1) Check using Index
-    let is_multus_ready = ctx.node_index.is_node_ready(&node_name);
+    // A node going away needs no gating
+    if node.metadata.deletion_timestamp.is_some() {
+        tracing::debug!("Skipping node {}, it is being deleted", node_name);
+        ctx.forget_node(&node_name);
+        return Ok(Action::await_change());
+    }
 
-    let want_taint = !is_multus_ready;
+    // Opted out: whatever taints/labels it carries stay as they are
+    if node.annotations().get(SKIP_ANNOTATION).map(String::as_str) == Some("true") {
+        tracing::debug!("Skipping node {}, annotated {}", node_name, SKIP_ANNOTATION);
+        return Ok(Action::await_change());
+    }
+
+    // While the kubelet isn't Ready the node carries node.kubernetes.io/not-ready anyway and
+    // no CNI pod can be up, so wait for the node update that flips the condition
+    if !is_kubelet_ready(&node) {
+        tracing::debug!("Deferring node {}, kubelet is not Ready", node_name);
+        return Ok(Action::requeue(ctx.resync_interval));
+    }
 
     // Incremental Check: Check cached node first to avoid unnecessary API calls
     let current_taints = node.spec.as_ref()
         .and_then(|s| s.taints.as_ref())
         .map(|t| t.as_slice())
         .unwrap_or(&[]);
-    
-    let has_taint = current_taints.iter().any(|t| t.key == TAINT_KEY);
 
-    if has_taint == want_taint {
-        return Ok(Action::requeue(Duration::from_secs(300)));
+    // Shortest wait before a debounced change becomes due
+    let mut requeue = ctx.resync_interval;
+
+    let gates: Vec<_> = ctx.gates.active()
+        .into_iter()
+        .filter(|g| g.applies_to(&node_name))
+        .collect();
+
+    // Nodes are only marked ready once the required NADs exist, but not un-marked when one goes
+    let nads_present = ctx.nads.all_present();
+    if !nads_present {
+        tracing::debug!("⏳ Node {} stays gated, missing NetworkAttachmentDefinitions {:?}", node_name, ctx.nads.missing());
+    }
+
+    // Alert on nodes stuck not ready, whatever the mode
+    for gate in &gates {
+        if let Some(left) = ctx.alerter.observe(&node_name, &gate.taint.key, gate.index.is_node_ready(&node_name)) {
+            requeue = requeue.min(left);
+        }
+        // A ready pod still too young gets its node reconciled once it is old enough
+        if let Some(left) = gate.index.ready_in(&node_name) {
+            requeue = requeue.min(left);
+        }
+    }
+
+    // Each rule gates its own taint independently, all changes go out in one apply
+    if ctx.mode.taints() {
+        let mut changes: Vec<(Taint, bool)> = Vec::new();
+
+        for gate in &gates {
+            // Fast O(1) Check using Index
+            let ready = gate.index.is_node_ready(&node_name);
+            NODE_READY.with_label_values(&[ctx.cluster.as_str(), node_name.as_str(), gate.taint.key.as_str()]).set(ready as i64);
+            let want_taint = !ready;
+            let has_taint = current_taints.iter().any(|t| t.key == gate.taint.key);
+
+            if has_taint == want_taint || (!want_taint && !nads_present) {
+                ctx.debouncer.clear(&node_name, &gate.taint.key);
+                continue;
+            }
+
+            // Wait for the readiness to settle so pod restarts don't flap the taint
+            let grace = if want_taint { gate.not_ready_grace } else { gate.ready_grace };
+            if let Some(left) = ctx.debouncer.check(&node_name, &gate.taint.key, want_taint, grace) {
+                tracing::debug!("⏳ Node {} {} in {:?} unless readiness flips back", node_name,
+                    if want_taint { "taint" } else { "untaint" }, left);
+                requeue = requeue.min(left);
+                continue;
+            }
+            ctx.debouncer.clear(&node_name, &gate.taint.key);
+            changes.push((gate.taint.clone(), want_taint));
+        }
+
+        // Taints of deleted or changed policies, and of rules that no longer apply to this node
+        let active: HashSet<&str> = gates.iter().map(|g| g.taint.key.as_str()).collect();
+        let mut orphaned: HashSet<String> = managed_taints(&node)
+            .into_iter()
+            .filter(|key| !active.contains(key.as_str()))
+            .collect();
+        orphaned.extend(ctx.gates.retired_keys().into_iter().filter(|key| !active.contains(key.as_str())));
+        for key in orphaned {
+            if current_taints.iter().any(|t| t.key == key) {
+                changes.push((Taint { key, ..Default::default() }, false));
+            }
+        }
+
+        // NoExecute evicts whatever doesn't tolerate the taint, say who before it happens
+        for (taint, _) in changes.iter().filter(|(t, want)| *want && t.effect == "NoExecute") {
+            report_evictions(&ctx, &node_name, taint).await;
+        }
+
+        if ctx.dry_run {
+            for (taint, want_taint) in &changes {
+                report_dry_run(&ctx, &node_name, if *want_taint { "taint" } else { "untaint" }, &taint.key);
+            }
+        } else if !changes.is_empty() {
+            ensure_taints(&ctx, &node_name, &changes).await?;
+        }
+
+        for gate in &gates {
+            let has_taint = current_taints.iter().any(|t| t.key == gate.taint.key);
+            let tainted = match changes.iter().find(|(t, _)| t.key == gate.taint.key) {
+                Some((_, want_taint)) if !ctx.dry_run => *want_taint,
+                _ => has_taint,
+            };
+            ctx.set_tainted(&node_name, &gate.taint.key, tainted);
+        }
+        for (taint, _) in changes.iter().filter(|(_, want)| !want && !ctx.dry_run) {
+            ctx.set_tainted(&node_name, &taint.key, false);
+        }
+    }
+
+    // The label and the cordon summarize all rules: ready only when every CNI daemon is,
+    // debounced with the longest grace of the rules involved
+    let ready = gates.iter().all(|g| g.index.is_node_ready(&node_name));
+    let grace = gates.iter()
+        .map(|g| if ready { g.ready_grace } else { g.not_ready_grace })
+        .max()
+        .unwrap_or_default();
+
+    if ctx.mode.labels() && (nads_present || !ready) {
+        let want_label = if ready { "true" } else { "false" };
+
+        if node.labels().get(READY_LABEL).map(String::as_str) == Some(want_label) {
+            ctx.debouncer.clear(&node_name, READY_LABEL);
+        } else if let Some(left) = ctx.debouncer.check(&node_name, READY_LABEL, !ready, grace) {
+            requeue = requeue.min(left);
+        } else {
+            ctx.debouncer.clear(&node_name, READY_LABEL);
+            if ctx.dry_run {
+                report_dry_run(&ctx, &node_name, "label", &format!("{}={}", READY_LABEL, want_label));
+            } else {
+                ensure_label_state(&ctx, &node_name, want_label).await?;
+            }
+        }
+    }
+
+    if ctx.mode.cordons() && (nads_present || !ready) {
+        let unschedulable = node.spec.as_ref().and_then(|s| s.unschedulable).unwrap_or(false);
+        let ours = node.annotations().contains_key(CORDON_ANNOTATION);
+        // Leave nodes cordoned by someone else alone, in both directions
+        let needs_change = if ready { ours } else { !unschedulable };
+
+        if !needs_change {
+            ctx.debouncer.clear(&node_name, CORDON_ANNOTATION);
+        } else if let Some(left) = ctx.debouncer.check(&node_name, CORDON_ANNOTATION, !ready, grace) {
+            requeue = requeue.min(left);
+        } else {
+            ctx.debouncer.clear(&node_name, CORDON_ANNOTATION);
+            if ctx.dry_run {
+                report_dry_run(&ctx, &node_name, if ready { "uncordon" } else { "cordon" }, CORDON_ANNOTATION);
+            } else {
+                ensure_cordon_state(&ctx, &node_name, !ready).await?;
+            }
+        }
     }
 
-    ensure_taint_state(client, &node_name, want_taint).await?;
+    // The condition reports the current state as is, without grace periods
+    if ctx.node_condition {
+        let not_ready: Vec<&str> = gates.iter()
+            .filter(|g| !g.index.is_node_ready(&node_name))
+            .map(|g| g.taint.key.as_str())
+            .collect();
+        let (status, reason, message) = if !not_ready.is_empty() {
+            ("False", "CniPodNotReady", format!("No ready CNI pod for {}", not_ready.join(", ")))
+        } else if !nads_present {
+            ("False", "NetworkAttachmentDefinitionMissing", format!("Missing {}", ctx.nads.missing().join(", ")))
+        } else {
+            ("True", "CniPodsReady", "All CNI pods are ready".to_string())
+        };
+
+        let current = node.status.as_ref()
+            .and_then(|s| s.conditions.as_ref())
+            .and_then(|c| c.iter().find(|c| c.type_ == CONDITION_TYPE));
+        let unchanged = current.is_some_and(|c| {
+            c.status == status && c.reason.as_deref() == Some(reason) && c.message.as_deref() == Some(message.as_str())
+        });
+
+        if !unchanged {
+            if ctx.dry_run {
+                report_dry_run(&ctx, &node_name, "condition", &format!("{}={}", CONDITION_TYPE, status));
+            } else {
+                let now = Time(Utc::now());
+                let transitioned = current.filter(|c| c.status == status).and_then(|c| c.last_transition_time.clone());
+                let condition = NodeCondition {
+                    type_: CONDITION_TYPE.to_string(),
+                    status: status.to_string(),
+                    reason: Some(reason.to_string()),
+                    message: Some(message),
+                    last_heartbeat_time: Some(now.clone()),
+                    last_transition_time: Some(transitioned.unwrap_or(now)),
+                };
+                ensure_condition_state(&ctx, &node_name, condition).await?;
+            }
+        }
+    }
 
-    Ok(Action::requeue(Duration::from_secs(300)))
+    ctx.failures.remove(&node_name);
+    Ok(Action::requeue(requeue))
 }
 
-async fn ensure_taint_state(client: &Client, node_name: &str, want_taint: bool) -> Result<(), kube::Error> {
-    let nodes: Api<Node> = Api::all(client.clone());
-    
-    for _ in 0..5 {
-        let node = nodes.get(node_name).await?;
-        
+/// Add (`true`) or remove (`false`) each taint of `changes`, in a single apply per attempt.
+/// The first attempt starts from the cached node, only retries after a conflict GET it.
+async fn ensure_taints(ctx: &Context, node_name: &str, changes: &[(Taint, bool)]) -> Result<(), kube::Error> {
+    for attempt in 0..5 {
+        let cached = (attempt == 0).then(|| ctx.node_cache.get(&ObjectRef::new(node_name))).flatten();
+        let node = match cached {
+            Some(node) => node,
+            None => {
+                ctx.throttle().await;
+                Arc::new(ctx.nodes.get(node_name).await?)
+            }
+        };
+
         let current_taints = node.spec.as_ref()
             .and_then(|s| s.taints.clone())
             .unwrap_or_default();
 
-        let has_taint = current_taints.iter().any(|t| t.key == TAINT_KEY);
-
-        if has_taint == want_taint {
-            return Ok(());
-        }
-
         let mut new_taints = current_taints.clone();
-        if want_taint {
-            if !has_taint {
-                tracing::info!("🔒 Tainting node {}", node_name);
-                new_taints.push(k8s_openapi::api::core::v1::Taint {
-                    key: TAINT_KEY.to_string(),
-                    value: None, 
-                    effect: "NoSchedule".to_string(),
-                    time_added: None,
-                });
+        let mut managed: BTreeSet<String> = managed_taints(&node).into_iter().collect();
+        let mut applied = 0;
+        for (taint, want_taint) in changes {
+            let has_taint = new_taints.iter().any(|t| t.key == taint.key);
+            if *want_taint && !has_taint {
+                tracing::info!("🔒 Tainting node {} with {}", node_name, taint.key);
+                new_taints.push(taint.clone());
+                managed.insert(taint.key.clone());
+                applied += 1;
+            } else if !*want_taint && has_taint {
+                tracing::info!("🔓 Removing {} from node {}", taint.key, node_name);
+                new_taints.retain(|t| t.key != taint.key);
+                applied += 1;
             }
-        } else {
-            if has_taint {
-                tracing::info!("🔓 Untainting node {}", node_name);
-                new_taints.retain(|t| t.key != TAINT_KEY);
+            if !*want_taint {
+                managed.remove(&taint.key);
             }
         }
 
+        if applied == 0 {
+            return Ok(());
+        }
+
+        // spec.taints is an atomic list, usually owned by the kubelet or cloud provider too, so
+        // an unforced apply would always conflict. The apply is forced and carries the whole
+        // list as just read, with the resourceVersion guarding against that read being stale,
+        // e.g. the cache lagging behind: then it conflicts and we retry from a fresh GET.
+        // An annotation left out of the apply is dropped, as we own it
+        let mut metadata = json!({ "name": node_name });
+        if let Some(version) = &node.metadata.resource_version {
+            metadata["resourceVersion"] = json!(version);
+        }
+        if !managed.is_empty() {
+            let keys: Vec<_> = managed.into_iter().collect();
+            metadata["annotations"] = json!({ MANAGED_TAINTS_ANNOTATION: keys.join(",") });
+        }
         let patch_json = json!({
             "apiVersion": "v1",
             "kind": "Node",
-            "metadata": {
-                "resourceVersion": node.resource_version(),
-            },
+            "metadata": metadata,
             "spec": {
                 "taints": new_taints
             }
         });
 
-        let params = PatchParams::default();
-        match nodes.patch(node_name, &params, &Patch::Merge(patch_json)).await {
+        ctx.throttle().await;
+        match ctx.nodes.apply(node_name, patch_json).await {
             Ok(_) => {
-                TAINT_OPERATIONS.inc();
+                TAINT_OPERATIONS.with_label_values(&[ctx.cluster.as_str()]).inc_by(applied as f64);
+                TAINT_APPLY_ATTEMPTS.with_label_values(&[ctx.cluster.as_str()]).observe((attempt + 1) as f64);
                 return Ok(());
             },
             Err(kube::Error::Api(ae)) if ae.code == 409 => {
+                TAINT_CONFLICTS.with_label_values(&[ctx.cluster.as_str()]).inc();
                 tracing::warn!("Conflict updating node {}, retrying...", node_name);
                 continue;
             },
             Err(e) => return Err(e),
         }
     }
-    
+
+    TAINT_RETRIES_EXHAUSTED.with_label_values(&[ctx.cluster.as_str()]).inc();
     Err(kube::Error::Api(kube::error::ErrorResponse {
         status: "Failure".to_string(),
         message: "Failed to update node taints after retries".to_string(),
@@ -235,40 +875,420 @@ async fn ensure_taint_state(client: &Client, node_name: &str, want_taint: bool)
     }))
 }
 
+async fn ensure_label_state(ctx: &Context, node_name: &str, value: &str) -> Result<(), kube::Error> {
+    tracing::info!("🏷️ Labeling node {} with {}={}", node_name, READY_LABEL, value);
+    let patch_json = json!({
+        "metadata": {
+            "labels": { READY_LABEL: value }
+        }
+    });
+
+    ctx.throttle().await;
+    ctx.nodes.merge(node_name, patch_json).await?;
+    LABEL_OPERATIONS.with_label_values(&[ctx.cluster.as_str()]).inc();
+    Ok(())
+}
+
+/// Set our condition in the node status, leaving the kubelet's conditions alone
+async fn ensure_condition_state(ctx: &Context, node_name: &str, condition: NodeCondition) -> Result<(), kube::Error> {
+    tracing::info!("📋 Setting {}={} on node {}", CONDITION_TYPE, condition.status, node_name);
+    // Strategic merge patches merge status.conditions by type
+    let patch_json = json!({
+        "status": {
+            "conditions": [condition]
+        }
+    });
+
+    ctx.throttle().await;
+    ctx.nodes.patch_status(node_name, patch_json).await?;
+    CONDITION_UPDATES.with_label_values(&[ctx.cluster.as_str()]).inc();
+    Ok(())
+}
+
+/// Cordon the node and mark it ours, or lift our cordon and the mark
+async fn ensure_cordon_state(ctx: &Context, node_name: &str, cordon: bool) -> Result<(), kube::Error> {
+    if cordon {
+        tracing::info!("🚧 Cordoning node {}", node_name);
+    } else {
+        tracing::info!("✅ Uncordoning node {}", node_name);
+    }
+    let patch_json = json!({
+        "metadata": {
+            "annotations": { CORDON_ANNOTATION: if cordon { Some("true") } else { None } }
+        },
+        "spec": {
+            "unschedulable": cordon
+        }
+    });
+
+    ctx.throttle().await;
+    ctx.nodes.merge(node_name, patch_json).await?;
+    CORDON_OPERATIONS.with_label_values(&[ctx.cluster.as_str()]).inc();
+    Ok(())
+}
+
 // --- 4. HELPERS ---
 
-fn error_policy(_node: Arc<Node>, err: &kube::Error, _ctx: Arc<Context>) -> Action {
-    tracing::error!("Reconcile error: {:?}", err);
-    Action::requeue(Duration::from_secs(5))
+/// Taint keys the controller added to the node
+fn managed_taints(node: &Node) -> Vec<String> {
+    node.annotations().get(MANAGED_TAINTS_ANNOTATION)
+        .map(|keys| keys.split(',').filter(|k| !k.is_empty()).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn is_kubelet_ready(node: &Node) -> bool {
+    node.status.as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .and_then(|c| c.iter().find(|c| c.type_ == "Ready"))
+        .is_some_and(|c| c.status == "True")
+}
+
+/// Log and count a change DRY_RUN kept us from making
+fn report_dry_run(ctx: &Context, node_name: &str, action: &str, target: &str) {
+    tracing::info!("🧪 [dry-run] Would {} node {} in {} ({})", action, node_name, ctx.cluster, target);
+    DRY_RUN_CHANGES.with_label_values(&[ctx.cluster.as_str(), action]).inc();
+}
+
+/// Log the pods a NoExecute `taint` evicts from the node, immediately or once their
+/// tolerationSeconds run out
+async fn report_evictions(ctx: &Context, node_name: &str, taint: &Taint) {
+    ctx.throttle().await;
+    let params = ListParams::default().fields(&format!("spec.nodeName={}", node_name));
+    let pods = match ctx.pods.list(&params).await {
+        Ok(pods) => pods,
+        Err(e) => {
+            tracing::warn!("Could not list the pods {} would evict from node {}: {}", taint.key, node_name, e);
+            return;
+        }
+    };
+
+    for pod in pods {
+        let tolerations = pod.spec.as_ref().and_then(|s| s.tolerations.as_ref());
+        let matching = tolerations.into_iter().flatten().find(|t| {
+            let key = t.key.is_none() || t.key.as_deref() == Some(taint.key.as_str());
+            let effect = t.effect.is_none() || t.effect.as_deref() == Some("NoExecute");
+            let value = t.operator.as_deref() == Some("Exists") || t.value == taint.value;
+            key && effect && value
+        });
+        let pod_name = format!("{}/{}", pod.namespace().unwrap_or_default(), pod.name_any());
+        match matching.map(|t| t.toleration_seconds) {
+            None => tracing::warn!("🚪 NoExecute taint {} evicts pod {} from node {}", taint.key, pod_name, node_name),
+            Some(Some(seconds)) => tracing::warn!("🚪 NoExecute taint {} evicts pod {} from node {} after its {}s tolerationSeconds",
+                taint.key, pod_name, node_name, seconds),
+            Some(None) => {}
+        }
+    }
 }
 
-fn start_leader_election(client: Client, ns: &str, hostname: &str) -> Arc<AtomicBool> {
+/// Retry failed nodes with exponential backoff, starting at the error requeue delay
+fn error_policy(node: Arc<Node>, err: &kube::Error, ctx: Arc<Context>) -> Action {
+    RECONCILE_ERRORS.with_label_values(&[ctx.cluster.as_str()]).inc();
+    tracing::error!("Reconcile error in {}: {:?}", ctx.cluster, err);
+
+    let failures = {
+        let mut entry = ctx.failures.entry(node.name_any()).or_insert(0);
+        *entry = entry.saturating_add(1);
+        *entry
+    };
+    let delay = ctx.error_requeue.saturating_mul(2u32.saturating_pow(failures - 1));
+    Action::requeue(delay.min(ctx.error_backoff_max))
+}
+
+/// Install the global subscriber, filtered by `RUST_LOG` (default `info`).
+/// `LOG_FORMAT` picks `text` (default, one line per event), `json` or `pretty`.
+/// With the `tokio-console` feature the console instrumentation is layered on top.
+fn init_logging() -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt = match std::env::var("LOG_FORMAT").unwrap_or_default().to_ascii_lowercase().as_str() {
+        "" | "text" => fmt::layer().boxed(),
+        "json" => fmt::layer().json().boxed(),
+        "pretty" => fmt::layer().pretty().boxed(),
+        other => return Err(anyhow::anyhow!("Invalid LOG_FORMAT '{}', expected text, json or pretty", other)),
+    };
+
+    let registry = tracing_subscriber::registry().with(fmt.with_filter(filter));
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+    Ok(())
+}
+
+/// Acquire and renew the lease in the background until `stopped` flips, then release it.
+/// Leadership changes are published through both the flag and the receiver.
+/// The returned task ends once the lease has been released.
+fn start_leader_election(
+    client: Client,
+    cluster: &str,
+    config: &Config,
+    mut stopped: watch::Receiver<bool>,
+) -> (Arc<AtomicBool>, watch::Receiver<bool>, JoinHandle<()>) {
     let is_leader = Arc::new(AtomicBool::new(false));
     let flag = is_leader.clone();
-    let lease_name = LEASE_NAME.to_string();
-    let hostname = hostname.to_string();
-    let ns = ns.to_string();
+    let (leadership, leadership_rx) = watch::channel(false);
+    let cluster = cluster.to_string();
+    let renew = config.lease_renew;
+    let params = LeaseLockParams {
+        holder_id: config.hostname.clone(),
+        lease_name: config.lease_name.clone(),
+        lease_ttl: config.lease_ttl,
+    };
+    let lock = LeaseLock::new(client, &config.namespace, params);
 
-    tokio::spawn(async move {
+    let task = tokio::spawn(async move {
         loop {
-            let params = LeaseLockParams {
-                holder_id: hostname.clone(),
-                lease_name: lease_name.clone(),
-                lease_ttl: Duration::from_secs(15),
-            };
-            let lock = LeaseLock::new(client.clone(), &ns, params);
-            
             match lock.try_acquire_or_renew().await {
                 Ok(lease) => {
                     if lease.acquired_lease != flag.load(Ordering::Relaxed) {
-                        tracing::info!("👑 Leader State Change: {}", lease.acquired_lease);
+                        tracing::info!("👑 Leader State Change in {}: {}", cluster, lease.acquired_lease);
                         flag.store(lease.acquired_lease, Ordering::Relaxed);
+                        IS_LEADER.with_label_values(&[cluster.as_str()]).set(lease.acquired_lease as i64);
+                        leadership.send_replace(lease.acquired_lease);
                     }
                 },
-                Err(e) => tracing::warn!("Leader election error: {}", e),
+                Err(e) => tracing::warn!("Leader election error in {}: {}", cluster, e),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(renew) => {},
+                _ = stopped.changed() => break,
+            }
+        }
+
+        if flag.swap(false, Ordering::Relaxed) {
+            IS_LEADER.with_label_values(&[cluster.as_str()]).set(0);
+            leadership.send_replace(false);
+            match lock.step_down().await {
+                Ok(()) => tracing::info!("👑 Released the lease in {}", cluster),
+                Err(e) => tracing::warn!("Failed to release the lease in {}: {}", cluster, e),
             }
-            tokio::time::sleep(Duration::from_secs(5)).await;
         }
     });
-    is_leader
+    (is_leader, leadership_rx, task)
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::runtime::{reflector, watcher::Event};
+
+    use super::*;
+    use crate::kube_api::fake::FakeNodeApi;
+    use crate::policy::Gate;
+    use crate::state::{NodeIndex, PodChecks, PodSource};
+
+    const TAINT_KEY: &str = "CriticalAddonsOnly";
+
+    fn node(name: &str, tainted: bool) -> Node {
+        let taints = if tainted {
+            json!([{ "key": TAINT_KEY, "effect": "NoSchedule" }])
+        } else {
+            json!([])
+        };
+        serde_json::from_value(json!({
+            "metadata": {
+                "name": name,
+                "annotations": { MANAGED_TAINTS_ANNOTATION: TAINT_KEY },
+            },
+            "spec": { "taints": taints },
+            "status": { "conditions": [{ "type": "Ready", "status": "True" }] },
+        })).unwrap()
+    }
+
+    fn ready_pod(node_name: &str) -> Pod {
+        serde_json::from_value(json!({
+            "metadata": { "name": format!("multus-{}", node_name), "uid": format!("uid-{}", node_name) },
+            "spec": { "nodeName": node_name, "containers": [] },
+            "status": { "phase": "Running", "conditions": [{ "type": "Ready", "status": "True" }] },
+        })).unwrap()
+    }
+
+    fn taint_keys(node: &Node) -> Vec<String> {
+        node.spec.as_ref()
+            .and_then(|s| s.taints.as_ref())
+            .map(|t| t.iter().map(|t| t.key.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn context(nodes: Arc<FakeNodeApi>, leader: bool, ready_nodes: &[&str]) -> Arc<Context> {
+        context_with_cache(nodes, leader, ready_nodes, Vec::new())
+    }
+
+    /// Context with a single rule whose pods are ready on `ready_nodes`, and `cached` nodes
+    /// in the node cache
+    fn context_with_cache(nodes: Arc<FakeNodeApi>, leader: bool, ready_nodes: &[&str], cached: Vec<Node>) -> Arc<Context> {
+        // Never contacted: the gates are inserted directly and no NADs are required
+        let client = Client::try_from(kube::Config::new("http://127.0.0.1:9".parse().unwrap())).unwrap();
+        let gates = GateRegistry::new(Api::all(client.clone()), PodChecks::default(), false);
+
+        let index = NodeIndex::new(PodSource::selector("app=multus"), PodChecks::default());
+        for node_name in ready_nodes {
+            index.update_from(0, &Event::Apply(ready_pod(node_name)));
+        }
+        gates.insert("test", vec![Gate {
+            policy: "test".to_string(),
+            source: PodSource::selector("app=multus"),
+            taint: Taint { key: TAINT_KEY.to_string(), effect: "NoSchedule".to_string(), ..Default::default() },
+            excluded_nodes: HashSet::new(),
+            not_ready_grace: Duration::ZERO,
+            ready_grace: Duration::ZERO,
+            index,
+        }]);
+
+        let (node_cache, mut writer) = reflector::store();
+        for node in cached {
+            writer.apply_watcher_event(&Event::Apply(node));
+        }
+
+        Arc::new(Context {
+            alerter: Arc::new(Alerter::new("test".to_string(), Duration::from_secs(600), None)),
+            node_cache,
+            cluster: "test".to_string(),
+            nodes,
+            pods: Api::all(client.clone()),
+            is_leader: Arc::new(AtomicBool::new(leader)),
+            nads: NadTracker::spawn(client, Vec::new(), gates.clone()),
+            gates,
+            mode: Mode::Taint,
+            dry_run: false,
+            node_condition: false,
+            resync_interval: Duration::from_secs(300),
+            error_requeue: Duration::from_secs(5),
+            error_backoff_max: Duration::from_secs(300),
+            failures: DashMap::new(),
+            tainted: DashMap::new(),
+            limiter: None,
+            debouncer: Debouncer::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn taints_node_without_ready_pod() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", false));
+        let ctx = context(api.clone(), true, &[]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert_eq!(taint_keys(&api.node("n1")), vec![TAINT_KEY]);
+        assert_eq!(api.calls(), vec!["get n1", "apply n1"]);
+    }
+
+    #[tokio::test]
+    async fn removes_taint_once_pod_is_ready() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", true));
+        let ctx = context(api.clone(), true, &["n1"]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert!(taint_keys(&api.node("n1")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_node_alone_when_already_in_sync() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", true));
+        let ctx = context(api.clone(), true, &[]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert!(api.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_from_fresh_node_after_conflict() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", false));
+        api.conflict_next(1);
+        let ctx = context(api.clone(), true, &[]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert_eq!(taint_keys(&api.node("n1")), vec![TAINT_KEY]);
+        assert_eq!(api.calls(), vec!["get n1", "apply n1", "get n1", "apply n1"]);
+    }
+
+    #[tokio::test]
+    async fn keeps_foreign_taint_in_a_single_apply() {
+        let api = Arc::new(FakeNodeApi::default());
+        let mut n1 = node("n1", false);
+        n1.spec.as_mut().unwrap().taints = Some(vec![Taint {
+            key: "node.cloudprovider.kubernetes.io/uninitialized".to_string(),
+            effect: "NoSchedule".to_string(),
+            ..Default::default()
+        }]);
+        api.insert(n1.clone());
+        let ctx = context_with_cache(api.clone(), true, &[], vec![api.node("n1")]);
+
+        reconcile(Arc::new(n1), ctx).await.unwrap();
+
+        assert_eq!(taint_keys(&api.node("n1")), vec!["node.cloudprovider.kubernetes.io/uninitialized", TAINT_KEY]);
+        assert_eq!(api.calls(), vec!["apply n1"]);
+    }
+
+    #[tokio::test]
+    async fn refetches_when_cached_node_is_stale() {
+        let api = Arc::new(FakeNodeApi::default());
+        let mut stale = node("n1", false);
+        stale.metadata.resource_version = Some("1".to_string());
+        let mut current = node("n1", false);
+        current.metadata.resource_version = Some("2".to_string());
+        api.insert(current);
+        let ctx = context_with_cache(api.clone(), true, &[], vec![stale]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert_eq!(taint_keys(&api.node("n1")), vec![TAINT_KEY]);
+        assert_eq!(api.calls(), vec!["apply n1", "get n1", "apply n1"]);
+    }
+
+    #[tokio::test]
+    async fn applies_from_cached_node_without_get() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", false));
+        let ctx = context_with_cache(api.clone(), true, &[], vec![node("n1", false)]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert_eq!(taint_keys(&api.node("n1")), vec![TAINT_KEY]);
+        assert_eq!(api.calls(), vec!["apply n1"]);
+    }
+
+    #[tokio::test]
+    async fn forgets_readiness_series_of_deleted_node() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n-deleted", true));
+        let ctx = context(api.clone(), true, &[]);
+        reconcile(Arc::new(api.node("n-deleted")), ctx.clone()).await.unwrap();
+
+        let mut deleted = api.node("n-deleted");
+        deleted.metadata.deletion_timestamp = Some(Time(Utc::now()));
+        reconcile(Arc::new(deleted), ctx).await.unwrap();
+
+        assert!(NODE_READY.remove_label_values(&["test", "n-deleted", TAINT_KEY]).is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_opted_out_node() {
+        let api = Arc::new(FakeNodeApi::default());
+        let mut n1 = node("n1", false);
+        n1.annotations_mut().insert(SKIP_ANNOTATION.to_string(), "true".to_string());
+        api.insert(n1);
+        let ctx = context(api.clone(), true, &[]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert!(api.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn follower_does_not_patch_nodes() {
+        let api = Arc::new(FakeNodeApi::default());
+        api.insert(node("n1", false));
+        let ctx = context(api.clone(), false, &[]);
+
+        reconcile(Arc::new(api.node("n1")), ctx).await.unwrap();
+
+        assert!(taint_keys(&api.node("n1")).is_empty());
+        assert!(api.calls().is_empty());
+    }
 }
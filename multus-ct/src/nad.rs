@@ -0,0 +1,100 @@
+use dashmap::DashSet;
+use futures::StreamExt;
+use kube::{
+    api::{Api, ApiResource, DynamicObject, GroupVersionKind},
+    runtime::{watcher, watcher::Event, WatchStreamExt},
+    Client, ResourceExt,
+};
+use std::{collections::HashSet, sync::Arc};
+
+use crate::policy::GateRegistry;
+
+/// Tracks which of the required NetworkAttachmentDefinitions exist. Pods referencing a
+/// missing NAD fail to start even with a ready Multus pod.
+#[derive(Clone)]
+pub struct NadTracker {
+    // "namespace/name" of every required NAD
+    required: Arc<Vec<String>>,
+    present: Arc<DashSet<String>>,
+}
+
+impl NadTracker {
+    /// Watch the NADs of the cluster, resyncing every node when a required one comes or goes.
+    /// Without required NADs nothing is watched and the check always passes.
+    pub fn spawn(client: Client, required: Vec<String>, gates: GateRegistry) -> Self {
+        let tracker = Self {
+            required: Arc::new(required),
+            present: Arc::new(DashSet::new()),
+        };
+        if tracker.required.is_empty() {
+            return tracker;
+        }
+
+        let nads: Api<DynamicObject> = Api::all_with(client, &nad_resource());
+        let state = tracker.clone();
+
+        tokio::spawn(async move {
+            let mut events = watcher(nads, watcher::Config::default()).default_backoff().boxed();
+            // NADs seen during a relist, replacing the set once it completes
+            let mut relisted: HashSet<String> = HashSet::new();
+
+            while let Some(event) = events.next().await {
+                let was_ready = state.all_present();
+                match event {
+                    Ok(Event::Apply(nad)) => { state.present.insert(key(&nad)); }
+                    Ok(Event::Delete(nad)) => { state.present.remove(&key(&nad)); }
+                    Ok(Event::Init) => relisted.clear(),
+                    Ok(Event::InitApply(nad)) => { relisted.insert(key(&nad)); }
+                    Ok(Event::InitDone) => {
+                        state.present.retain(|k| relisted.contains(k));
+                        for k in relisted.drain() {
+                            state.present.insert(k);
+                        }
+                    }
+                    Err(e) => tracing::warn!("NetworkAttachmentDefinition watcher error: {}", e),
+                }
+
+                if state.all_present() != was_ready {
+                    tracing::info!("🧩 Required NetworkAttachmentDefinitions present: {}", !was_ready);
+                    gates.resync();
+                }
+            }
+        });
+
+        tracker
+    }
+
+    pub fn all_present(&self) -> bool {
+        self.required.iter().all(|nad| self.present.contains(nad))
+    }
+
+    /// Required NADs that don't exist (yet)
+    pub fn missing(&self) -> Vec<String> {
+        self.required.iter().filter(|nad| !self.present.contains(*nad)).cloned().collect()
+    }
+}
+
+/// Multus registers the CRD with a hyphenated plural, which `from_gvk` can't guess
+fn nad_resource() -> ApiResource {
+    let gvk = GroupVersionKind::gvk("k8s.cni.cncf.io", "v1", "NetworkAttachmentDefinition");
+    ApiResource::from_gvk_with_plural(&gvk, "network-attachment-definitions")
+}
+
+fn key(nad: &DynamicObject) -> String {
+    format!("{}/{}", nad.namespace().unwrap_or_default(), nad.name_any())
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::Resource;
+
+    use super::*;
+
+    #[test]
+    fn nad_resource_uses_multus_plural() {
+        assert_eq!(
+            DynamicObject::url_path(&nad_resource(), None),
+            "/apis/k8s.cni.cncf.io/v1/network-attachment-definitions",
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use dashmap::{DashMap, DashSet};
+use futures::{FutureExt, Stream, StreamExt};
+use k8s_openapi::api::core::v1::{Node, Pod, Taint};
+use kube::{
+    api::Api,
+    runtime::{reflector::ObjectRef, watcher, watcher::Event, WatchStreamExt},
+    Client, CustomResource, ResourceExt,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::Notify, task::AbortHandle};
+
+use crate::config::ReadinessRule;
+use crate::state::{NodeIndex, PodChecks, PodSource};
+
+/// Registry key of the rules configured through the environment
+pub const ENV_POLICY: &str = "env";
+
+/// Cluster-wide readiness policy, watched at runtime so rules can change without a restart.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "multus.network.k8s.io",
+    version = "v1alpha1",
+    kind = "CniReadinessPolicy",
+    plural = "cnireadinesspolicies"
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CniReadinessPolicySpec {
+    /// CNI daemons (pod selector + taint key) that must be ready on a node
+    pub selectors: Vec<PolicySelector>,
+    #[serde(default)]
+    pub taint: TaintSpec,
+    /// Seconds a node must stay not-ready before it is tainted
+    #[serde(default)]
+    pub not_ready_grace_seconds: u64,
+    /// Seconds a node must stay ready before its taint is removed
+    #[serde(default)]
+    pub ready_grace_seconds: u64,
+    /// Nodes this policy never touches
+    #[serde(default)]
+    pub excluded_nodes: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicySelector {
+    /// Label selector of the daemon's pods, ignored when `daemonSet` is set
+    #[serde(default)]
+    pub selector: String,
+    /// Count only the pods owned by this DaemonSet
+    #[serde(default)]
+    pub daemon_set: Option<DaemonSetRef>,
+    /// Namespaces to watch for `selector`, all when empty
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    pub taint_key: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct DaemonSetRef {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl PolicySelector {
+    fn source(&self) -> PodSource {
+        match &self.daemon_set {
+            Some(ds) => PodSource::DaemonSet { namespace: ds.namespace.clone(), name: ds.name.clone() },
+            None => PodSource::selector(&self.selector).in_namespaces(&self.namespaces),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TaintSpec {
+    #[serde(default = "default_effect")]
+    pub effect: String,
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+impl Default for TaintSpec {
+    fn default() -> Self {
+        Self { effect: default_effect(), value: None }
+    }
+}
+
+fn default_effect() -> String {
+    "NoSchedule".to_string()
+}
+
+/// A readiness rule with the index of its ready pods
+pub struct Gate {
+    pub policy: String,
+    pub source: PodSource,
+    pub taint: Taint,
+    pub excluded_nodes: HashSet<String>,
+    pub not_ready_grace: Duration,
+    pub ready_grace: Duration,
+    pub index: NodeIndex,
+}
+
+impl Gate {
+    pub fn applies_to(&self, node_name: &str) -> bool {
+        !self.excluded_nodes.contains(node_name)
+    }
+
+    /// The gate and its index contents, for `/debug/state`
+    pub fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "policy": self.policy,
+            "source": self.source.to_string(),
+            "taintKey": self.taint.key,
+            "excludedNodes": self.excluded_nodes,
+            "notReadyGraceSeconds": self.not_ready_grace.as_secs(),
+            "readyGraceSeconds": self.ready_grace.as_secs(),
+            "synced": self.index.is_synced(),
+            "readyPods": self.index.snapshot(),
+        })
+    }
+}
+
+struct PolicyEntry {
+    generation: Option<i64>,
+    gates: Vec<Arc<Gate>>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Drop for PolicyEntry {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Reconcile requests of the registry. They coalesce instead of queueing: the indexes run on
+/// every replica, but only the leader's controller takes the requests.
+#[derive(Clone, Default)]
+pub struct ReconcileTriggers {
+    // Set when every node should be reconciled
+    all: Arc<Notify>,
+    // Nodes whose readiness flipped in some index since the controller last took them
+    nodes: Arc<DashSet<String>>,
+    nodes_flipped: Arc<Notify>,
+}
+
+impl ReconcileTriggers {
+    fn reconcile_all(&self) {
+        self.all.notify_one();
+    }
+
+    fn reconcile_node(&self, node_name: &str) {
+        self.nodes.insert(node_name.to_string());
+        self.nodes_flipped.notify_one();
+    }
+
+    /// Drop the requests made while no controller ran, a starting one reconciles every node anyway
+    pub fn clear(&self) {
+        let _ = self.all.notified().now_or_never();
+        let _ = self.nodes_flipped.notified().now_or_never();
+        self.nodes.clear();
+    }
+
+    /// Yields whenever every node should be reconciled
+    pub fn all_requests(&self) -> impl Stream<Item = ()> + Send + 'static {
+        futures::stream::unfold(self.all.clone(), |all| async move {
+            all.notified().await;
+            Some(((), all))
+        })
+    }
+
+    /// Yields the nodes whose readiness flipped, each once however often it flipped meanwhile
+    pub fn node_requests(&self) -> impl Stream<Item = ObjectRef<Node>> + Send + 'static {
+        futures::stream::unfold(self.clone(), |triggers| async move {
+            triggers.nodes_flipped.notified().await;
+            let names: Vec<String> = triggers.nodes.iter().map(|n| n.clone()).collect();
+            for name in &names {
+                triggers.nodes.remove(name);
+            }
+            let nodes = futures::stream::iter(names.into_iter().map(|n| ObjectRef::new(n.as_str())));
+            Some((nodes, triggers))
+        })
+        .flatten()
+    }
+}
+
+/// Active gates from the environment and from every `CniReadinessPolicy`.
+#[derive(Clone)]
+pub struct GateRegistry {
+    pods_api: Api<Pod>,
+    pod_checks: PodChecks,
+    allow_no_execute: bool,
+    policies: Arc<DashMap<String, PolicyEntry>>,
+    // Taint keys no longer managed by any policy, removed from nodes that still carry them
+    retired: Arc<DashSet<String>>,
+    triggers: ReconcileTriggers,
+}
+
+impl GateRegistry {
+    pub fn new(pods_api: Api<Pod>, pod_checks: PodChecks, allow_no_execute: bool) -> Self {
+        Self {
+            pods_api,
+            pod_checks,
+            allow_no_execute,
+            policies: Arc::new(DashMap::new()),
+            retired: Arc::new(DashSet::new()),
+            triggers: ReconcileTriggers::default(),
+        }
+    }
+
+    /// Which nodes to reconcile because of policy changes and readiness flips
+    pub fn triggers(&self) -> &ReconcileTriggers {
+        &self.triggers
+    }
+
+    /// Register the rules from the environment. The controller watches their pods too, but
+    /// its reconcile can run before this index saw the same event, so the nodes the index
+    /// flips are requeued like they are for policies.
+    pub fn set_static(&self, rules: &[ReadinessRule], not_ready_grace: Duration, ready_grace: Duration) {
+        let mut tasks = Vec::new();
+        let gates = rules.iter()
+            .map(|rule| {
+                let (index, task) = self.spawn_index(&rule.source);
+                tasks.push(task);
+                Arc::new(Gate {
+                    policy: ENV_POLICY.to_string(),
+                    source: rule.source.clone(),
+                    taint: Taint {
+                        key: rule.taint_key.clone(),
+                        effect: rule.effect.clone(),
+                        ..Default::default()
+                    },
+                    excluded_nodes: HashSet::new(),
+                    not_ready_grace,
+                    ready_grace,
+                    index,
+                })
+            })
+            .collect();
+
+        self.replace(ENV_POLICY, PolicyEntry { generation: None, gates, tasks });
+    }
+
+    /// Add or update a policy, rebuilding its gates only when the spec changed
+    pub fn apply(&self, policy: &CniReadinessPolicy) {
+        let name = policy.name_any();
+        let generation = policy.metadata.generation;
+        if let Some(entry) = self.policies.get(&name) {
+            if generation.is_some() && entry.generation == generation {
+                return;
+            }
+        }
+
+        tracing::info!("📜 Loading CniReadinessPolicy {}", name);
+        let spec = &policy.spec;
+        let effect = if spec.taint.effect == "NoExecute" && !self.allow_no_execute {
+            tracing::warn!("⚠️ CniReadinessPolicy {} asks for NoExecute, using NoSchedule (ALLOW_NO_EXECUTE is off)", name);
+            default_effect()
+        } else {
+            spec.taint.effect.clone()
+        };
+        let mut tasks = Vec::new();
+        let gates = spec.selectors.iter()
+            .map(|sel| {
+                let source = sel.source();
+                let (index, task) = self.spawn_index(&source);
+                tasks.push(task);
+                Arc::new(Gate {
+                    policy: name.clone(),
+                    source,
+                    taint: Taint {
+                        key: sel.taint_key.clone(),
+                        value: spec.taint.value.clone(),
+                        effect: effect.clone(),
+                        time_added: None,
+                    },
+                    excluded_nodes: spec.excluded_nodes.iter().cloned().collect(),
+                    not_ready_grace: Duration::from_secs(spec.not_ready_grace_seconds),
+                    ready_grace: Duration::from_secs(spec.ready_grace_seconds),
+                    index,
+                })
+            })
+            .collect();
+
+        self.replace(&name, PolicyEntry { generation, gates, tasks });
+    }
+
+    /// Register prebuilt gates under `name`, without watching any pods
+    #[cfg(test)]
+    pub fn insert(&self, name: &str, gates: Vec<Gate>) {
+        let gates = gates.into_iter().map(Arc::new).collect();
+        self.replace(name, PolicyEntry { generation: None, gates, tasks: Vec::new() });
+    }
+
+    pub fn remove(&self, name: &str) {
+        if let Some((_, entry)) = self.policies.remove(name) {
+            tracing::info!("📜 Removing CniReadinessPolicy {}", name);
+            self.retire(&entry.gates);
+            self.resync();
+        }
+    }
+
+    /// Names of the registered CRD policies
+    fn policy_names(&self) -> Vec<String> {
+        self.policies.iter()
+            .map(|e| e.key().clone())
+            .filter(|name| name != ENV_POLICY)
+            .collect()
+    }
+
+    /// Snapshot of all active gates
+    pub fn active(&self) -> Vec<Arc<Gate>> {
+        self.policies.iter()
+            .flat_map(|e| e.gates.clone())
+            .collect()
+    }
+
+    /// Whether every active gate has indexed its initial pod list
+    pub fn synced(&self) -> bool {
+        self.active().iter().all(|g| g.index.is_synced())
+    }
+
+    /// Taint keys to strip because no policy manages them anymore
+    pub fn retired_keys(&self) -> Vec<String> {
+        self.retired.iter().map(|k| k.clone()).collect()
+    }
+
+    fn replace(&self, name: &str, entry: PolicyEntry) {
+        for key in entry.gates.iter().map(|g| &g.taint.key) {
+            self.retired.remove(key);
+        }
+        if let Some(old) = self.policies.insert(name.to_string(), entry) {
+            self.retire(&old.gates);
+        }
+        self.resync();
+    }
+
+    fn retire(&self, gates: &[Arc<Gate>]) {
+        let active: HashSet<String> = self.active().iter().map(|g| g.taint.key.clone()).collect();
+        for gate in gates {
+            if !active.contains(&gate.taint.key) {
+                self.retired.insert(gate.taint.key.clone());
+            }
+        }
+    }
+
+    /// Stop every pod watch, for shutdown. Dropping the entries aborts their index tasks.
+    pub fn stop(&self) {
+        self.policies.clear();
+    }
+
+    /// Ask for every node to be reconciled
+    pub fn resync(&self) {
+        self.triggers.reconcile_all();
+    }
+
+    /// Keep a NodeIndex of ready pods from `source` up to date in the background, one
+    /// watch per namespace and alternative selector.
+    /// A node's readiness flip requeues that node.
+    fn spawn_index(&self, source: &PodSource) -> (NodeIndex, AbortHandle) {
+        // The index is the only consumer, so the events go straight into it without a store
+        let pod_watchers = source.watches().into_iter().enumerate().map(|(stream, (namespace, config))| {
+            let pods_api = match namespace {
+                Some(ns) => Api::namespaced(self.pods_api.clone().into_client(), ns),
+                None => self.pods_api.clone(),
+            };
+            watcher(pods_api, config)
+                .default_backoff()
+                .map(move |res| (stream, res))
+                .boxed()
+        });
+        let pod_watcher = futures::stream::select_all(pod_watchers);
+
+        let node_index = NodeIndex::new(source.clone(), self.pod_checks);
+        let node_index_clone = node_index.clone();
+        let registry = self.clone();
+
+        let task = tokio::spawn(async move {
+            pod_watcher.for_each(|(stream, res)| {
+                let idx = node_index_clone.clone();
+                let registry = registry.clone();
+                async move {
+                    match res {
+                        Ok(event) => {
+                            for node in idx.update_from(stream, &event) {
+                                registry.triggers.reconcile_node(&node);
+                            }
+                        }
+                        Err(e) => tracing::warn!("Pod watcher error: {}", e),
+                    }
+                }
+            }).await;
+        });
+
+        (node_index, task.abort_handle())
+    }
+}
+
+/// Follow `CniReadinessPolicy` objects and keep the registry in sync with them
+pub fn spawn_policy_watcher(client: Client, registry: GateRegistry) {
+    let policies: Api<CniReadinessPolicy> = Api::all(client);
+
+    tokio::spawn(async move {
+        let mut events = watcher(policies, watcher::Config::default()).default_backoff().boxed();
+        // Names seen during a relist, policies missing from it were deleted meanwhile
+        let mut relisted: HashSet<String> = HashSet::new();
+
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(Event::Apply(policy)) => registry.apply(&policy),
+                Ok(Event::Delete(policy)) => registry.remove(&policy.name_any()),
+                Ok(Event::Init) => relisted.clear(),
+                Ok(Event::InitApply(policy)) => {
+                    relisted.insert(policy.name_any());
+                    registry.apply(&policy);
+                }
+                Ok(Event::InitDone) => {
+                    for name in registry.policy_names() {
+                        if !relisted.contains(&name) {
+                            registry.remove(&name);
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!("CniReadinessPolicy watcher error: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn node_flips_coalesce_until_taken() {
+        let triggers = ReconcileTriggers::default();
+        for _ in 0..3 {
+            triggers.reconcile_node("n1");
+        }
+        triggers.reconcile_node("n2");
+
+        let mut requests = triggers.node_requests().boxed();
+        let mut names = vec![requests.next().await.unwrap().name, requests.next().await.unwrap().name];
+        names.sort();
+
+        assert_eq!(names, ["n1", "n2"]);
+        assert!(requests.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn clear_drops_pending_requests() {
+        let triggers = ReconcileTriggers::default();
+        triggers.reconcile_all();
+        triggers.reconcile_node("n1");
+
+        triggers.clear();
+
+        assert!(triggers.all_requests().boxed().next().now_or_never().is_none());
+        assert!(triggers.node_requests().boxed().next().now_or_never().is_none());
+    }
+}
@@ -1,47 +1,342 @@
 use dashmap::DashMap;
 use kube::ResourceExt;
-use std::sync::Arc;
-use k8s_openapi::api::core::v1::Pod;
-use kube::runtime::watcher::Event;
-use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use k8s_openapi::{api::core::v1::Pod, chrono::{DateTime, Utc}};
+use kube::runtime::watcher::{self, Event};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Default)]
+const DAEMONSET_PREFIX: &str = "daemonset/";
+/// Separates alternative label selectors, a pod matching any of them counts
+const SELECTOR_OR: &str = "||";
+
+/// Where a readiness rule finds the CNI pods it waits for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PodSource {
+    /// Pods matching a label selector, or any of several separated by `||`, in the listed
+    /// namespaces or in all of them when there are none
+    Selector { selector: String, namespaces: Vec<String> },
+    /// Pods owned by a DaemonSet, so a too broad selector can't mark nodes ready
+    DaemonSet { namespace: String, name: String },
+}
+
+impl PodSource {
+    /// Parse `daemonset/<namespace>/<name>`, anything else is a label selector
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let Some(ds) = spec.strip_prefix(DAEMONSET_PREFIX) else {
+            return Ok(PodSource::selector(spec));
+        };
+        match ds.split_once('/') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() && !name.contains('/') => {
+                Ok(PodSource::DaemonSet { namespace: namespace.to_string(), name: name.to_string() })
+            }
+            _ => Err(anyhow::anyhow!("Invalid DaemonSet '{}', expected {}<namespace>/<name>", spec, DAEMONSET_PREFIX)),
+        }
+    }
+
+    /// Label selector matching pods in any namespace
+    pub fn selector(selector: &str) -> Self {
+        PodSource::Selector { selector: selector.to_string(), namespaces: Vec::new() }
+    }
+
+    /// Restrict a selector to `namespaces`, DaemonSets have their own
+    pub fn in_namespaces(self, namespaces: &[String]) -> Self {
+        match self {
+            PodSource::Selector { selector, .. } => PodSource::Selector { selector, namespaces: namespaces.to_vec() },
+            ds => ds,
+        }
+    }
+
+    /// One watch per namespace (None for all namespaces) and alternative selector
+    pub fn watches(&self) -> Vec<(Option<&str>, watcher::Config)> {
+        match self {
+            PodSource::Selector { selector, namespaces } => {
+                let alternatives: Vec<&str> = selector.split(SELECTOR_OR).map(str::trim).collect();
+                let namespaces: Vec<Option<&str>> = if namespaces.is_empty() {
+                    vec![None]
+                } else {
+                    namespaces.iter().map(|ns| Some(ns.as_str())).collect()
+                };
+                namespaces.iter()
+                    .flat_map(|ns| alternatives.iter().map(move |sel| (*ns, watcher::Config::default().labels(sel))))
+                    .collect()
+            }
+            PodSource::DaemonSet { namespace, .. } => vec![(Some(namespace.as_str()), watcher::Config::default())],
+        }
+    }
+
+    /// Whether the source would pick the pod, evaluating the selector locally. Only
+    /// equality-based requirements (`k=v`, `k!=v`, `k`, `!k`) are understood, a selector
+    /// with set-based ones never matches.
+    pub fn selects(&self, pod: &Pod) -> bool {
+        let PodSource::Selector { selector, namespaces } = self else {
+            return self.owns(pod);
+        };
+        if !namespaces.is_empty() && !pod.namespace().is_some_and(|ns| namespaces.contains(&ns)) {
+            return false;
+        }
+        selector.split(SELECTOR_OR).any(|alternative| matches_selector(alternative, pod))
+    }
+
+    /// Whether the pod counts for this source (DaemonSets check the ownerReferences)
+    pub fn owns(&self, pod: &Pod) -> bool {
+        match self {
+            PodSource::Selector { .. } => true,
+            PodSource::DaemonSet { name, .. } => pod.owner_references().iter()
+                .any(|o| o.kind == "DaemonSet" && &o.name == name),
+        }
+    }
+}
+
+fn matches_selector(selector: &str, pod: &Pod) -> bool {
+    let labels = pod.labels();
+    selector.split(',').map(str::trim).filter(|r| !r.is_empty()).all(|requirement| {
+        if requirement.contains('(') {
+            false
+        } else if let Some((key, value)) = requirement.split_once("!=") {
+            labels.get(key.trim()).map(String::as_str) != Some(value.trim())
+        } else if let Some((key, value)) = requirement.split_once('=') {
+            let value = value.trim_start_matches('=');
+            labels.get(key.trim()).map(String::as_str) == Some(value.trim())
+        } else if let Some(key) = requirement.strip_prefix('!') {
+            !labels.contains_key(key.trim())
+        } else {
+            labels.contains_key(requirement)
+        }
+    })
+}
+
+impl fmt::Display for PodSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PodSource::Selector { selector, namespaces } if namespaces.is_empty() => f.write_str(selector),
+            PodSource::Selector { selector, namespaces } => write!(f, "{} in {}", selector, namespaces.join(",")),
+            PodSource::DaemonSet { namespace, name } => write!(f, "{}{}/{}", DAEMONSET_PREFIX, namespace, name),
+        }
+    }
+}
+
+/// A pod restarting this often is not ready, even while it reports Ready between crashes.
+#[derive(Clone, Copy, Debug)]
+pub struct CrashLoopLimit {
+    pub restarts: usize,
+    pub window: Duration,
+}
+
+/// Checks a Ready pod must pass on top of its Ready condition to count.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PodChecks {
+    pub crash_loop: Option<CrashLoopLimit>,
+    /// Pods whose containers (re)started more recently than this don't count yet
+    pub min_age: Duration,
+}
+
+#[derive(Clone)]
 pub struct NodeIndex {
+    source: PodSource,
+    checks: PodChecks,
+    // Map: Pod UID -> (Restart count last seen, When it grew, within the window)
+    restarts: Arc<DashMap<String, (i32, VecDeque<Instant>)>>,
+    // Map: Pod UID -> Start of its most recently started container
+    started: Arc<DashMap<String, DateTime<Utc>>>,
     // Map: NodeName -> Set of "Ready" Pod UIDs
     ready_pods: Arc<DashMap<String, HashSet<String>>>,
+    // One per watch of the source, see PodSource::watches
+    streams: usize,
+    // Streams whose initial pod list has been processed
+    synced: Arc<Mutex<HashSet<usize>>>,
+    // Map: Stream -> Pod UIDs seen since its watcher started a relist
+    relisted: Arc<Mutex<HashMap<usize, HashSet<String>>>>,
+    // Map: Pod UID -> Stream that reported it
+    origins: Arc<DashMap<String, usize>>,
 }
 
 impl NodeIndex {
-    pub fn new() -> Self {
+    pub fn new(source: PodSource, checks: PodChecks) -> Self {
         Self {
+            streams: source.watches().len(),
+            source,
+            checks,
+            restarts: Arc::new(DashMap::new()),
+            started: Arc::new(DashMap::new()),
             ready_pods: Arc::new(DashMap::new()),
+            synced: Arc::new(Mutex::new(HashSet::new())),
+            relisted: Arc::new(Mutex::new(HashMap::new())),
+            origins: Arc::new(DashMap::new()),
         }
     }
 
-    /// Check if a node has at least one ready Multus pod (O(1))
+    /// Whether the initial pod list of every watch has been indexed
+    pub fn is_synced(&self) -> bool {
+        self.synced.lock().unwrap().len() >= self.streams
+    }
+
+    /// Check if a node has at least one ready pod of the rule's CNI daemon that is not crash
+    /// looping and old enough
     pub fn is_node_ready(&self, node_name: &str) -> bool {
         if let Some(set) = self.ready_pods.get(node_name) {
-            !set.is_empty()
+            set.iter().any(|uid| !self.is_crash_looping(uid) && self.too_young_for(uid).is_none())
         } else {
             false
         }
     }
 
-    /// Process a watcher event to update the index
-    pub fn update(&self, event: &Event<Pod>) {
+    /// When the node only has ready pods that are too young, how long until the first
+    /// of them is old enough
+    pub fn ready_in(&self, node_name: &str) -> Option<Duration> {
+        if self.is_node_ready(node_name) {
+            return None;
+        }
+        let set = self.ready_pods.get(node_name)?;
+        set.iter()
+            .filter(|uid| !self.is_crash_looping(uid))
+            .filter_map(|uid| self.too_young_for(uid))
+            .min()
+    }
+
+    /// How much longer the pod's containers must run before it counts, None when they
+    /// have run for `min_age` already
+    fn too_young_for(&self, uid: &str) -> Option<Duration> {
+        if self.checks.min_age.is_zero() {
+            return None;
+        }
+        let started = *self.started.get(uid)?;
+        let age = (Utc::now() - started).to_std().unwrap_or_default();
+        self.checks.min_age.checked_sub(age).filter(|left| !left.is_zero())
+    }
+
+    /// Remember when the pod's youngest running container started
+    fn track_start(&self, uid: &str, pod: &Pod) {
+        if self.checks.min_age.is_zero() {
+            return;
+        }
+        let started = pod.status.as_ref()
+            .and_then(|s| s.container_statuses.as_ref())
+            .and_then(|cs| cs.iter()
+                .filter_map(|c| c.state.as_ref()?.running.as_ref()?.started_at.as_ref())
+                .map(|t| t.0)
+                .max());
+        match started {
+            Some(started) => { self.started.insert(uid.to_string(), started); },
+            None => { self.started.remove(uid); },
+        }
+    }
+
+    /// Whether the pod restarted at least `restarts` times within the last `window`. Old
+    /// restarts age out on their own, the next reconcile sees the pod ready again.
+    fn is_crash_looping(&self, uid: &str) -> bool {
+        let (Some(limit), Some(restarts)) = (self.checks.crash_loop, self.restarts.get(uid)) else {
+            return false;
+        };
+        let now = Instant::now();
+        restarts.1.iter().filter(|at| now.duration_since(**at) < limit.window).count() >= limit.restarts
+    }
+
+    /// Remember when the pod's restart count grew. Restarts from before the pod was first
+    /// seen are unknown in time and not counted.
+    fn track_restarts(&self, uid: &str, pod: &Pod) {
+        let Some(limit) = self.checks.crash_loop else {
+            return;
+        };
+        let count: i32 = pod.status.as_ref()
+            .and_then(|s| s.container_statuses.as_ref())
+            .map(|cs| cs.iter().map(|c| c.restart_count).sum())
+            .unwrap_or(0);
+
+        let now = Instant::now();
+        let mut entry = self.restarts.entry(uid.to_string()).or_insert((count, VecDeque::new()));
+        let (seen, at) = &mut *entry;
+        for _ in 0..count.saturating_sub(*seen).min(limit.restarts as i32) {
+            at.push_back(now);
+        }
+        *seen = count;
+        while at.len() > limit.restarts || at.front().is_some_and(|t| now.duration_since(*t) >= limit.window) {
+            at.pop_front();
+        }
+    }
+
+    /// Ready pod UIDs per node, for debugging
+    pub fn snapshot(&self) -> BTreeMap<String, Vec<String>> {
+        self.ready_pods.iter()
+            .map(|e| (e.key().clone(), e.value().iter().cloned().collect()))
+            .collect()
+    }
+
+    /// Process an event of the `stream`th watch to update the index, returns the nodes whose
+    /// readiness changed: the pod's node, or at the end of a relist those left without pods
+    pub fn update_from(&self, stream: usize, event: &Event<Pod>) -> Vec<String> {
+        let node_name = match event {
+            Event::Apply(pod) | Event::Delete(pod) | Event::InitApply(pod) => {
+                pod.spec.as_ref().and_then(|s| s.node_name.clone())
+            }
+            Event::Init | Event::InitDone => None,
+        };
+        let was_ready = node_name.as_deref().map(|n| self.is_node_ready(n));
+
         match event {
-            Event::Apply(pod) => self.handle_pod(pod),
-            Event::Delete(pod) => self.handle_pod_delete(pod),
-            Event::InitApply(pod) => self.handle_pod(pod),
+            Event::Apply(pod) => {
+                if let Some(uid) = pod.uid() {
+                    self.origins.insert(uid, stream);
+                }
+                self.handle_pod(pod)
+            }
+            Event::Delete(pod) => {
+                if let Some(uid) = pod.uid() {
+                    self.origins.remove(&uid);
+                }
+                self.handle_pod_delete(pod)
+            }
+            Event::InitApply(pod) => {
+                if let Some(uid) = pod.uid() {
+                    if let Some(seen) = self.relisted.lock().unwrap().get_mut(&stream) {
+                        seen.insert(uid.clone());
+                    }
+                    self.origins.insert(uid, stream);
+                }
+                self.handle_pod(pod)
+            }
             Event::Init => {
-                // Initial sync handled by individual InitApply calls
-                // If reflector restarts, we might want to clear, but reflector handles diffs.
+                self.relisted.lock().unwrap().insert(stream, HashSet::new());
             },
-            Event::InitDone => {},
+            Event::InitDone => {
+                self.synced.lock().unwrap().insert(stream);
+                // Pods deleted while the watch was down never get a Delete event
+                return self.prune_unseen(stream);
+            },
+        }
+
+        match (node_name, was_ready) {
+            (Some(node), Some(was_ready)) if self.is_node_ready(&node) != was_ready => vec![node],
+            _ => Vec::new(),
         }
     }
 
+    /// Drop the stream's pods missing from its relist, returns the nodes that lost their
+    /// last ready pod
+    fn prune_unseen(&self, stream: usize) -> Vec<String> {
+        let Some(seen) = self.relisted.lock().unwrap().remove(&stream) else {
+            return Vec::new();
+        };
+        self.origins.retain(|uid, origin| *origin != stream || seen.contains(uid));
+
+        let mut changed = Vec::new();
+        for mut entry in self.ready_pods.iter_mut() {
+            let was_ready = !entry.is_empty();
+            entry.retain(|uid| self.origins.contains_key(uid));
+            if was_ready && entry.is_empty() {
+                changed.push(entry.key().clone());
+            }
+        }
+        self.restarts.retain(|uid, _| self.origins.contains_key(uid));
+        self.started.retain(|uid, _| self.origins.contains_key(uid));
+        changed
+    }
+
     fn handle_pod(&self, pod: &Pod) {
+        if !self.source.owns(pod) {
+            return;
+        }
         let node_name = match pod.spec.as_ref().and_then(|s| s.node_name.as_deref()) {
             Some(n) => n.to_string(),
             None => return, // Pod not assigned to a node yet
@@ -52,6 +347,8 @@ impl NodeIndex {
             None => return,
         };
 
+        self.track_restarts(&uid, pod);
+        self.track_start(&uid, pod);
         let is_ready = self.check_pod_readiness(pod);
 
         if is_ready {
@@ -84,9 +381,25 @@ impl NodeIndex {
         if let Some(mut set) = self.ready_pods.get_mut(&node_name) {
             set.remove(&uid);
         }
+        self.restarts.remove(&uid);
+        self.started.remove(&uid);
     }
     
+    /// Running with a passing readiness probe, not shutting down and not backing off a crash:
+    /// a terminating pod keeps its Ready condition until the kubelet notices
     fn check_pod_readiness(&self, pod: &Pod) -> bool {
+        if pod.metadata.deletion_timestamp.is_some() {
+            return false;
+        }
+        let backing_off = pod.status.as_ref()
+            .and_then(|s| s.container_statuses.as_ref())
+            .is_some_and(|cs| cs.iter().any(|c| {
+                c.state.as_ref().and_then(|s| s.waiting.as_ref())
+                    .is_some_and(|w| w.reason.as_deref() == Some("CrashLoopBackOff"))
+            }));
+        if backing_off {
+            return false;
+        }
         let phase_running = pod.status.as_ref().map(|s| s.phase.as_deref() == Some("Running")).unwrap_or(false);
         let conditions_ready = pod.status.as_ref().and_then(|s| s.conditions.as_ref()).map(|conds| {
             conds.iter().any(|c| c.type_ == "Ready" && c.status == "True")
@@ -94,3 +407,41 @@ impl NodeIndex {
         phase_running && conditions_ready
     }
 }
+
+/// Remembers since when a node's taint/label has been out of date, so a change is only
+/// applied once the new readiness held for the grace period.
+#[derive(Clone, Default)]
+pub struct Debouncer {
+    // Map: (NodeName, Key) -> (Wanted state, Since)
+    pending: Arc<DashMap<(String, String), (bool, Instant)>>,
+}
+
+impl Debouncer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `None` when `want` has held for `grace` and may be applied now,
+    /// otherwise the time left to wait
+    pub fn check(&self, node_name: &str, key: &str, want: bool, grace: Duration) -> Option<Duration> {
+        let k = (node_name.to_string(), key.to_string());
+        if grace.is_zero() {
+            self.pending.remove(&k);
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut entry = self.pending.entry(k).or_insert((want, now));
+        // Readiness flipped back before the grace ran out, start over
+        if entry.0 != want {
+            *entry = (want, now);
+        }
+
+        grace.checked_sub(now.duration_since(entry.1)).filter(|left| !left.is_zero())
+    }
+
+    /// Forget the pending change once the node is in the wanted state
+    pub fn clear(&self, node_name: &str, key: &str) {
+        self.pending.remove(&(node_name.to_string(), key.to_string()));
+    }
+}
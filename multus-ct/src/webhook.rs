@@ -0,0 +1,114 @@
+use k8s_openapi::api::core::v1::{Pod, Toleration};
+use kube::core::{
+    admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+    DynamicObject,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::policy::Gate;
+
+/// Answer a pod admission review: CNI pods picked by a gate get a toleration for its taint,
+/// so the taint never keeps out the pods that would lift it.
+pub fn mutate(review: AdmissionReview<Pod>, gates: &[Arc<Gate>]) -> AdmissionReview<DynamicObject> {
+    let request: AdmissionRequest<Pod> = match review.try_into() {
+        Ok(request) => request,
+        Err(e) => return AdmissionResponse::invalid(e.to_string()).into_review(),
+    };
+    let response = AdmissionResponse::from(&request);
+    let Some(mut pod) = request.object.clone() else {
+        return response.into_review();
+    };
+    // Pods being created may not carry their namespace yet, selectors can be namespaced
+    if pod.metadata.namespace.is_none() {
+        pod.metadata.namespace = request.namespace.clone();
+    }
+
+    let missing: Vec<Toleration> = missing_tolerations(&pod, gates);
+    if missing.is_empty() {
+        return response.into_review();
+    }
+
+    let has_tolerations = pod.spec.as_ref().is_some_and(|s| s.tolerations.is_some());
+    let ops: Vec<_> = if has_tolerations {
+        missing.iter().map(|t| json!({ "op": "add", "path": "/spec/tolerations/-", "value": t })).collect()
+    } else {
+        vec![json!({ "op": "add", "path": "/spec/tolerations", "value": missing })]
+    };
+
+    tracing::info!("💉 Adding {} toleration(s) to pod {}/{}", missing.len(),
+        request.namespace.as_deref().unwrap_or_default(), request.name);
+    let patch = serde_json::from_value(json!(ops)).expect("valid JSON patch");
+    match response.with_patch(patch) {
+        Ok(response) => response.into_review(),
+        Err(e) => AdmissionResponse::invalid(e.to_string()).into_review(),
+    }
+}
+
+/// Tolerations for the taints of every gate picking the pod, unless it tolerates them already
+fn missing_tolerations(pod: &Pod, gates: &[Arc<Gate>]) -> Vec<Toleration> {
+    let existing = pod.spec.as_ref().and_then(|s| s.tolerations.clone()).unwrap_or_default();
+    let tolerated = |key: &str| existing.iter().any(|t| {
+        t.key.as_deref() == Some(key) || (t.key.is_none() && t.operator.as_deref() == Some("Exists"))
+    });
+
+    let mut missing: Vec<Toleration> = Vec::new();
+    for gate in gates.iter().filter(|g| g.source.selects(pod)) {
+        let key = gate.taint.key.as_str();
+        if !tolerated(key) && !missing.iter().any(|t| t.key.as_deref() == Some(key)) {
+            missing.push(Toleration {
+                key: Some(key.to_string()),
+                operator: Some("Exists".to_string()),
+                ..Default::default()
+            });
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::Taint;
+    use std::{collections::HashSet, time::Duration};
+
+    use super::*;
+    use crate::state::{NodeIndex, PodChecks, PodSource};
+
+    fn gate(selector: &str, key: &str) -> Arc<Gate> {
+        let source = PodSource::selector(selector);
+        Arc::new(Gate {
+            policy: "test".to_string(),
+            source: source.clone(),
+            taint: Taint { key: key.to_string(), effect: "NoSchedule".to_string(), ..Default::default() },
+            excluded_nodes: HashSet::new(),
+            not_ready_grace: Duration::ZERO,
+            ready_grace: Duration::ZERO,
+            index: NodeIndex::new(source, PodChecks::default()),
+        })
+    }
+
+    fn pod(labels: serde_json::Value, tolerations: serde_json::Value) -> Pod {
+        serde_json::from_value(json!({
+            "metadata": { "name": "p", "labels": labels },
+            "spec": { "containers": [], "tolerations": tolerations },
+        })).unwrap()
+    }
+
+    #[test]
+    fn tolerates_taints_of_matching_gates_only() {
+        let gates = [gate("app=multus", "multus-not-ready"), gate("app=whereabouts", "ipam-not-ready")];
+        let missing = missing_tolerations(&pod(json!({ "app": "multus" }), json!([])), &gates);
+
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].key.as_deref(), Some("multus-not-ready"));
+    }
+
+    #[test]
+    fn keeps_existing_tolerations() {
+        let gates = [gate("app=multus", "multus-not-ready")];
+        let tolerations = json!([{ "key": "multus-not-ready", "operator": "Exists" }]);
+        let missing = missing_tolerations(&pod(json!({ "app": "multus" }), tolerations), &gates);
+
+        assert!(missing.is_empty());
+    }
+}
@@ -0,0 +1,349 @@
+use std::{net::Ipv4Addr, str::FromStr};
+use anyhow::{Result, anyhow};
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use crate::gateway::FALLBACK_GATEWAY;
+use crate::logging::LogTime;
+
+// ProtonVPN's NAT-PMP server ignores the internal port, 0 is what its docs use
+const PROTON_INTERNAL_PORT: u16 = 0;
+// Public port 1 asks for "any port"; Proton hands out a random high port, the same for TCP and UDP
+const PROTON_PUBLIC_PORT: u16 = 1;
+// Proton grants at most 60s no matter what is requested
+const PROTON_LIFETIME: u32 = 60;
+// Landing on another VPN server resets the epoch, probe often enough to remap within a lifetime
+const PROTON_EPOCH_CHECK_INTERVAL: u64 = 15;
+
+/// What to do when NAT-PMP hands out different public ports for TCP and UDP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortPolicy {
+    /// Re-request both mappings until the ports match, skip the qBittorrent update otherwise
+    Retry,
+    /// Use the TCP port (historical behavior)
+    PreferTcp,
+    /// Use the UDP port
+    PreferUdp,
+}
+
+impl FromStr for PortPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "retry" => Ok(PortPolicy::Retry),
+            "tcp" => Ok(PortPolicy::PreferTcp),
+            "udp" => Ok(PortPolicy::PreferUdp),
+            other => Err(anyhow!("Invalid PORT_MISMATCH_POLICY '{}' (expected retry, tcp or udp)", other)),
+        }
+    }
+}
+
+/// Which protocols get a mapping on the gateway.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocols {
+    Tcp,
+    Udp,
+    Both,
+}
+
+impl Protocols {
+    pub fn tcp(self) -> bool {
+        self != Protocols::Udp
+    }
+
+    pub fn udp(self) -> bool {
+        self != Protocols::Tcp
+    }
+}
+
+impl FromStr for Protocols {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Protocols::Tcp),
+            "udp" => Ok(Protocols::Udp),
+            "both" => Ok(Protocols::Both),
+            other => Err(anyhow!("Invalid MAP_PROTOCOLS '{}' (expected tcp, udp or both)", other)),
+        }
+    }
+}
+
+/// Bundled settings for a known VPN provider.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    ProtonVpn,
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "protonvpn" | "proton" => Ok(Preset::ProtonVpn),
+            other => Err(anyhow!("Invalid PRESET '{}' (expected protonvpn)", other)),
+        }
+    }
+}
+
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub config: Config,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Keep the mappings alive and the torrent client in sync (default)
+    Daemon,
+    /// Run under the Windows service control manager
+    #[cfg(windows)]
+    Service,
+    /// Run a single mapping cycle and print the granted ports as JSON
+    MapOnce,
+    /// Query the state endpoint of a running daemon
+    Status {
+        #[arg(long, env = "PNP_STATUS_URL", default_value = "http://127.0.0.1:9090/state")]
+        url: String,
+    },
+}
+
+#[derive(Args, Clone)]
+pub struct Config {
+    /// Provider preset applied on top of the other settings: protonvpn
+    #[arg(long, env = "PRESET")]
+    pub preset: Option<Preset>,
+
+    /// NAT-PMP gateways (comma separated), auto-detected from the default route when unset.
+    /// The torrent client follows the first one.
+    #[arg(long = "gateway", env = "NATPMP_GATEWAY", value_delimiter = ',')]
+    pub gateways: Vec<Ipv4Addr>,
+
+    // Proton expects internal port 0 and public port 1
+    #[arg(long, env = "INTERNAL_PORT", default_value_t = 0)]
+    pub internal_port: u16,
+
+    #[arg(long, env = "PUBLIC_PORT", default_value_t = 1)]
+    pub public_port: u16,
+
+    /// Protocols to map: tcp, udp or both
+    #[arg(long, env = "MAP_PROTOCOLS", default_value = "both")]
+    pub protocols: Protocols,
+
+    /// Per-protocol overrides of INTERNAL_PORT/PUBLIC_PORT, e.g. to forward UDP to another service
+    #[arg(long, env = "TCP_INTERNAL_PORT")]
+    pub tcp_internal_port: Option<u16>,
+
+    #[arg(long, env = "TCP_PUBLIC_PORT")]
+    pub tcp_public_port: Option<u16>,
+
+    #[arg(long, env = "UDP_INTERNAL_PORT")]
+    pub udp_internal_port: Option<u16>,
+
+    #[arg(long, env = "UDP_PUBLIC_PORT")]
+    pub udp_public_port: Option<u16>,
+
+    #[arg(long, env = "MAPPING_LIFETIME", default_value_t = 60)]
+    pub lifetime: u32,
+
+    /// Upper bound between refreshes, in seconds
+    #[arg(long, env = "REFRESH_INTERVAL", default_value_t = 30)]
+    pub refresh_interval: u64,
+
+    /// Fraction of the granted lifetime after which the mapping is refreshed
+    #[arg(long, env = "LIFETIME_REFRESH_RATIO", default_value_t = 0.5)]
+    pub lifetime_refresh_ratio: f64,
+
+    /// Which client follows the forwarded port: qbittorrent or rtorrent
+    #[arg(long, env = "TORRENT_CLIENT", default_value = "qbittorrent")]
+    pub torrent_client: String,
+
+    #[arg(long, env = "QBITTORRENT_HOST", default_value = "http://127.0.0.1")]
+    pub qbittorrent_host: String,
+
+    #[arg(long, env = "QBITTORRENT_PORT", default_value_t = 8080)]
+    pub qbittorrent_port: u16,
+
+    /// PEM bundle used to verify a self-signed qBittorrent certificate
+    #[arg(long, env = "QBITTORRENT_CA_CERT")]
+    pub qbittorrent_ca_cert: Option<String>,
+
+    #[arg(long, env = "QBITTORRENT_INSECURE_SKIP_VERIFY", default_value = "false", value_parser = clap::value_parser!(bool), action = ArgAction::Set)]
+    pub qbittorrent_insecure_skip_verify: bool,
+
+    /// `scgi://host:port` or `http(s)://.../RPC2`
+    #[arg(long, env = "RTORRENT_URL", default_value = "scgi://127.0.0.1:5000")]
+    pub rtorrent_url: String,
+
+    /// Turn off qBittorrent's own UPnP/NAT-PMP and random port, which fight with us
+    #[arg(long, env = "FIX_CONFLICTING_SETTINGS", default_value = "false", value_parser = clap::value_parser!(bool), action = ArgAction::Set)]
+    pub fix_conflicting_settings: bool,
+
+    /// Enforce qBittorrent's max_connec when fixing conflicting settings
+    #[arg(long, env = "QBITTORRENT_MAX_CONNECTIONS")]
+    pub qbittorrent_max_connections: Option<i64>,
+
+    /// Reannounce torrents after the listen port changed
+    #[arg(long, env = "REANNOUNCE", default_value = "true", value_parser = clap::value_parser!(bool), action = ArgAction::Set)]
+    pub reannounce: bool,
+
+    /// qBittorrent torrents to reannounce: "all" or hashes separated by `|`
+    #[arg(long, env = "REANNOUNCE_HASHES", default_value = "all")]
+    pub reannounce_hashes: String,
+
+    #[arg(long, env = "PORT_MISMATCH_POLICY", default_value = "tcp")]
+    pub port_policy: PortPolicy,
+
+    #[arg(long, env = "PORT_MISMATCH_RETRIES", default_value_t = 3)]
+    pub mismatch_retries: u32,
+
+    /// ntfy topic or Telegram sendMessage URL notified of port changes and failures
+    #[arg(long, env = "NOTIFY_URL")]
+    pub notify_url: Option<String>,
+
+    /// Shell command run with the new port whenever it changes
+    #[arg(long, env = "ON_PORT_CHANGE_CMD")]
+    pub on_port_change_cmd: Option<String>,
+
+    #[arg(long, env = "ON_PORT_CHANGE_TIMEOUT", default_value_t = 30)]
+    pub on_port_change_timeout: u64,
+
+    /// Seconds between epoch probes used to detect gateway restarts between refreshes, 0 disables
+    #[arg(long, env = "EPOCH_CHECK_INTERVAL", default_value_t = 0)]
+    pub epoch_check_interval: u64,
+
+    /// Granted lifetime in seconds below which the tighter LOW_LIFETIME_REFRESH schedule is used, 0 disables
+    #[arg(long, env = "LOW_LIFETIME_THRESHOLD", default_value_t = 30)]
+    pub low_lifetime_threshold: u64,
+
+    /// Seconds between refreshes (minus jitter) while lifetimes are low
+    #[arg(long, env = "LOW_LIFETIME_REFRESH", default_value_t = 10)]
+    pub low_lifetime_refresh: u64,
+
+    /// Pause active torrents after this many consecutive failed cycles and resume them
+    /// once forwarding is restored, 0 disables
+    #[arg(long, env = "PAUSE_AFTER_FAILURES", default_value_t = 0)]
+    pub pause_after_failures: u32,
+
+    /// Only pause torrents in this qBittorrent category
+    #[arg(long, env = "PAUSE_CATEGORY")]
+    pub pause_category: Option<String>,
+
+    /// Minutes after a port change within which the torrent client must report incoming
+    /// connections before an alert is sent, 0 disables the check
+    #[arg(long, env = "CONNECTIVITY_CHECK_TIMEOUT", default_value_t = 0)]
+    pub connectivity_check_timeout: u64,
+
+    /// WireGuard interface to watch; a reconnect or peer endpoint change triggers an immediate remap
+    #[arg(long, env = "WATCH_INTERFACE")]
+    pub watch_interface: Option<String>,
+
+    /// Seconds between polls of WATCH_INTERFACE
+    #[arg(long, env = "INTERFACE_CHECK_INTERVAL", default_value_t = 5)]
+    pub interface_check_interval: u64,
+
+    /// First delay after a failed refresh cycle, doubled on each further failure
+    #[arg(long, env = "BACKOFF_INITIAL", default_value_t = 5)]
+    pub backoff_initial: u64,
+
+    #[arg(long, env = "BACKOFF_MAX", default_value_t = 300)]
+    pub backoff_max: u64,
+
+    /// Rolling window in seconds for the port-forwarding availability gauge
+    #[arg(long, env = "AVAILABILITY_WINDOW", default_value_t = 3600)]
+    pub availability_window: u64,
+
+    /// Log timestamp format: rfc3339 (UTC) or local
+    #[arg(long, env = "LOG_TIME_FORMAT", default_value = "rfc3339")]
+    pub log_time: LogTime,
+
+    /// Emit one JSON object per log line instead of plain text
+    #[arg(long, env = "LOG_JSON", default_value = "false", value_parser = clap::value_parser!(bool), action = ArgAction::Set)]
+    pub log_json: bool,
+
+    /// Identifies this instance among several pnp sidecars behind one gateway. Refreshes are
+    /// staggered by a jitter derived from it, and a trailing number (`pnp-2`) offsets
+    /// non-zero internal ports so each instance maps its own device port.
+    #[arg(long, env = "INSTANCE_ID")]
+    pub instance_id: Option<String>,
+
+    /// Port of the HTTP server exposing `GET /state`, `/health` and `/metrics`
+    #[arg(long, env = "STATE_PORT", default_value_t = 9090)]
+    pub state_port: u16,
+}
+
+impl Config {
+    /// Apply the selected preset. Provider-mandated values are forced, the gateway and
+    /// epoch probing only fill in what was left unset.
+    pub fn apply_preset(&mut self) {
+        match self.preset {
+            Some(Preset::ProtonVpn) => {
+                if self.gateways.is_empty() {
+                    self.gateways = vec![FALLBACK_GATEWAY];
+                }
+                self.internal_port = PROTON_INTERNAL_PORT;
+                self.public_port = PROTON_PUBLIC_PORT;
+                self.tcp_internal_port = None;
+                self.tcp_public_port = None;
+                self.udp_internal_port = None;
+                self.udp_public_port = None;
+                self.lifetime = PROTON_LIFETIME;
+                if self.epoch_check_interval == 0 {
+                    self.epoch_check_interval = PROTON_EPOCH_CHECK_INTERVAL;
+                }
+            }
+            None => {}
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(self.lifetime_refresh_ratio > 0.0 && self.lifetime_refresh_ratio <= 1.0) {
+            anyhow::bail!("LIFETIME_REFRESH_RATIO must be in (0, 1], got {}", self.lifetime_refresh_ratio);
+        }
+
+        Ok(())
+    }
+
+    /// Internal and public port requested for TCP
+    pub fn tcp_ports(&self) -> (u16, u16) {
+        let internal = self.tcp_internal_port.unwrap_or(self.internal_port);
+        (self.instance_port(internal), self.tcp_public_port.unwrap_or(self.public_port))
+    }
+
+    /// Internal and public port requested for UDP
+    pub fn udp_ports(&self) -> (u16, u16) {
+        let internal = self.udp_internal_port.unwrap_or(self.internal_port);
+        (self.instance_port(internal), self.udp_public_port.unwrap_or(self.public_port))
+    }
+
+    /// Stable fraction in [0, 1) derived from INSTANCE_ID (FNV-1a), 0 without one
+    pub fn instance_jitter(&self) -> f64 {
+        let Some(id) = &self.instance_id else {
+            return 0.0;
+        };
+
+        let hash = id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        (hash % 10_000) as f64 / 10_000.0
+    }
+
+    /// Trailing number of INSTANCE_ID, e.g. 2 for a StatefulSet pod `pnp-2`
+    fn instance_ordinal(&self) -> Option<u16> {
+        let id = self.instance_id.as_deref()?;
+        let digits = id.len() - id.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        id[id.len() - digits..].parse().ok()
+    }
+
+    /// Offset a device port by the instance ordinal. Port 0 (let the gateway pick) is kept.
+    fn instance_port(&self, internal: u16) -> u16 {
+        match self.instance_ordinal() {
+            Some(ordinal) if internal != 0 => internal.saturating_add(ordinal),
+            _ => internal,
+        }
+    }
+}
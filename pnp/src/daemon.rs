@@ -0,0 +1,803 @@
+use std::{future::Future, net::SocketAddr, sync::{Arc, RwLock}, time::{Duration, Instant}};
+use anyhow::Result;
+use chrono::Utc;
+use tokio_retry::strategy::jitter;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::config::Config;
+use crate::gateway::{self, DefaultRoute};
+use crate::hook::PortChangeHook;
+use crate::mapping::{GatewayMapper, PortMapper, Ports};
+use crate::metrics::{
+    AvailabilityWindow, CONNECTIVITY_CHECK_FAILURES, MAPPING_DURATION, MAPPING_FAILURES, TORRENT_API_DURATION,
+    TORRENT_CONNECTABLE, TUNNEL_IPV6,
+};
+use crate::notify::Notifier;
+use crate::server;
+use crate::state::{DaemonState, SharedState};
+use crate::torrent::{self, TorrentClient};
+use crate::tunnel::TunnelWatcher;
+
+// Never refresh more often than this, even if the gateway grants a tiny lifetime
+const MIN_REFRESH: Duration = Duration::from_secs(5);
+
+// Share of each refresh interval taken off according to the instance jitter, so
+// instances with different INSTANCE_IDs drift apart instead of refreshing in lockstep
+const INSTANCE_STAGGER: f64 = 0.1;
+
+// Up to this share of LOW_LIFETIME_REFRESH is randomly taken off each tight refresh
+const LOW_LIFETIME_JITTER: f64 = 0.2;
+
+/// Keep the mappings alive and the torrent client in sync until `shutdown` resolves
+pub async fn run(config: Config, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let mut daemon = Daemon::new(config)?;
+
+    server::spawn(daemon.state.clone(), SocketAddr::from(([0, 0, 0, 0], daemon.config.state_port)));
+
+    // Main loop with shutdown support
+    tokio::select! {
+        _ = daemon.run_loop() => {},
+        _ = shutdown => {
+            info!("Graceful shutdown complete.");
+        }
+    }
+
+    Ok(())
+}
+
+struct Daemon {
+    config: Config,
+    // The torrent client follows the first gateway, the others only keep their mappings alive
+    mappers: Vec<Box<dyn PortMapper>>,
+    // Set when the gateway was auto-detected, re-checked every cycle
+    route: Option<DefaultRoute>,
+    notifier: Notifier,
+    torrent: Box<dyn TorrentClient>,
+    hook: PortChangeHook,
+    tunnel: Option<TunnelWatcher>,
+    state: SharedState,
+    availability: AvailabilityWindow,
+    last_port: Option<u16>,
+    // Consecutive failed cycles
+    failures: u32,
+    // Torrents paused while forwarding is down, resumed on recovery
+    paused: Option<Vec<String>>,
+    // Whether the gateway currently grants lifetimes below LOW_LIFETIME_THRESHOLD
+    low_lifetime: bool,
+    // Set on a port change until the torrent client reports incoming connections
+    port_changed_at: Option<Instant>,
+}
+
+impl Daemon {
+    fn new(config: Config) -> Result<Self> {
+        // Without NATPMP_GATEWAY, follow the default route and re-detect it every cycle
+        let (gateways, route) = gateway::resolve(&config.gateways)?;
+        if let Some(detected) = &route {
+            info!(
+                "Detected default gateway {} via {}",
+                detected.gateway,
+                detected.interface
+            );
+        }
+
+        let mappers = gateways.into_iter()
+            .map(|gw| GatewayMapper::new(gw).map(|m| Box::new(m) as Box<dyn PortMapper>))
+            .collect::<Result<Vec<_>>>()?;
+        let torrent = torrent::from_config(&config)?;
+
+        Ok(Self::with_parts(config, mappers, route, torrent))
+    }
+
+    /// Assemble a daemon from already built mappers and torrent client
+    fn with_parts(
+        config: Config,
+        mappers: Vec<Box<dyn PortMapper>>,
+        route: Option<DefaultRoute>,
+        torrent: Box<dyn TorrentClient>,
+    ) -> Self {
+        let names: Vec<String> = mappers.iter().map(|m| m.gateway().to_string()).collect();
+        let state = Arc::new(RwLock::new(DaemonState::new(config.instance_id.clone(), &names, torrent.name())));
+
+        info!(
+            "Starting NAT-PMP refresher for gateway(s) {}",
+            names.join(", ")
+        );
+
+        let availability = AvailabilityWindow::new(Duration::from_secs(config.availability_window));
+        let tunnel = config.watch_interface.clone().map(|interface| {
+            TunnelWatcher::new(interface, Duration::from_secs(config.interface_check_interval.max(1)))
+        });
+
+        Self {
+            notifier: Notifier::new(config.notify_url.clone()),
+            hook: PortChangeHook::new(
+                config.on_port_change_cmd.clone(),
+                Duration::from_secs(config.on_port_change_timeout),
+            ),
+            config,
+            mappers,
+            route,
+            torrent,
+            tunnel,
+            state,
+            availability,
+            last_port: None,
+            failures: 0,
+            paused: None,
+            low_lifetime: false,
+            port_changed_at: None,
+        }
+    }
+
+    async fn run_loop(&mut self) {
+        // Instances started together begin at different points of the refresh interval
+        let mut next_refresh = Duration::from_secs(self.config.refresh_interval).mul_f64(self.config.instance_jitter());
+        if let Some(id) = &self.config.instance_id {
+            info!(
+                "Instance {} starts refreshing in {}ms",
+                id,
+                next_refresh.as_millis()
+            );
+        }
+
+        let mut cycle_id: u64 = 0;
+        loop {
+            self.wait_for_next_cycle(next_refresh).await;
+
+            // Every line logged during a cycle carries its ID
+            cycle_id += 1;
+            next_refresh = self.run_cycle().instrument(info_span!("cycle", id = cycle_id)).await;
+
+            info!(
+                "Next refresh in {}s",
+                next_refresh.as_secs()
+            );
+        }
+    }
+
+    /// Run one cycle and record its outcome, returns the delay until the next one
+    async fn run_cycle(&mut self) -> Duration {
+        // Never give up: back off while the gateway is unreachable and report unhealthy
+        let result = self.cycle().await;
+        self.availability.record(result.is_ok());
+
+        let next_refresh = match result {
+            Ok(delay) => {
+                if self.failures > 0 {
+                    info!(
+                        "Recovered after {} failed cycle(s)",
+                        self.failures
+                    );
+                    self.notifier.send(
+                        "Port forwarding restored",
+                        &format!("Recovered after {} failed refresh cycle(s)", self.failures),
+                    ).await;
+                }
+                self.failures = 0;
+                self.resume_torrents().await;
+                delay
+            }
+            Err(e) => {
+                self.failures += 1;
+                let delay = self.backoff_delay(self.failures);
+                warn!(
+                    "Refresh cycle failed ({} in a row): {}. Backing off for {}s",
+                    self.failures,
+                    e,
+                    delay.as_secs()
+                );
+                if self.config.pause_after_failures > 0 && self.failures >= self.config.pause_after_failures {
+                    self.pause_torrents().await;
+                }
+                delay
+            }
+        };
+
+        let mut state = self.state.write().expect("state lock poisoned");
+        state.healthy = self.failures == 0;
+        state.consecutive_failures = self.failures;
+
+        next_refresh
+    }
+
+    /// Pause seeding while unconnectable so private tracker ratios don't suffer
+    async fn pause_torrents(&mut self) {
+        if self.paused.is_some() {
+            return;
+        }
+
+        match self.torrent.pause().await {
+            Ok(hashes) => {
+                info!(
+                    "Port forwarding lost for {} cycle(s), paused {} torrent(s)",
+                    self.failures,
+                    hashes.len()
+                );
+                self.notifier.send(
+                    "Torrents paused",
+                    &format!("Port forwarding lost for {} cycle(s), paused {} torrent(s)", self.failures, hashes.len()),
+                ).await;
+                self.paused = Some(hashes);
+            }
+            Err(e) => warn!(
+                "Pausing torrents failed: {}",
+                e
+            ),
+        }
+    }
+
+    /// Resume the torrents paused by `pause_torrents`
+    async fn resume_torrents(&mut self) {
+        let Some(hashes) = self.paused.take() else {
+            return;
+        };
+
+        match self.torrent.resume(&hashes).await {
+            Ok(()) => {
+                info!(
+                    "Port forwarding restored, resumed {} torrent(s)",
+                    hashes.len()
+                );
+                self.notifier.send(
+                    "Torrents resumed",
+                    &format!("Port forwarding restored, resumed {} torrent(s)", hashes.len()),
+                ).await;
+            }
+            Err(e) => {
+                warn!(
+                    "Resuming torrents failed: {}",
+                    e
+                );
+                // Try again after the next successful cycle
+                self.paused = Some(hashes);
+            }
+        }
+    }
+
+    /// Sleep until the next refresh, cut short by a gateway restart or a tunnel reconnect
+    async fn wait_for_next_cycle(&mut self, delay: Duration) {
+        let watcher = self.tunnel.as_mut();
+        let tunnel = async move {
+            match watcher {
+                Some(watcher) => {
+                    let reason = watcher.changed().await;
+                    info!(
+                        "Tunnel changed ({}), refreshing mappings now",
+                        reason
+                    );
+                }
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {},
+            _ = watch_epoch(&self.mappers, self.config.epoch_check_interval) => {},
+            _ = tunnel => {},
+        }
+    }
+
+    /// One refresh cycle, returns the delay until the next one
+    async fn cycle(&mut self) -> Result<Duration> {
+        self.redetect_gateway().await?;
+        self.detect_ipv6();
+
+        // Wait for torrent client availability
+        self.torrent.wait_until_available().await?;
+
+        // A failure on the primary gateway is fatal, secondary gateways are only reported
+        let primary = self.refresh_gateway(0).await?;
+        let mut shortest = primary.lifetime;
+
+        for index in 1..self.mappers.len() {
+            if let Ok(ports) = self.refresh_gateway(index).await {
+                shortest = shortest.min(ports.lifetime);
+            }
+        }
+
+        let next_refresh = self.schedule_refresh(shortest);
+
+        let target_port = match primary.target(self.config.port_policy) {
+            Some(port) => port,
+            None => {
+                info!(
+                    "Ports still differ, skipping {} update until next refresh",
+                    self.torrent.name()
+                );
+                return Ok(next_refresh);
+            }
+        };
+
+        if self.last_port != Some(target_port) {
+            let message = match self.last_port {
+                Some(old) => format!("Public port changed from {} to {}", old, target_port),
+                None => format!("Public port is {}", target_port),
+            };
+            self.notifier.send("Port forwarding updated", &message).await;
+            self.hook.run(self.last_port, target_port).await;
+            self.last_port = Some(target_port);
+            if self.config.connectivity_check_timeout > 0 {
+                self.port_changed_at = Some(Instant::now());
+            }
+        }
+
+        let result = self.sync_torrent(target_port).await;
+        {
+            let mut state = self.state.write().expect("state lock poisoned");
+            state.torrent.in_sync = result.is_ok();
+            state.torrent.last_error = result.as_ref().err().map(|e| e.to_string());
+        }
+        result?;
+
+        self.verify_connectivity(target_port).await;
+
+        Ok(next_refresh)
+    }
+
+    /// Alert when the torrent client is still not connectable CONNECTIVITY_CHECK_TIMEOUT
+    /// minutes after the port changed
+    async fn verify_connectivity(&mut self, port: u16) {
+        let Some(changed_at) = self.port_changed_at else {
+            return;
+        };
+
+        let status = match self.torrent.connection_status().await {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                // Nothing to verify against
+                self.port_changed_at = None;
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "Connectivity check failed: {}",
+                    e
+                );
+                return;
+            }
+        };
+
+        let connected = status == "connected";
+        TORRENT_CONNECTABLE.set(connected as i64);
+        self.state.write().expect("state lock poisoned").torrent.connection_status = Some(status.clone());
+
+        if connected {
+            info!(
+                "{} reports incoming connections on port {}",
+                self.torrent.name(),
+                port
+            );
+            self.port_changed_at = None;
+            return;
+        }
+
+        let timeout = Duration::from_secs(self.config.connectivity_check_timeout * 60);
+        if changed_at.elapsed() >= timeout {
+            CONNECTIVITY_CHECK_FAILURES.inc();
+            warn!(
+                "{} still reports '{}' {} minute(s) after switching to port {}",
+                self.torrent.name(),
+                status,
+                self.config.connectivity_check_timeout,
+                port
+            );
+            self.notifier.send(
+                "Incoming connectivity check failed",
+                &format!(
+                    "{} reports '{}' {} minute(s) after the port changed to {}",
+                    self.torrent.name(),
+                    status,
+                    self.config.connectivity_check_timeout,
+                    port
+                ),
+            ).await;
+            self.port_changed_at = None;
+        }
+    }
+
+    /// Exponential backoff after `failures` consecutive failed cycles, capped at BACKOFF_MAX
+    fn backoff_delay(&self, failures: u32) -> Duration {
+        let initial = Duration::from_secs(self.config.backoff_initial.max(1));
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        initial.saturating_mul(factor).min(Duration::from_secs(self.config.backoff_max))
+    }
+
+    /// Schedule the next refresh well before the shortest granted lifetime runs out,
+    /// on a tighter jittered schedule while the gateway grants short lifetimes
+    fn schedule_refresh(&mut self, granted: Duration) -> Duration {
+        let low = granted < Duration::from_secs(self.config.low_lifetime_threshold);
+        if low != self.low_lifetime {
+            if low {
+                info!(
+                    "Granted lifetime {}s is below {}s, refreshing every ~{}s",
+                    granted.as_secs(),
+                    self.config.low_lifetime_threshold,
+                    self.config.low_lifetime_refresh
+                );
+            } else {
+                info!(
+                    "Granted lifetime recovered to {}s, back to the normal refresh interval",
+                    granted.as_secs()
+                );
+            }
+            self.low_lifetime = low;
+        }
+
+        let mut next_refresh = Duration::from_secs(self.config.refresh_interval);
+        if low {
+            let tight = Duration::from_secs(self.config.low_lifetime_refresh);
+            next_refresh = next_refresh.min(tight.saturating_sub(jitter(tight.mul_f64(LOW_LIFETIME_JITTER))));
+        }
+
+        next_refresh
+            .min(granted.mul_f64(self.config.lifetime_refresh_ratio))
+            .mul_f64(1.0 - INSTANCE_STAGGER * self.config.instance_jitter())
+            .max(MIN_REFRESH)
+    }
+
+    /// Re-detect the gateway in case the tunnel interface changed
+    async fn redetect_gateway(&mut self) -> Result<()> {
+        let Some(current) = self.route.clone() else {
+            return Ok(());
+        };
+
+        match gateway::detect_default_route() {
+            Ok(detected) if detected != current => {
+                info!(
+                    "Default route changed to {} via {}, recreating NAT-PMP client",
+                    detected.gateway,
+                    detected.interface
+                );
+                self.mappers[0].set_gateway(detected.gateway).await?;
+                self.state.write().expect("state lock poisoned").gateways[0].gateway = detected.gateway.to_string();
+                self.route = Some(detected);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Gateway detection failed: {}. Keeping {}",
+                e,
+                self.mappers[0].gateway()
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Report whether the tunnel is dual-stack. Only the IPv4 mappings are maintained:
+    /// IPv6 pinholes need PCP, which pnp does not speak yet. Skipped while the tunnel
+    /// interface is unknown, the LAN side's IPv6 says nothing about it.
+    fn detect_ipv6(&self) {
+        let Some(interface) = self.config.watch_interface.as_deref()
+            .or(self.route.as_ref().map(|r| r.interface.as_str()))
+        else {
+            return;
+        };
+        let ipv6 = gateway::has_global_ipv6(interface);
+
+        let mut state = self.state.write().expect("state lock poisoned");
+        if ipv6 != state.ipv6 {
+            info!(
+                "Tunnel IPv6 connectivity {}",
+                if ipv6 { "detected, IPv6 peers are not covered by NAT-PMP" } else { "lost" }
+            );
+        }
+        state.ipv6 = ipv6;
+        TUNNEL_IPV6.set(ipv6 as i64);
+    }
+
+    /// Map one gateway, record the outcome in the shared state and notify on failure
+    async fn refresh_gateway(&self, index: usize) -> Result<Ports> {
+        let mapper = &self.mappers[index];
+
+        // A restart seen by the epoch probe is handled by this refresh itself
+        let probed_restart = mapper.take_restart();
+        let label = mapper.gateway().to_string();
+        let timer = MAPPING_DURATION.with_label_values(&[&label]).start_timer();
+        let mut result = mapper.map(&self.config).await;
+
+        // The gateway lost all mappings while we were mapping: request them again
+        let mapped_restart = result.is_ok() && mapper.take_restart();
+        if mapped_restart {
+            result = mapper.map(&self.config).await;
+        }
+        timer.observe_duration();
+
+        if probed_restart || mapped_restart {
+            self.notifier.send(
+                "NAT-PMP gateway restarted",
+                &format!("Gateway {} restarted, mappings re-requested", mapper.gateway()),
+            ).await;
+        }
+
+        let ports = match result {
+            Ok(p) => p,
+            Err(e) => {
+                MAPPING_FAILURES.with_label_values(&[&label]).inc();
+                warn!(
+                    "Gateway {}: mapping failed: {}",
+                    mapper.gateway(),
+                    e
+                );
+                // Only notify when the gateway starts failing, not on every backoff retry
+                let was_failing = self.state.write().expect("state lock poisoned").gateways[index]
+                    .last_error
+                    .replace(e.to_string())
+                    .is_some();
+                if !was_failing {
+                    self.notifier.send(
+                        "NAT-PMP mapping failed",
+                        &format!("Mapping on gateway {} failed after retries: {}", mapper.gateway(), e),
+                    ).await;
+                }
+                return Err(e);
+            }
+        };
+
+        // The external address is informational only, don't fail the cycle over it
+        let external_ip = mapper.public_address().await.ok();
+
+        info!(
+            "Gateway {}: public {}, lifetime: {}s",
+            mapper.gateway(),
+            ports.describe(),
+            ports.lifetime.as_secs()
+        );
+
+        let now = Utc::now();
+        let mut state = self.state.write().expect("state lock poisoned");
+        let gw = &mut state.gateways[index];
+        gw.tcp_port = ports.tcp;
+        gw.udp_port = ports.udp;
+        gw.expires_at = chrono::Duration::from_std(ports.lifetime).ok().map(|l| now + l);
+        gw.last_refresh = Some(now);
+        gw.external_ip = external_ip.map(|ip| ip.to_string()).or(gw.external_ip.take());
+        gw.last_error = None;
+
+        Ok(ports)
+    }
+
+    /// Bring the torrent client listen port in line with the forwarded port
+    async fn sync_torrent(&self, target_port: u16) -> Result<()> {
+        if self.config.fix_conflicting_settings {
+            for change in self.torrent.fix_conflicting_settings().await? {
+                info!(
+                    "{} setting corrected: {}",
+                    self.torrent.name(),
+                    change
+                );
+            }
+        }
+
+        // Check torrent client current listen port
+        let timer = TORRENT_API_DURATION.with_label_values(&["get_listen_port"]).start_timer();
+        let current_port = self.torrent.get_listen_port().await;
+        timer.observe_duration();
+        let current_port = current_port?;
+
+        if current_port != target_port {
+            let timer = TORRENT_API_DURATION.with_label_values(&["set_listen_port"]).start_timer();
+            let result = self.torrent.set_listen_port(target_port).await;
+            timer.observe_duration();
+
+            if let Err(e) = result {
+                self.notifier.send(
+                    &format!("{} update failed", self.torrent.name()),
+                    &format!("Could not set listen_port to {}: {}", target_port, e),
+                ).await;
+                return Err(e);
+            }
+            info!(
+                "{} listen_port updated from {} to {}",
+                self.torrent.name(),
+                current_port,
+                target_port
+            );
+
+            if self.config.reannounce {
+                match self.torrent.reannounce().await {
+                    Ok(()) => info!(
+                        "{} torrents reannounced",
+                        self.torrent.name()
+                    ),
+                    Err(e) => warn!(
+                        "Reannounce failed: {}",
+                        e
+                    ),
+                }
+            }
+        } else {
+            info!(
+                "{} listen_port {} is up-to-date",
+                self.torrent.name(),
+                current_port
+            );
+        }
+
+        let mut state = self.state.write().expect("state lock poisoned");
+        state.torrent.listen_port = Some(target_port);
+        state.torrent.last_sync = Some(Utc::now());
+
+        Ok(())
+    }
+}
+
+/// Probe the gateway epoch every `interval` seconds and resolve once a restart is seen.
+/// Never resolves when `interval` is 0.
+async fn watch_epoch(mappers: &[Box<dyn PortMapper>], interval: u64) {
+    if interval == 0 {
+        return std::future::pending().await;
+    }
+    let probe = Duration::from_secs(interval);
+
+    loop {
+        tokio::time::sleep(probe).await;
+
+        for mapper in mappers {
+            // Errors here surface on the next regular refresh
+            let _ = mapper.public_address().await;
+            if mapper.restart_pending() {
+                info!(
+                    "Gateway {} restarted, refreshing mappings now",
+                    mapper.gateway()
+                );
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, net::Ipv4Addr, sync::{Mutex, atomic::{AtomicBool, AtomicU32, Ordering}}};
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Cli;
+
+    /// Mapper handing out scripted ports, optionally seeing the gateway restart during the first map
+    struct MockMapper {
+        ports: Mutex<VecDeque<u16>>,
+        restart_on_map: AtomicBool,
+        restarted: AtomicBool,
+        maps: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl PortMapper for MockMapper {
+        fn gateway(&self) -> Ipv4Addr {
+            Ipv4Addr::new(10, 2, 0, 1)
+        }
+
+        async fn set_gateway(&mut self, _gateway: Ipv4Addr) -> Result<()> {
+            Ok(())
+        }
+
+        fn restart_pending(&self) -> bool {
+            false
+        }
+
+        fn take_restart(&self) -> bool {
+            self.restarted.swap(false, Ordering::Relaxed)
+        }
+
+        async fn public_address(&self) -> Result<Ipv4Addr> {
+            Ok(Ipv4Addr::new(203, 0, 113, 7))
+        }
+
+        async fn map(&self, _config: &Config) -> Result<Ports> {
+            self.maps.fetch_add(1, Ordering::Relaxed);
+            if self.restart_on_map.swap(false, Ordering::Relaxed) {
+                self.restarted.store(true, Ordering::Relaxed);
+            }
+            let port = self.ports.lock().unwrap().pop_front().ok_or_else(|| anyhow!("gateway unreachable"))?;
+            Ok(Ports { tcp: Some(port), udp: Some(port), lifetime: Duration::from_secs(60) })
+        }
+    }
+
+    /// Torrent client keeping its listen port in memory
+    struct MockTorrent {
+        listen_port: Arc<Mutex<u16>>,
+        reject_updates: bool,
+    }
+
+    #[async_trait]
+    impl TorrentClient for MockTorrent {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        async fn wait_until_available(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn get_listen_port(&self) -> Result<u16> {
+            Ok(*self.listen_port.lock().unwrap())
+        }
+
+        async fn set_listen_port(&self, new_port: u16) -> Result<()> {
+            if self.reject_updates {
+                anyhow::bail!("HTTP 403 Forbidden");
+            }
+            *self.listen_port.lock().unwrap() = new_port;
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        daemon: Daemon,
+        listen_port: Arc<Mutex<u16>>,
+        maps: Arc<AtomicU32>,
+    }
+
+    fn harness(ports: &[u16], restart_on_map: bool, reject_updates: bool) -> Harness {
+        let config = Cli::parse_from(["pnp", "--gateway", "10.2.0.1"]).config;
+        let listen_port = Arc::new(Mutex::new(6881));
+        let maps = Arc::new(AtomicU32::new(0));
+
+        let mapper = MockMapper {
+            ports: Mutex::new(ports.iter().copied().collect()),
+            restart_on_map: AtomicBool::new(restart_on_map),
+            restarted: AtomicBool::new(false),
+            maps: maps.clone(),
+        };
+        let torrent = MockTorrent { listen_port: listen_port.clone(), reject_updates };
+
+        Harness {
+            daemon: Daemon::with_parts(config, vec![Box::new(mapper)], None, Box::new(torrent)),
+            listen_port,
+            maps,
+        }
+    }
+
+    #[tokio::test]
+    async fn port_change_updates_torrent_client() {
+        let mut h = harness(&[40000, 41000], false, false);
+
+        h.daemon.cycle().await.unwrap();
+        assert_eq!(*h.listen_port.lock().unwrap(), 40000);
+
+        h.daemon.cycle().await.unwrap();
+        assert_eq!(*h.listen_port.lock().unwrap(), 41000);
+        assert_eq!(h.daemon.last_port, Some(41000));
+
+        let state = h.daemon.state.read().unwrap();
+        assert!(state.torrent.in_sync);
+        assert_eq!(state.gateways[0].tcp_port, Some(41000));
+    }
+
+    #[tokio::test]
+    async fn gateway_restart_during_mapping_remaps() {
+        let mut h = harness(&[40000, 42000], true, false);
+
+        h.daemon.cycle().await.unwrap();
+
+        assert_eq!(h.maps.load(Ordering::Relaxed), 2);
+        assert_eq!(*h.listen_port.lock().unwrap(), 42000);
+    }
+
+    #[tokio::test]
+    async fn rejected_update_fails_cycle() {
+        let mut h = harness(&[40000], false, true);
+
+        let err = h.daemon.cycle().await.unwrap_err();
+
+        assert!(err.to_string().contains("403"));
+        assert_eq!(*h.listen_port.lock().unwrap(), 6881);
+        assert!(!h.daemon.state.read().unwrap().torrent.in_sync);
+    }
+
+    #[tokio::test]
+    async fn failed_mappings_mark_daemon_unhealthy() {
+        let mut h = harness(&[], false, false);
+
+        h.daemon.run_cycle().await;
+        h.daemon.run_cycle().await;
+
+        let state = h.daemon.state.read().unwrap();
+        assert!(!state.healthy);
+        assert_eq!(state.consecutive_failures, 2);
+        assert!(state.gateways[0].last_error.is_some());
+    }
+}
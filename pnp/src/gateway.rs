@@ -0,0 +1,140 @@
+use std::net::Ipv4Addr;
+use anyhow::Result;
+
+/// Proton's NAT-PMP gateway, applied by the protonvpn preset when no gateway is configured
+pub const FALLBACK_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 2, 0, 1);
+
+// RTF_UP | RTF_GATEWAY from linux/route.h
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefaultRoute {
+    pub interface: String,
+    pub gateway: Ipv4Addr,
+}
+
+/// Configured gateways, or the gateway of the default route when none are configured
+pub fn resolve(configured: &[Ipv4Addr]) -> Result<(Vec<Ipv4Addr>, Option<DefaultRoute>)> {
+    if !configured.is_empty() {
+        return Ok((configured.to_vec(), None));
+    }
+
+    let route = detect_default_route()?;
+    Ok((vec![route.gateway], Some(route)))
+}
+
+/// Discover the default IPv4 route from the kernel routing table
+#[cfg(target_os = "linux")]
+pub fn detect_default_route() -> Result<DefaultRoute> {
+    let table = std::fs::read_to_string("/proc/net/route")?;
+    let (interface, gateway) = parse_route_table(&table)
+        .ok_or_else(|| anyhow::anyhow!("No default IPv4 route found in /proc/net/route"))?;
+
+    // Tunnels like wg0 install a device-only default route, the NAT-PMP server can't be told from it
+    let gateway = gateway.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Default route via {} has no next hop, set NATPMP_GATEWAY or PRESET",
+            interface
+        )
+    })?;
+    Ok(DefaultRoute { interface, gateway })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn detect_default_route() -> Result<DefaultRoute> {
+    anyhow::bail!("Gateway auto-detection is only supported on Linux, set NATPMP_GATEWAY")
+}
+
+/// Pick the lowest-metric default route from `/proc/net/route` content: its interface and
+/// next hop, None for a device-only route.
+///
+/// Columns: Iface Destination Gateway Flags RefCnt Use Metric Mask ...
+/// Addresses are hex in host byte order.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_route_table(table: &str) -> Option<(String, Option<Ipv4Addr>)> {
+    table.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 8 {
+                return None;
+            }
+
+            let destination = u32::from_str_radix(cols[1], 16).ok()?;
+            let gateway = u32::from_str_radix(cols[2], 16).ok()?;
+            let flags = u32::from_str_radix(cols[3], 16).ok()?;
+            let metric: u32 = cols[6].parse().ok()?;
+            let mask = u32::from_str_radix(cols[7], 16).ok()?;
+
+            if destination != 0 || mask != 0 || flags & RTF_UP == 0 {
+                return None;
+            }
+
+            let gateway = (flags & RTF_GATEWAY != 0).then(|| Ipv4Addr::from(gateway.to_ne_bytes()));
+
+            Some((metric, (cols[0].to_string(), gateway)))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, route)| route)
+}
+
+/// Whether `interface` has a global IPv6 address.
+///
+/// NAT-PMP only maps IPv4; this is reported so operators can see when the tunnel
+/// is dual-stack and IPv6 peers bypass the mapping.
+#[cfg(target_os = "linux")]
+pub fn has_global_ipv6(interface: &str) -> bool {
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .map(|table| parse_if_inet6(&table, interface))
+        .unwrap_or(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn has_global_ipv6(_interface: &str) -> bool {
+    false
+}
+
+/// Columns: address ifindex prefixlen scope flags ifname; scope 00 is global
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_if_inet6(table: &str, interface: &str) -> bool {
+    table.lines().any(|line| {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        cols.len() >= 6 && cols[3] == "00" && cols[5] == interface
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n";
+
+    #[test]
+    fn picks_lowest_metric_default_route() {
+        let table = format!(
+            "{HEADER}eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+             eth1\t00000000\t0101A8C0\t0003\t0\t0\t50\t00000000\t0\t0\t0\n\
+             eth1\t0001A8C0\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n"
+        );
+        assert_eq!(
+            parse_route_table(&table),
+            Some(("eth1".to_string(), Some(Ipv4Addr::new(192, 168, 1, 1))))
+        );
+    }
+
+    #[test]
+    fn device_only_route_has_no_gateway() {
+        let table = format!("{HEADER}wg0\t00000000\t00000000\t0001\t0\t0\t0\t00000000\t0\t0\t0\n");
+        assert_eq!(parse_route_table(&table), Some(("wg0".to_string(), None)));
+    }
+
+    #[test]
+    fn ipv6_only_counts_on_the_given_interface() {
+        // Global address on the LAN NIC, only link-local on the tunnel
+        let table = "20010db8000000000000000000000001 02 40 00 80 eth0\n\
+                     fe800000000000000000000000000002 03 40 20 80 wg0\n";
+        assert!(parse_if_inet6(table, "eth0"));
+        assert!(!parse_if_inet6(table, "wg0"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{process::Stdio, time::Duration};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Runs `ON_PORT_CHANGE_CMD` through the shell whenever the forwarded port changes.
+///
+/// The new port is passed as `$1` and in `PNP_PUBLIC_PORT`; the previous port (if any)
+/// is in `PNP_OLD_PORT`. The command is killed after `ON_PORT_CHANGE_TIMEOUT` seconds.
+pub struct PortChangeHook {
+    command: Option<String>,
+    timeout: Duration,
+}
+
+impl PortChangeHook {
+    pub fn new(command: Option<String>, timeout: Duration) -> Self {
+        Self { command, timeout }
+    }
+
+    /// Run the hook. Failures are logged and never propagated.
+    pub async fn run(&self, old_port: Option<u16>, new_port: u16) {
+        let Some(cmd) = &self.command else {
+            return;
+        };
+
+        #[cfg(unix)]
+        let mut command = {
+            let mut c = Command::new("sh");
+            c.arg("-c").arg(cmd).arg("pnp").arg(new_port.to_string());
+            c
+        };
+
+        #[cfg(not(unix))]
+        let mut command = {
+            let mut c = Command::new("cmd");
+            c.arg("/C").arg(cmd).arg(new_port.to_string());
+            c
+        };
+
+        command
+            .env("PNP_PUBLIC_PORT", new_port.to_string())
+            .env("PNP_OLD_PORT", old_port.map(|p| p.to_string()).unwrap_or_default())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        let child = match command.spawn() {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to start port change hook: {}", e);
+                return;
+            }
+        };
+
+        match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+            Ok(Ok(output)) => {
+                info!(
+                    "Port change hook exited with {}",
+                    output.status
+                );
+                for line in String::from_utf8_lossy(&output.stdout).lines() {
+                    info!(stream = "stdout", "Port change hook: {}", line);
+                }
+                for line in String::from_utf8_lossy(&output.stderr).lines() {
+                    warn!(stream = "stderr", "Port change hook: {}", line);
+                }
+            }
+            Ok(Err(e)) => warn!("Port change hook failed: {}", e),
+            Err(_) => warn!(
+                "Port change hook timed out after {}s and was killed",
+                self.timeout.as_secs()
+            ),
+        }
+    }
+}
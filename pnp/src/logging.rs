@@ -0,0 +1,50 @@
+use std::{fmt, str::FromStr};
+use anyhow::{Result, anyhow};
+use chrono::{Local, SecondsFormat, Utc};
+use tracing_subscriber::{fmt::{format::Writer, time::FormatTime}, EnvFilter};
+
+/// Timestamp format of log lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogTime {
+    /// RFC 3339 in UTC with milliseconds, for journald/Loki ingestion
+    Rfc3339,
+    /// Local wall-clock `HH:MM:SS`, the historical format
+    Local,
+}
+
+impl FromStr for LogTime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rfc3339" => Ok(LogTime::Rfc3339),
+            "local" => Ok(LogTime::Local),
+            other => Err(anyhow!("Invalid LOG_TIME_FORMAT '{}' (expected rfc3339 or local)", other)),
+        }
+    }
+}
+
+impl FormatTime for LogTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> fmt::Result {
+        match self {
+            LogTime::Rfc3339 => write!(w, "{}", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+            LogTime::Local => write!(w, "{}", Local::now().format("%H:%M:%S")),
+        }
+    }
+}
+
+/// Install the global subscriber: one event per line on stderr, filtered by `RUST_LOG`
+/// (default `info`), plain text or JSON.
+pub fn init(time: LogTime, json: bool) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_timer(time)
+        .with_ansi(false)
+        .with_writer(std::io::stderr);
+
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
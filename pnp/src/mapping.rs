@@ -0,0 +1,405 @@
+use std::{net::Ipv4Addr, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+use natpmp::{Natpmp, Protocol, Response, Error};
+use tokio::sync::Mutex;
+use tokio_retry::strategy::{ExponentialBackoff, jitter};
+use tokio_retry::Retry;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::config::{Config, PortPolicy};
+use crate::metrics::MAPPING_RETRIES;
+
+/// Public ports granted by the gateway for the mapped protocols, with the shortest lifetime
+#[derive(Clone, Copy, Debug)]
+pub struct Ports {
+    pub tcp: Option<u16>,
+    pub udp: Option<u16>,
+    pub lifetime: Duration,
+}
+
+impl Ports {
+    /// Port the torrent client should listen on, `None` while the retry policy
+    /// is still waiting for TCP and UDP to match
+    pub fn target(&self, policy: PortPolicy) -> Option<u16> {
+        match (self.tcp, self.udp, policy) {
+            (Some(tcp), Some(udp), PortPolicy::Retry) if tcp != udp => None,
+            (Some(_), Some(udp), PortPolicy::PreferUdp) => Some(udp),
+            (tcp, udp, _) => tcp.or(udp),
+        }
+    }
+
+    /// "TCP port: x, UDP port: y" for the mapped protocols
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(tcp) = self.tcp {
+            parts.push(format!("TCP port: {}", tcp));
+        }
+        if let Some(udp) = self.udp {
+            parts.push(format!("UDP port: {}", udp));
+        }
+        parts.join(", ")
+    }
+}
+
+/// A single mapping as granted by the gateway
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping {
+    pub public_port: u16,
+    pub lifetime: Duration,
+    pub epoch: u32,
+}
+
+/// One NAT-PMP request/response exchange with a gateway.
+#[async_trait]
+pub trait NatPmpClient: Send {
+    async fn request_mapping(&mut self, protocol: Protocol, internal_port: u16, public_port: u16, lifetime: u32) -> Result<Mapping>;
+
+    /// External address and epoch reported by the gateway
+    async fn public_address(&mut self) -> Result<(Ipv4Addr, u32)>;
+}
+
+#[async_trait]
+impl NatPmpClient for Natpmp {
+    async fn request_mapping(&mut self, protocol: Protocol, internal_port: u16, public_port: u16, lifetime: u32) -> Result<Mapping> {
+        refresh_nat_mapping(self, protocol, internal_port, public_port, lifetime).await
+    }
+
+    async fn public_address(&mut self) -> Result<(Ipv4Addr, u32)> {
+        self.send_public_address_request()
+            .map_err(|e| anyhow!("Failed to send NAT-PMP request: {:?}", e))?;
+
+        loop {
+            match self.read_response_or_retry() {
+                Ok(Response::Gateway(resp)) => return Ok((*resp.public_address(), resp.epoch())),
+                Ok(_) => return Err(anyhow!("Unexpected NAT-PMP response type")),
+                Err(Error::NATPMP_TRYAGAIN) => tokio::time::sleep(Duration::from_millis(50)).await,
+                Err(e) => return Err(anyhow!("NAT-PMP error: {:?}", e)),
+            }
+        }
+    }
+}
+
+/// Keeps the mappings on one gateway alive.
+#[async_trait]
+pub trait PortMapper: Send + Sync {
+    fn gateway(&self) -> Ipv4Addr;
+
+    /// Point the mapper at a different gateway (e.g. after the default route changed)
+    async fn set_gateway(&mut self, gateway: Ipv4Addr) -> Result<()>;
+
+    /// Whether a gateway restart was seen and not handled yet
+    fn restart_pending(&self) -> bool;
+
+    /// Whether the gateway was seen restarting since the last call (clears the flag)
+    fn take_restart(&self) -> bool;
+
+    /// Ask the gateway for its external IPv4 address (also a cheap epoch probe)
+    async fn public_address(&self) -> Result<Ipv4Addr>;
+
+    /// Map the configured protocols
+    async fn map(&self, config: &Config) -> Result<Ports>;
+}
+
+/// Last seconds-since-start-of-epoch value reported by the gateway
+#[derive(Clone, Copy)]
+struct EpochSample {
+    epoch: u32,
+    seen: Instant,
+}
+
+/// NAT-PMP client bound to one gateway
+pub struct GatewayMapper {
+    gateway: Ipv4Addr,
+    client: Arc<Mutex<Box<dyn NatPmpClient>>>,
+    last_epoch: std::sync::Mutex<Option<EpochSample>>,
+    restarted: AtomicBool,
+}
+
+impl GatewayMapper {
+    pub fn new(gateway: Ipv4Addr) -> Result<Self> {
+        Ok(Self::with_client(gateway, Box::new(Natpmp::new_with(gateway)?)))
+    }
+
+    pub fn with_client(gateway: Ipv4Addr, client: Box<dyn NatPmpClient>) -> Self {
+        Self {
+            gateway,
+            client: Arc::new(Mutex::new(client)),
+            last_epoch: std::sync::Mutex::new(None),
+            restarted: AtomicBool::new(false),
+        }
+    }
+
+    /// Record the epoch from a response and flag a restart if it did not advance as expected.
+    ///
+    /// RFC 6886 §3.6: the epoch must grow by at least 7/8 of the elapsed time; a smaller
+    /// value (with 2s slack) means the gateway rebooted or we landed on another VPN server,
+    /// and all mappings are gone.
+    fn observe_epoch(&self, epoch: u32) {
+        let now = Instant::now();
+        let mut last = self.last_epoch.lock().expect("epoch lock poisoned");
+
+        if let Some(prev) = *last {
+            let elapsed = now.duration_since(prev.seen).as_secs();
+            if u64::from(epoch) + 2 < u64::from(prev.epoch) + elapsed * 7 / 8 {
+                info!(
+                    "Gateway {}: epoch went from {} to {}, gateway restarted",
+                    self.gateway,
+                    prev.epoch,
+                    epoch
+                );
+                self.restarted.store(true, Ordering::Relaxed);
+            }
+        }
+
+        *last = Some(EpochSample { epoch, seen: now });
+    }
+
+    /// Map the configured protocols, re-requesting while the ports differ if the policy asks for it
+    async fn map_matching(&self, config: &Config) -> Result<Ports> {
+        let mut ports = self.map_ports(config).await;
+
+        // Retry policy: re-request both mappings while the gateway hands out different ports
+        let mut attempts = 0;
+        while config.port_policy == PortPolicy::Retry && attempts < config.mismatch_retries {
+            match &ports {
+                Ok(Ports { tcp: Some(tcp), udp: Some(udp), .. }) if tcp != udp => {
+                    info!(
+                        "Gateway {}: TCP port {} and UDP port {} differ, re-requesting mappings ({}/{})",
+                        self.gateway,
+                        tcp,
+                        udp,
+                        attempts + 1,
+                        config.mismatch_retries
+                    );
+                }
+                _ => break,
+            }
+            attempts += 1;
+            ports = self.map_ports(config).await;
+        }
+
+        ports
+    }
+
+    /// Request the configured TCP and/or UDP mappings and return the public ports
+    async fn map_ports(&self, config: &Config) -> Result<Ports> {
+        let tcp = if config.protocols.tcp() {
+            let (internal_port, public_port) = config.tcp_ports();
+            Some(self.map_protocol(Protocol::TCP, internal_port, public_port, config.lifetime).await?)
+        } else {
+            None
+        };
+
+        let udp = if config.protocols.udp() {
+            let (internal_port, public_port) = config.udp_ports();
+            Some(self.map_protocol(Protocol::UDP, internal_port, public_port, config.lifetime).await?)
+        } else {
+            None
+        };
+
+        let lifetime = tcp.iter().chain(udp.iter())
+            .map(|m| m.lifetime)
+            .min()
+            .unwrap_or_default();
+
+        Ok(Ports {
+            tcp: tcp.map(|m| m.public_port),
+            udp: udp.map(|m| m.public_port),
+            lifetime,
+        })
+    }
+
+    /// Request one mapping with retries and record the epoch it reports
+    async fn map_protocol(&self, protocol: Protocol, internal_port: u16, public_port: u16, lifetime: u32) -> Result<Mapping> {
+        let mapping_strategy = ExponentialBackoff::from_millis(50).map(jitter).take(5);
+
+        let client_clone = self.client.clone();
+        let mut retry_counter = RetryCounter::new(self.gateway);
+        let mapping = Retry::start(mapping_strategy, move || {
+            let client_clone = client_clone.clone();
+            retry_counter.attempt();
+            async move {
+                let mut c = client_clone.lock().await;
+                c.request_mapping(protocol, internal_port, public_port, lifetime).await
+            }
+        }).await?;
+
+        self.observe_epoch(mapping.epoch);
+        Ok(mapping)
+    }
+}
+
+#[async_trait]
+impl PortMapper for GatewayMapper {
+    fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    async fn set_gateway(&mut self, gateway: Ipv4Addr) -> Result<()> {
+        *self.client.lock().await = Box::new(Natpmp::new_with(gateway)?);
+        self.gateway = gateway;
+        *self.last_epoch.lock().expect("epoch lock poisoned") = None;
+        Ok(())
+    }
+
+    fn restart_pending(&self) -> bool {
+        self.restarted.load(Ordering::Relaxed)
+    }
+
+    fn take_restart(&self) -> bool {
+        self.restarted.swap(false, Ordering::Relaxed)
+    }
+
+    async fn public_address(&self) -> Result<Ipv4Addr> {
+        let (address, epoch) = self.client.lock().await.public_address().await?;
+        self.observe_epoch(epoch);
+        Ok(address)
+    }
+
+    async fn map(&self, config: &Config) -> Result<Ports> {
+        self.map_matching(config).await
+    }
+}
+
+/// Counts every attempt after the first one into the retry metric
+struct RetryCounter {
+    gateway: String,
+    first: bool,
+}
+
+impl RetryCounter {
+    fn new(gateway: Ipv4Addr) -> Self {
+        Self { gateway: gateway.to_string(), first: true }
+    }
+
+    fn attempt(&mut self) {
+        if !self.first {
+            MAPPING_RETRIES.with_label_values(&[&self.gateway]).inc();
+        }
+        self.first = false;
+    }
+}
+
+/// Refresh NAT-PMP mapping and return the granted public port and lifetime
+async fn refresh_nat_mapping(
+    client: &mut Natpmp,
+    protocol: Protocol,
+    internal_port: u16,
+    public_port: u16,
+    lifetime: u32,
+) -> Result<Mapping> {
+    client.send_port_mapping_request(protocol, internal_port, public_port, lifetime)
+        .map_err(|e| anyhow!("Failed to send NAT-PMP request: {:?}", e))?;
+
+    loop {
+        match client.read_response_or_retry() {
+            Ok(Response::TCP(resp)) if protocol == Protocol::TCP => {
+                return Ok(Mapping { public_port: resp.public_port(), lifetime: *resp.lifetime(), epoch: resp.epoch() })
+            }
+            Ok(Response::UDP(resp)) if protocol == Protocol::UDP => {
+                return Ok(Mapping { public_port: resp.public_port(), lifetime: *resp.lifetime(), epoch: resp.epoch() })
+            }
+            Ok(_) => return Err(anyhow!("Unexpected NAT-PMP response type")),
+            Err(Error::NATPMP_TRYAGAIN) => tokio::time::sleep(Duration::from_millis(50)).await,
+            Err(e) => return Err(anyhow!("NAT-PMP error: {:?}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::Cli;
+
+    /// Fake NAT-PMP responder replaying scripted (public port, epoch) answers
+    struct FakeGateway {
+        responses: VecDeque<(u16, u32)>,
+        epoch: u32,
+    }
+
+    impl FakeGateway {
+        fn mapper(responses: &[(u16, u32)]) -> GatewayMapper {
+            let fake = FakeGateway { responses: responses.iter().copied().collect(), epoch: 0 };
+            GatewayMapper::with_client(Ipv4Addr::new(10, 2, 0, 1), Box::new(fake))
+        }
+    }
+
+    #[async_trait]
+    impl NatPmpClient for FakeGateway {
+        async fn request_mapping(&mut self, _protocol: Protocol, _internal_port: u16, _public_port: u16, lifetime: u32) -> Result<Mapping> {
+            let (public_port, epoch) = self.responses.pop_front().ok_or_else(|| anyhow!("No scripted response left"))?;
+            self.epoch = epoch;
+            Ok(Mapping { public_port, lifetime: Duration::from_secs(lifetime.into()), epoch })
+        }
+
+        async fn public_address(&mut self) -> Result<(Ipv4Addr, u32)> {
+            Ok((Ipv4Addr::new(203, 0, 113, 7), self.epoch))
+        }
+    }
+
+    fn config(args: &[&str]) -> Config {
+        Cli::parse_from(["pnp"].iter().chain(args).copied()).config
+    }
+
+    #[tokio::test]
+    async fn maps_both_protocols() {
+        let mapper = FakeGateway::mapper(&[(40000, 100), (40000, 100)]);
+
+        let ports = mapper.map(&config(&[])).await.unwrap();
+
+        assert_eq!(ports.tcp, Some(40000));
+        assert_eq!(ports.udp, Some(40000));
+        assert_eq!(ports.lifetime, Duration::from_secs(60));
+        assert!(!mapper.take_restart());
+    }
+
+    #[tokio::test]
+    async fn maps_only_selected_protocol() {
+        let mapper = FakeGateway::mapper(&[(5000, 100)]);
+
+        let ports = mapper.map(&config(&["--protocols", "udp"])).await.unwrap();
+
+        assert_eq!(ports.tcp, None);
+        assert_eq!(ports.udp, Some(5000));
+    }
+
+    #[tokio::test]
+    async fn epoch_reset_flags_gateway_restart() {
+        let mapper = FakeGateway::mapper(&[(40000, 1000), (40000, 1000), (41000, 3), (41000, 3)]);
+        let config = config(&[]);
+
+        mapper.map(&config).await.unwrap();
+        assert!(!mapper.restart_pending());
+
+        let ports = mapper.map(&config).await.unwrap();
+        assert_eq!(ports.tcp, Some(41000));
+        assert!(mapper.take_restart());
+        assert!(!mapper.take_restart());
+    }
+
+    #[tokio::test]
+    async fn retry_policy_rerequests_mismatched_ports() {
+        let mapper = FakeGateway::mapper(&[(40000, 10), (40001, 10), (40002, 10), (40002, 10)]);
+
+        let ports = mapper.map(&config(&["--port-policy", "retry"])).await.unwrap();
+
+        assert_eq!(ports.tcp, Some(40002));
+        assert_eq!(ports.udp, Some(40002));
+    }
+
+    #[test]
+    fn target_follows_port_policy() {
+        let ports = Ports { tcp: Some(1000), udp: Some(2000), lifetime: Duration::from_secs(60) };
+
+        assert_eq!(ports.target(PortPolicy::PreferTcp), Some(1000));
+        assert_eq!(ports.target(PortPolicy::PreferUdp), Some(2000));
+        assert_eq!(ports.target(PortPolicy::Retry), None);
+
+        let udp_only = Ports { tcp: None, ..ports };
+        assert_eq!(udp_only.target(PortPolicy::PreferTcp), Some(2000));
+    }
+}
@@ -0,0 +1,61 @@
+use std::{collections::VecDeque, time::{Duration, Instant}};
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+};
+
+lazy_static::lazy_static! {
+    pub static ref MAPPING_DURATION: HistogramVec = register_histogram_vec!(
+        "pnp_mapping_duration_seconds", "Duration of a TCP+UDP mapping refresh", &["gateway"]
+    ).unwrap();
+    pub static ref MAPPING_RETRIES: IntCounterVec = register_int_counter_vec!(
+        "pnp_mapping_retries_total", "NAT-PMP requests retried after a failed attempt", &["gateway"]
+    ).unwrap();
+    pub static ref MAPPING_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "pnp_mapping_failures_total", "Mapping refreshes that failed after all retries", &["gateway"]
+    ).unwrap();
+    pub static ref TORRENT_API_DURATION: HistogramVec = register_histogram_vec!(
+        "pnp_torrent_api_duration_seconds", "Latency of torrent client API calls", &["operation"]
+    ).unwrap();
+    pub static ref AVAILABILITY: Gauge = register_gauge!(
+        "pnp_port_forwarding_availability_ratio", "Share of successful refresh cycles over the rolling window"
+    ).unwrap();
+    pub static ref TUNNEL_IPV6: IntGauge = register_int_gauge!(
+        "pnp_tunnel_ipv6", "1 when the tunnel interface has a global IPv6 address (not covered by NAT-PMP)"
+    ).unwrap();
+    pub static ref TORRENT_CONNECTABLE: IntGauge = register_int_gauge!(
+        "pnp_torrent_connectable", "1 when the torrent client reports incoming connections, 0 otherwise"
+    ).unwrap();
+    pub static ref CONNECTIVITY_CHECK_FAILURES: IntCounter = register_int_counter!(
+        "pnp_connectivity_check_failures_total", "Port changes after which the torrent client never became connectable"
+    ).unwrap();
+}
+
+/// Rolling window of refresh cycle outcomes backing the availability gauge
+pub struct AvailabilityWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, bool)>,
+}
+
+impl AvailabilityWindow {
+    pub fn new(window: Duration) -> Self {
+        Self { window, samples: VecDeque::new() }
+    }
+
+    /// Record a cycle outcome and update the gauge
+    pub fn record(&mut self, success: bool) {
+        let now = Instant::now();
+        self.samples.push_back((now, success));
+
+        while let Some((at, _)) = self.samples.front() {
+            if now.duration_since(*at) > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let ok = self.samples.iter().filter(|(_, s)| *s).count();
+        AVAILABILITY.set(ok as f64 / self.samples.len() as f64);
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+use reqwest::Client;
+use tracing::warn;
+
+/// Sends notifications to ntfy or Telegram, depending on `NOTIFY_URL`.
+///
+/// - ntfy: `NOTIFY_URL=https://ntfy.sh/my-topic` (message is POSTed as the body)
+/// - Telegram: `NOTIFY_URL=https://api.telegram.org/bot<TOKEN>/sendMessage?chat_id=<ID>`
+///
+/// When `NOTIFY_URL` is unset, every call is a no-op.
+pub struct Notifier {
+    client: Client,
+    url: Option<String>,
+}
+
+impl Notifier {
+    pub fn new(url: Option<String>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+
+        Self { client, url: url.filter(|u| !u.is_empty()) }
+    }
+
+    /// Deliver a notification. Failures are logged and never propagated,
+    /// a broken notifier must not stop the refresh loop.
+    pub async fn send(&self, title: &str, message: &str) {
+        let Some(url) = &self.url else {
+            return;
+        };
+
+        let request = if url.contains("api.telegram.org") {
+            self.client
+                .post(url)
+                .form(&[("text", format!("{}\n{}", title, message))])
+        } else {
+            self.client
+                .post(url)
+                .header("Title", title)
+                .body(message.to_string())
+        };
+
+        match request.send().await {
+            Ok(resp) if resp.status().is_success() => {}
+            Ok(resp) => warn!(
+                "Notification failed: HTTP {}",
+                resp.status()
+            ),
+            // The URL may carry a Telegram bot token
+            Err(e) => warn!(
+                "Notification failed: {}",
+                e.without_url()
+            ),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::net::SocketAddr;
+use prometheus::{Encoder, TextEncoder};
+use warp::{http::StatusCode, Filter};
+
+use crate::state::SharedState;
+
+/// Serve `GET /state`, `GET /health` and `GET /metrics` in the background
+pub fn spawn(state: SharedState, addr: SocketAddr) {
+    let health_state = state.clone();
+    let state_route = warp::path("state")
+        .and(warp::get())
+        .map(move || {
+            let body = state.read().expect("state lock poisoned").to_json();
+            warp::reply::json(&body)
+        });
+
+    let health_route = warp::path("health")
+        .and(warp::get())
+        .map(move || {
+            let healthy = health_state.read().expect("state lock poisoned").healthy;
+            let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            warp::reply::with_status(String::from(if healthy { "ok" } else { "unhealthy" }), status)
+        });
+
+    let metrics_route = warp::path("metrics").map(|| {
+        let encoder = TextEncoder::new();
+        let families = prometheus::gather();
+        let mut buffer = vec![];
+        encoder.encode(&families, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    });
+
+    // .boxed() erases the filter type so the server future can be spawned
+    let routes = state_route.or(health_route).or(metrics_route).boxed();
+
+    tokio::spawn(async move {
+        warp::serve(routes).run(addr).await;
+    });
+}
@@ -0,0 +1,85 @@
+use std::{ffi::OsString, sync::{Mutex, OnceLock}, time::Duration};
+use anyhow::Result;
+use windows_service::{
+    define_windows_service,
+    service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::daemon;
+
+const SERVICE_NAME: &str = "pnp";
+
+// The dispatcher calls service_main without arguments, so the parsed config is parked here
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Hand the process over to the Windows service control manager.
+///
+/// Blocks until the service stops. Register the binary with
+/// `sc create pnp binPath= "C:\path\pnp.exe service"`; configuration comes from the
+/// environment as usual.
+pub fn run(config: Config) -> Result<()> {
+    let _ = CONFIG.set(config);
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("pnp service failed: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let config = CONFIG.get().cloned().ok_or_else(|| anyhow::anyhow!("Service started without configuration"))?;
+
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+    let stop_tx = Mutex::new(Some(stop_tx));
+
+    let status_handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(tx) = stop_tx.lock().expect("stop lock poisoned").take() {
+                let _ = tx.send(());
+            }
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    set_state(&status_handle, ServiceState::Running, ServiceExitCode::Win32(0))?;
+
+    let result = tokio::runtime::Runtime::new()?.block_on(daemon::run(config, async {
+        let _ = stop_rx.await;
+        info!("Service stop requested, shutting down...");
+    }));
+
+    let exit_code = if result.is_ok() { ServiceExitCode::Win32(0) } else { ServiceExitCode::ServiceSpecific(1) };
+    set_state(&status_handle, ServiceState::Stopped, exit_code)?;
+
+    result
+}
+
+fn set_state(handle: &ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        _ => ServiceControlAccept::empty(),
+    };
+
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })?;
+
+    Ok(())
+}
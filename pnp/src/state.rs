@@ -0,0 +1,87 @@
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+/// Daemon state shared with the HTTP server
+pub type SharedState = Arc<RwLock<DaemonState>>;
+
+#[derive(Default)]
+pub struct GatewayState {
+    pub gateway: String,
+    pub tcp_port: Option<u16>,
+    pub udp_port: Option<u16>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_refresh: Option<DateTime<Utc>>,
+    pub external_ip: Option<String>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct TorrentState {
+    pub client: String,
+    pub listen_port: Option<u16>,
+    pub in_sync: bool,
+    pub connection_status: Option<String>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct DaemonState {
+    pub instance_id: Option<String>,
+    /// False while refresh cycles are failing and being retried with backoff
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    /// Whether the tunnel has global IPv6 connectivity, which NAT-PMP cannot map
+    pub ipv6: bool,
+    pub gateways: Vec<GatewayState>,
+    pub torrent: TorrentState,
+}
+
+impl DaemonState {
+    pub fn new(instance_id: Option<String>, gateways: &[String], client: &str) -> Self {
+        Self {
+            instance_id,
+            healthy: true,
+            consecutive_failures: 0,
+            ipv6: false,
+            gateways: gateways.iter()
+                .map(|g| GatewayState { gateway: g.clone(), ..Default::default() })
+                .collect(),
+            torrent: TorrentState { client: client.to_string(), ..Default::default() },
+        }
+    }
+
+    /// Render the state as JSON, computing the remaining lifetime at request time
+    pub fn to_json(&self) -> Value {
+        let now = Utc::now();
+
+        let gateways: Vec<Value> = self.gateways.iter()
+            .map(|g| json!({
+                "gateway": g.gateway,
+                "tcp_port": g.tcp_port,
+                "udp_port": g.udp_port,
+                "lifetime_remaining": g.expires_at.map(|t| (t - now).num_seconds().max(0)),
+                "last_refresh": g.last_refresh.map(|t| t.to_rfc3339()),
+                "external_ip": g.external_ip,
+                "last_error": g.last_error,
+            }))
+            .collect();
+
+        json!({
+            "instance_id": self.instance_id,
+            "healthy": self.healthy,
+            "consecutive_failures": self.consecutive_failures,
+            "ipv6": self.ipv6,
+            "gateways": gateways,
+            "torrent_client": {
+                "client": self.torrent.client,
+                "listen_port": self.torrent.listen_port,
+                "in_sync": self.torrent.in_sync,
+                "connection_status": self.torrent.connection_status,
+                "last_sync": self.torrent.last_sync.map(|t| t.to_rfc3339()),
+                "last_error": self.torrent.last_error,
+            },
+        })
+    }
+}
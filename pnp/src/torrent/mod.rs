@@ -0,0 +1,61 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+
+use crate::config::Config;
+
+pub mod qbittorrent;
+pub mod rtorrent;
+
+use qbittorrent::Qbittorrent;
+use rtorrent::Rtorrent;
+
+/// A torrent client whose listen port follows the forwarded public port.
+#[async_trait]
+pub trait TorrentClient: Send + Sync {
+    /// Human readable client name used in logs and notifications
+    fn name(&self) -> &'static str;
+
+    /// Block until the client API answers
+    async fn wait_until_available(&self) -> Result<()>;
+
+    async fn get_listen_port(&self) -> Result<u16>;
+
+    async fn set_listen_port(&self, new_port: u16) -> Result<()>;
+
+    /// Correct client settings that conflict with external port management,
+    /// returning a description of each change
+    async fn fix_conflicting_settings(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Ask trackers to learn the new port now instead of at their next announce interval
+    async fn reannounce(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pause active torrents while unconnectable, returning the hashes that were
+    /// paused so exactly those are resumed later
+    async fn pause(&self) -> Result<Vec<String>> {
+        Err(anyhow!("{} does not support pausing torrents", self.name()))
+    }
+
+    /// Resume torrents previously returned by `pause`
+    async fn resume(&self, _hashes: &[String]) -> Result<()> {
+        Err(anyhow!("{} does not support resuming torrents", self.name()))
+    }
+
+    /// Incoming connectivity as reported by the client (e.g. "connected", "firewalled"),
+    /// `None` when the client does not expose it
+    async fn connection_status(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// Build the client selected by `TORRENT_CLIENT`
+pub fn from_config(config: &Config) -> Result<Box<dyn TorrentClient>> {
+    match config.torrent_client.to_ascii_lowercase().as_str() {
+        "qbittorrent" => Ok(Box::new(Qbittorrent::new(config)?)),
+        "rtorrent" => Ok(Box::new(Rtorrent::new(config)?)),
+        other => Err(anyhow!("Unsupported TORRENT_CLIENT '{}' (expected qbittorrent or rtorrent)", other)),
+    }
+}
@@ -0,0 +1,277 @@
+use std::time::Duration;
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::warn;
+
+use super::TorrentClient;
+use crate::config::Config;
+
+/// rTorrent XML-RPC client.
+///
+/// `RTORRENT_URL` is either `scgi://host:port` (rTorrent's `network.scgi.open_port`)
+/// or an `http(s)://.../RPC2` endpoint exposed by a web server or ruTorrent.
+pub struct Rtorrent {
+    transport: Transport,
+}
+
+enum Transport {
+    Scgi(String),
+    Http { client: Client, url: String },
+}
+
+/// XML-RPC call parameter
+enum Param<'a> {
+    Str(&'a str),
+    Int(i64),
+}
+
+impl Rtorrent {
+    pub fn new(config: &Config) -> Result<Self> {
+        let url = config.rtorrent_url.as_str();
+
+        let transport = if let Some(addr) = url.strip_prefix("scgi://") {
+            Transport::Scgi(addr.trim_end_matches('/').to_string())
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Transport::Http {
+                client: Client::builder().timeout(Duration::from_secs(10)).build()?,
+                url: url.to_string(),
+            }
+        } else {
+            anyhow::bail!("Invalid RTORRENT_URL '{}' (expected scgi:// or http(s)://)", url);
+        };
+
+        Ok(Self { transport })
+    }
+
+    /// Execute an XML-RPC method and return the first value of the response
+    async fn call(&self, method: &str, params: &[Param<'_>]) -> Result<String> {
+        let body = build_method_call(method, params);
+
+        let response = match &self.transport {
+            Transport::Scgi(addr) => tokio::time::timeout(Duration::from_secs(10), scgi_request(addr, &body))
+                .await
+                .map_err(|_| anyhow!("rTorrent SCGI request to {} timed out", addr))??,
+            Transport::Http { client, url } => {
+                let resp = client.post(url)
+                    .header("Content-Type", "text/xml")
+                    .body(body)
+                    .send()
+                    .await?;
+                if !resp.status().is_success() {
+                    anyhow::bail!("rTorrent XML-RPC returned HTTP {}", resp.status());
+                }
+                resp.text().await?
+            }
+        };
+
+        if response.contains("<fault>") {
+            let reason = extract_tag(&response, "string").unwrap_or_else(|| "unknown fault".to_string());
+            anyhow::bail!("rTorrent {} failed: {}", method, reason);
+        }
+
+        extract_value(&response).ok_or_else(|| anyhow!("Malformed XML-RPC response for {}", method))
+    }
+}
+
+#[async_trait]
+impl TorrentClient for Rtorrent {
+    fn name(&self) -> &'static str {
+        "rTorrent"
+    }
+
+    async fn wait_until_available(&self) -> Result<()> {
+        loop {
+            match self.call("system.client_version", &[]).await {
+                Ok(_) => break,
+                Err(e) => warn!("rTorrent not reachable ({}). Retrying...", e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Read `network.port_range` (e.g. "51413-51413") and return its lower bound
+    async fn get_listen_port(&self) -> Result<u16> {
+        let range = self.call("network.port_range", &[Param::Str("")]).await?;
+        range.split('-')
+            .next()
+            .and_then(|p| p.trim().parse().ok())
+            .ok_or_else(|| anyhow!("Unexpected network.port_range value '{}'", range))
+    }
+
+    /// Pin the port range to the single forwarded port and disable random port selection
+    async fn set_listen_port(&self, new_port: u16) -> Result<()> {
+        let range = format!("{}-{}", new_port, new_port);
+        self.call("network.port_range.set", &[Param::Str(""), Param::Str(&range)]).await?;
+        self.call("network.port_random.set", &[Param::Str(""), Param::Int(0)]).await?;
+        Ok(())
+    }
+
+    /// Announce every loaded download to its trackers
+    async fn reannounce(&self) -> Result<()> {
+        self.call("d.multicall2", &[Param::Str(""), Param::Str("main"), Param::Str("d.tracker_announce=")]).await?;
+        Ok(())
+    }
+}
+
+/// Send an XML-RPC body over SCGI and return the response body
+async fn scgi_request(addr: &str, body: &str) -> Result<String> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("Failed to connect to rTorrent SCGI at {}", addr))?;
+
+    // SCGI header is a netstring of NUL-separated key/value pairs, CONTENT_LENGTH first
+    let headers = format!("CONTENT_LENGTH\0{}\0SCGI\x001\0", body.len());
+    let request = format!("{}:{},{}", headers.len(), headers, body);
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    // Response is CGI-style: headers, blank line, body
+    match response.split_once("\r\n\r\n") {
+        Some((_, body)) => Ok(body.to_string()),
+        None => Ok(response),
+    }
+}
+
+fn build_method_call(method: &str, params: &[Param]) -> String {
+    let params: String = params.iter()
+        .map(|p| match p {
+            Param::Str(s) => format!("<param><value><string>{}</string></value></param>", escape(s)),
+            Param::Int(i) => format!("<param><value><i8>{}</i8></value></param>", i),
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0"?><methodCall><methodName>{}</methodName><params>{}</params></methodCall>"#,
+        method, params
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Return the text of the first `<value>` in the response, with any type tag stripped
+fn extract_value(xml: &str) -> Option<String> {
+    let start = xml.find("<value>")? + "<value>".len();
+    let end = start + xml[start..].find("</value>")?;
+    let inner = xml[start..end].trim();
+
+    // Untyped values are strings; typed ones look like <i8>0</i8>
+    if inner.ends_with("/>") {
+        // Empty value such as <string/>
+        Some(String::new())
+    } else if inner.starts_with('<') {
+        let open_end = inner.find('>')?;
+        let close_start = inner.rfind("</")?;
+        Some(inner[open_end + 1..close_start].to_string())
+    } else {
+        Some(inner.to_string())
+    }
+}
+
+fn extract_tag(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::config::Cli;
+
+    fn client(url: &str) -> Rtorrent {
+        let config = Cli::parse_from(["pnp", "--rtorrent-url", url]).config;
+        Rtorrent::new(&config).unwrap()
+    }
+
+    fn response(value: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><methodResponse><params><param><value>{}</value></param></params></methodResponse>"#,
+            value
+        )
+    }
+
+    #[tokio::test]
+    async fn scgi_request_is_framed_as_a_netstring() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // The client never shuts down its write side, one read takes the whole small request
+            let mut request = vec![0; 4096];
+            let n = stream.read(&mut request).await.unwrap();
+            let reply = format!("Status: 200 OK\r\nContent-Type: text/xml\r\n\r\n{}", response("<string>51413-51413</string>"));
+            stream.write_all(reply.as_bytes()).await.unwrap();
+            String::from_utf8(request[..n].to_vec()).unwrap()
+        });
+
+        let port = client(&format!("scgi://{}", addr)).get_listen_port().await.unwrap();
+        assert_eq!(port, 51413);
+
+        let request = server.await.unwrap();
+        let (length, rest) = request.split_once(':').unwrap();
+        let length: usize = length.parse().unwrap();
+        let (headers, body) = (&rest[..length], &rest[length + 1..]);
+        assert_eq!(&rest[length..length + 1], ",");
+        assert_eq!(headers, format!("CONTENT_LENGTH\0{}\0SCGI\x001\0", body.len()));
+        assert!(body.contains("<methodName>network.port_range</methodName>"));
+    }
+
+    #[tokio::test]
+    async fn http_response_value_is_read() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("network.port_range"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(response("<string>6881-6889</string>")))
+            .mount(&server)
+            .await;
+
+        assert_eq!(client(&format!("{}/RPC2", server.uri())).get_listen_port().await.unwrap(), 6881);
+    }
+
+    #[tokio::test]
+    async fn fault_is_reported() {
+        let server = MockServer::start().await;
+        let fault = r#"<?xml version="1.0"?><methodResponse><fault><value><struct>
+            <member><name>faultCode</name><value><i4>-506</i4></value></member>
+            <member><name>faultString</name><value><string>Method 'network.port_range.set' not defined</string></value></member>
+            </struct></value></fault></methodResponse>"#;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(fault))
+            .mount(&server)
+            .await;
+
+        let err = client(&format!("{}/RPC2", server.uri())).set_listen_port(40000).await.unwrap_err();
+        assert!(err.to_string().contains("not defined"), "{}", err);
+    }
+
+    #[test]
+    fn values_are_extracted_with_or_without_type() {
+        assert_eq!(extract_value(&response("<i8>0</i8>")).as_deref(), Some("0"));
+        assert_eq!(extract_value(&response("0.9.8")).as_deref(), Some("0.9.8"));
+        assert_eq!(extract_value(&response("<string/>")).as_deref(), Some(""));
+        assert_eq!(extract_value("<methodResponse></methodResponse>"), None);
+    }
+
+    #[test]
+    fn method_call_escapes_strings() {
+        let body = build_method_call("d.multicall2", &[Param::Str("a<b&c"), Param::Int(7)]);
+        assert!(body.contains("<string>a&lt;b&amp;c</string>"));
+        assert!(body.contains("<i8>7</i8>"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+use tokio::process::Command;
+
+// IFF_UP from linux/if.h
+const IFF_UP: u32 = 0x1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TunnelSnapshot {
+    up: bool,
+    // Raw `wg show <iface> endpoints` output, empty when unavailable
+    endpoints: String,
+}
+
+/// Polls the WireGuard interface so a reconnect triggers a remap right away
+/// instead of on the next timer tick.
+pub struct TunnelWatcher {
+    interface: String,
+    interval: Duration,
+    last: Option<TunnelSnapshot>,
+}
+
+impl TunnelWatcher {
+    pub fn new(interface: String, interval: Duration) -> Self {
+        Self { interface, interval, last: None }
+    }
+
+    /// Resolve with a reason once the interface came back up or the peer endpoint changed.
+    ///
+    /// The last observation is kept across calls, so a cancelled wait loses nothing.
+    pub async fn changed(&mut self) -> String {
+        if self.last.is_none() {
+            self.last = Some(self.snapshot().await);
+        }
+
+        loop {
+            tokio::time::sleep(self.interval).await;
+
+            let current = self.snapshot().await;
+            let Some(previous) = self.last.replace(current.clone()) else {
+                continue;
+            };
+
+            if !previous.up && current.up {
+                return format!("interface {} is back up", self.interface);
+            }
+            if current.up
+                && !previous.endpoints.is_empty()
+                && !current.endpoints.is_empty()
+                && previous.endpoints != current.endpoints
+            {
+                return format!("peer endpoint of {} changed", self.interface);
+            }
+        }
+    }
+
+    async fn snapshot(&self) -> TunnelSnapshot {
+        TunnelSnapshot {
+            up: self.is_up(),
+            endpoints: self.endpoints().await.unwrap_or_default(),
+        }
+    }
+
+    /// WireGuard links report operstate "unknown", so look at the IFF_UP flag instead
+    fn is_up(&self) -> bool {
+        std::fs::read_to_string(format!("/sys/class/net/{}/flags", self.interface))
+            .ok()
+            .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+            .is_some_and(|flags| flags & IFF_UP != 0)
+    }
+
+    async fn endpoints(&self) -> Option<String> {
+        let output = Command::new("wg")
+            .args(["show", &self.interface, "endpoints"])
+            .kill_on_drop(true)
+            .output()
+            .await
+            .ok()?;
+
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+}
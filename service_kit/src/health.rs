@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse, Responder};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the service can take traffic. Starts not ready, add it as app data and
+/// flip it once startup work is done.
+#[derive(Default)]
+pub struct Readiness(AtomicBool);
+
+impl Readiness {
+    pub fn set_ready(&self, ready: bool) {
+        self.0.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The process is up and serving
+pub async fn liveness() -> impl Responder {
+    HttpResponse::Ok().body("ok")
+}
+
+/// 503 until the service is ready. Services without a `Readiness` are ready as soon as
+/// they serve.
+pub async fn readiness(readiness: Option<web::Data<Readiness>>) -> impl Responder {
+    match readiness {
+        Some(r) if !r.is_ready() => HttpResponse::ServiceUnavailable().body("not ready"),
+        _ => HttpResponse::Ok().body("ready"),
+    }
+}
@@ -0,0 +1,33 @@
+//! Pieces every HTTP service of the repo needs: logging setup, health and readiness
+//! probes, the Prometheus endpoint, request ids and graceful shutdown.
+
+use actix_web::dev::Server;
+use actix_web::web;
+
+pub mod health;
+pub mod logging;
+pub mod metrics;
+pub mod request_id;
+
+/// Register `/healthz`, `/readyz` and `/metrics`. Call before routes with a wildcard
+/// first segment, or those would capture the probes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(health::liveness))
+        .route("/readyz", web::get().to(health::readiness))
+        .route("/metrics", web::get().to(metrics::metrics_endpoint));
+}
+
+/// Seconds in-flight requests get to finish after the termination signal, for
+/// `HttpServer::shutdown_timeout`
+pub const DRAIN_TIMEOUT_SECONDS: u64 = 20;
+
+/// Run `server` until the termination signal, then stop accepting connections and drain
+/// the in-flight requests. Build it with `.disable_signals()` so this is the only handler.
+pub async fn run(server: Server) -> std::io::Result<()> {
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown::signal().await;
+        handle.stop(true).await;
+    });
+    server.await
+}
@@ -0,0 +1,30 @@
+use std::env;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+impl LogFormat {
+    /// `LOG_FORMAT=json|pretty`, `default` when unset or unknown
+    pub fn from_env(default: LogFormat) -> Self {
+        match env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            Ok("pretty") => LogFormat::Pretty,
+            _ => default,
+        }
+    }
+}
+
+/// Install the global subscriber. The filter comes from RUST_LOG, `info` without it.
+pub fn init(default: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match LogFormat::from_env(default) {
+        LogFormat::Json => builder.json().init(),
+        LogFormat::Pretty => builder.init(),
+    }
+}
@@ -0,0 +1,10 @@
+use actix_web::{HttpResponse, Responder};
+use prometheus::{Encoder, TextEncoder};
+
+/// Everything registered in the default Prometheus registry, in the text format
+pub async fn metrics_endpoint() -> impl Responder {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
+    HttpResponse::Ok().content_type(encoder.format_type()).body(buffer)
+}
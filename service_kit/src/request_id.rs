@@ -0,0 +1,14 @@
+use actix_request_identifier::{RequestId, RequestIdentifier};
+use actix_web::{HttpMessage, HttpRequest};
+
+/// Middleware giving every request a UUID, returned in the `x-request-id` header
+pub fn middleware() -> RequestIdentifier {
+    RequestIdentifier::with_uuid()
+}
+
+/// Attach the request's id to the current span's `request_id` field
+pub fn record(req: &HttpRequest) {
+    if let Some(id) = req.extensions().get::<RequestId>() {
+        tracing::Span::current().record("request_id", id.as_str());
+    }
+}
@@ -0,0 +1,164 @@
+//! Graceful shutdown shared by the binaries: waiting for the termination signal, a token
+//! to tell tasks (async or threads) to stop, and bounded draining of in-flight work.
+
+use std::future::Future;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// Resolve on Ctrl+C, SIGTERM on Unix, or a console close/shutdown event on Windows
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("failed to install Ctrl+C handler");
+        info!("Received Ctrl+C, shutting down...");
+    };
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut term_signal = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = term_signal.recv() => {
+                info!("Received SIGTERM, shutting down...");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows;
+
+        let mut ctrl_break = windows::ctrl_break().expect("failed to install Ctrl+Break handler");
+        let mut ctrl_close = windows::ctrl_close().expect("failed to install console close handler");
+        let mut ctrl_shutdown = windows::ctrl_shutdown().expect("failed to install shutdown handler");
+        tokio::select! {
+            _ = ctrl_c => {},
+            _ = ctrl_break.recv() => {},
+            _ = ctrl_close.recv() => {},
+            _ = ctrl_shutdown.recv() => {},
+        }
+        info!("Received console event, shutting down...");
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    ctrl_c.await;
+}
+
+/// Cancellation token: cheap to clone, triggered once, observable from async tasks and
+/// from plain threads alike.
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    triggered: Mutex<bool>,
+    // Wakes threads blocked in wait_timeout
+    condvar: Condvar,
+    // Wakes tasks awaiting wait
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        *self.0.triggered.lock().unwrap() = true;
+        self.0.condvar.notify_all();
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.triggered.lock().unwrap()
+    }
+
+    /// Resolve once triggered
+    pub async fn wait(&self) {
+        loop {
+            let notified = self.0.notify.notified();
+            tokio::pin!(notified);
+            // Registered before the check, a trigger in between isn't missed
+            notified.as_mut().enable();
+            if self.is_triggered() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Block the thread for up to `timeout`, true when woken by the trigger. Replaces
+    /// `thread::sleep` in loops that should stop promptly.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let triggered = self.0.triggered.lock().unwrap();
+        let (triggered, _) = self.0.condvar.wait_timeout_while(triggered, timeout, |t| !*t).unwrap();
+        *triggered
+    }
+
+    /// Trigger on the termination signal. Needs a running tokio runtime.
+    pub fn listen(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            signal().await;
+            shutdown.trigger();
+        });
+    }
+
+    /// `listen` for synchronous programs, waiting for the signal on a thread of its own
+    pub fn listen_blocking(&self) -> std::io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let shutdown = self.clone();
+        std::thread::Builder::new().name("shutdown".into()).spawn(move || {
+            runtime.block_on(signal());
+            shutdown.trigger();
+        })?;
+        Ok(())
+    }
+}
+
+/// Let `work` finish within `timeout`, None when it had to be abandoned
+pub async fn drain<F: Future>(what: &str, work: F, timeout: Duration) -> Option<F::Output> {
+    match tokio::time::timeout(timeout, work).await {
+        Ok(output) => Some(output),
+        Err(_) => {
+            warn!("{} did not finish within {:?}, abandoning it", what, timeout);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_resolves_after_trigger() {
+        let shutdown = Shutdown::new();
+        let waiter = shutdown.clone();
+        let task = tokio::spawn(async move { waiter.wait().await });
+
+        shutdown.trigger();
+        tokio::time::timeout(Duration::from_secs(1), task).await.unwrap().unwrap();
+        assert!(shutdown.is_triggered());
+    }
+
+    #[test]
+    fn wait_timeout_reports_trigger() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(10)));
+
+        let trigger = shutdown.clone();
+        std::thread::spawn(move || trigger.trigger());
+        assert!(shutdown.wait_timeout(Duration::from_secs(5)));
+    }
+
+    #[tokio::test]
+    async fn drain_abandons_slow_work() {
+        let slow = tokio::time::sleep(Duration::from_secs(5));
+        assert!(drain("Slow work", slow, Duration::from_millis(10)).await.is_none());
+        assert_eq!(drain("Quick work", async { 1 }, Duration::from_secs(1)).await, Some(1));
+    }
+}
@@ -1,7 +1,5 @@
-use std::env;
 use std::io::Read;
 use std::net::{TcpStream, ToSocketAddrs};
-use std::thread;
 use std::time::Duration;
 
 use rand::seq::SliceRandom;
@@ -10,31 +8,30 @@ use ssh2::Session;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
 use chrono::Local;
-use ctrlc;
 use reqwest::blocking::Client;
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde_json::Value;
 use sha2::{Digest, Sha512};
+use shutdown::Shutdown;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv::dotenv().ok();
+    // .env (or CONFIG_FILE), with the credentials also readable from *_FILE secrets
+    layered_config::load_or(".env", &["AUTH_TOKEN", "SESSION_ID", "MIKROTIK_PASS"])?;
 
     // --- Environment setup ---
-    let auth_server = env::var("AUTH_SERVER")?;
-    let auth_token = env::var("AUTH_TOKEN")?;
-    let session_id = env::var("SESSION_ID")?;
+    let auth_server = layered_config::required("AUTH_SERVER")?;
+    let auth_token = layered_config::required("AUTH_TOKEN")?;
+    let session_id = layered_config::required("SESSION_ID")?;
 
-    let mikrotik_host = env::var("MIKROTIK_HOST")?;
-    let mikrotik_user = env::var("MIKROTIK_USER")?;
-    let mikrotik_pass = env::var("MIKROTIK_PASS")?;
+    let mikrotik_host = layered_config::required("MIKROTIK_HOST")?;
+    let mikrotik_user = layered_config::required("MIKROTIK_USER")?;
+    let mikrotik_pass = layered_config::required("MIKROTIK_PASS")?;
 
-    let countries_str = env::var("COUNTRIES").unwrap_or_else(|_| "RO".to_string());
+    let countries_str = layered_config::get("COUNTRIES").unwrap_or_else(|| "RO".to_string());
     let countries: Vec<&str> = countries_str.split(',').collect();
 
-    let tier: u32 = env::var("TIER")
-        .unwrap_or_else(|_| "2".to_string())
-        .parse()?;
-    let features_str = env::var("FEATURES").unwrap_or_else(|_| "P2P".to_string());
+    let tier: u32 = layered_config::parse_var("TIER")?.unwrap_or(2);
+    let features_str = layered_config::get("FEATURES").unwrap_or_else(|| "P2P".to_string());
     let features: Vec<&str> = features_str.split(',').collect();
 
     let mut headers = HeaderMap::new();
@@ -57,18 +54,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let client = Client::builder().default_headers(headers.clone()).build()?;
 
-    // --- Ctrl+C handler ---
-    let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        println!("\n[INFO] Ctrl+C pressed, exiting...");
-        r.store(false, std::sync::atomic::Ordering::SeqCst);
-    })?;
+    // --- Ctrl+C / SIGTERM handler ---
+    let shutdown = Shutdown::new();
+    shutdown.listen_blocking()?;
 
     // --- Monitoring loop ---
-    while running.load(std::sync::atomic::Ordering::SeqCst) {
+    while !shutdown.is_triggered() {
         let mut fail_count = 0;
-        while fail_count < 5 && running.load(std::sync::atomic::Ordering::SeqCst) {
+        while fail_count < 5 && !shutdown.is_triggered() {
             if host_reachable_port("10.2.0.1", 51820) {
                 fail_count = 0;
             } else {
@@ -78,10 +71,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     fail_count
                 );
             }
-            thread::sleep(Duration::from_secs(2));
+            if shutdown.wait_timeout(Duration::from_secs(2)) {
+                break;
+            }
         }
 
-        if !running.load(std::sync::atomic::Ordering::SeqCst) {
+        if shutdown.is_triggered() {
             break;
         }
 
@@ -190,7 +185,7 @@ fn regenerate_vpn_flow(
         ];
 
         status == 1
-            && (!countries.contains(&entry_country) && !countries.contains(&exit_country)) == false
+            && (countries.contains(&entry_country) || countries.contains(&exit_country))
             && server_tier == tier
             && !features.iter().any(|f| !feat_flags.contains(f))
     });